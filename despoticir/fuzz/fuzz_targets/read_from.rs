@@ -0,0 +1,51 @@
+//! Feed arbitrary bytes to Cloud::read_from; any input must produce
+//! Ok or Err rather than a panic
+//!
+//! INCLUDE and EMITTER lines open the files they name, so an input
+//! could name a device such as /dev/zero that never finishes reading,
+//! and results would depend on the host's filesystem. Those lines are
+//! blanked to comments before parsing, so the fuzzer exercises
+//! everything in the parser except the file reads they trigger.
+
+#![no_main]
+
+use despoticir::cloud::{Cloud, DuplicatePolicy, ReadOptions};
+use libfuzzer_sys::fuzz_target;
+
+/// Whether a line is an INCLUDE or EMITTER directive, matching its
+/// keyword the way Cloud::read_from does
+fn opens_file(line: &[u8]) -> bool {
+    let Ok(line) = std::str::from_utf8(line) else {
+        return false;
+    };
+    line.split_once('=').is_some_and(|(key, _)| {
+        let keyword = key.trim().to_uppercase();
+        keyword == "INCLUDE" || keyword == "EMITTER"
+    })
+}
+
+fuzz_target!(|data: &[u8]| {
+    // The first byte selects the read options, so that every
+    // combination is exercised
+    let Some((&flags, input)) = data.split_first() else {
+        return;
+    };
+    let opts = ReadOptions {
+        duplicates: match flags % 3 {
+            0 => DuplicatePolicy::LastWins,
+            1 => DuplicatePolicy::FirstWins,
+            _ => DuplicatePolicy::Error,
+        },
+        allow_partial: flags & 0x80 != 0,
+    };
+
+    // Blank directives that open files, keeping line numbers intact
+    let input: Vec<u8> = input
+        .split(|&b| b == b'\n')
+        .map(|line| if opens_file(line) { &b"#"[..] } else { line })
+        .collect::<Vec<_>>()
+        .join(&b'\n');
+
+    let mut cloud = Cloud::builder().build();
+    let _ = cloud.read_from(input.as_slice(), false, &opts);
+});
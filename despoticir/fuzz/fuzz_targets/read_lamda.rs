@@ -0,0 +1,28 @@
+//! Feed arbitrary bytes to EmitterData::read_lamda; any input must
+//! produce Ok or Err rather than a panic
+//!
+//! The counts in a LAMDA file are read before the data they
+//! introduce, so this also checks that no count, however large, is
+//! trusted beyond the data that follows it.
+
+#![no_main]
+
+use despoticir::emitter_data::{EmitterData, FreqUnit};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    // The first byte selects the frequency unit
+    let Some((&flags, input)) = data.split_first() else {
+        return;
+    };
+    let unit = match flags % 4 {
+        0 => FreqUnit::GHz,
+        1 => FreqUnit::MHz,
+        2 => FreqUnit::Hz,
+        _ => FreqUnit::InvCm,
+    };
+    if let Ok(em) = EmitterData::read_lamda_with(input, unit) {
+        let _ = em.partners();
+        let _ = em.lte_pops(100.0);
+    }
+});
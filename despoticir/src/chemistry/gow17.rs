@@ -0,0 +1,296 @@
+use super::{co_photodissoc_rate, cr_induced_photo_rate, h2_photodissoc_rate, ChemNetwork};
+use crate::cloud::Cloud;
+use crate::emitter::Abundance;
+
+/// Species evolved by the network
+const SPECIES: [&str; 10] = [
+    "H2", "H+", "H3+", "He+", "C+", "CHx", "OHx", "CO", "HCO+", "Si+",
+];
+const H2: usize = 0;
+const HP: usize = 1;
+const H3P: usize = 2;
+const HEP: usize = 3;
+const CP: usize = 4;
+const CHX: usize = 5;
+const OHX: usize = 6;
+const CO: usize = 7;
+const HCOP: usize = 8;
+const SIP: usize = 9;
+
+/// Rate coefficient for H2 formation on Milky Way dust grains, in
+/// cm^3 s^-1
+const R_H2_GRAIN: f64 = 3.0e-17;
+
+/// Fit coefficients for grain-assisted recombination (Weingartner &
+/// Draine 2001, table 1)
+const GR_HP: [f64; 7] = [12.25, 8.074e-6, 1.378, 5.087e2, 1.586e-2, 0.4723, 1.102e-5];
+const GR_HEP: [f64; 7] = [5.572, 3.185e-7, 1.512, 5.115e3, 3.903e-7, 0.4956, 5.494e-7];
+const GR_CP: [f64; 7] = [45.58, 6.089e-3, 1.128, 4.331e2, 4.845e-2, 0.8120, 1.333e-4];
+const GR_SIP: [f64; 7] = [2.166, 5.678e-8, 1.874, 4.375e4, 1.635e-6, 0.8964, 7.538e-5];
+
+/// Rate coefficient for recombination of an ion on grains, in cm^3
+/// s^-1 per H nucleus for Milky Way dust
+///
+/// Parameters
+///    C : array
+///       fit coefficients for the ion
+///    psi : float
+///       grain charging parameter G0 sqrt(T) / n_e
+///    T : float
+///       gas temperature, in K
+fn grain_recombination(C: &[f64; 7], psi: f64, T: f64) -> f64 {
+    let psi = psi.max(1.0e-20);
+    1.0e-14 * C[0]
+        / (1.0
+            + C[1] * psi.powf(C[2]) * (1.0 + C[3] * T.powf(C[4]) * psi.powf(-C[5] - C[6] * T.ln())))
+}
+
+/// The hydrogen-carbon-oxygen network of Gong, Ostriker & Wolfire
+/// (2017)
+///
+/// Unlike NL99, this network evolves H2 and H+ itself, including H2
+/// formation on grains and self-shielded photodissociation. CHx and
+/// OHx stand for all hydrocarbons and oxygen hydrides, and Si for the
+/// low ionization potential metals. Atomic H, He, C, O, and Si follow
+/// from conservation, and electrons from charge neutrality.
+#[derive(Debug, Clone)]
+pub struct GOW17 {
+    /// total abundance of He nuclei per H nucleus
+    pub xHe: f64,
+    /// total abundance of C nuclei per H nucleus
+    pub xC: f64,
+    /// total abundance of O nuclei per H nucleus
+    pub xO: f64,
+    /// total abundance of Si nuclei per H nucleus
+    pub xSi: f64,
+    /// if true, CO is also photodissociated by cosmic-ray-induced
+    /// UV photons; on by default
+    pub cr_photo: bool,
+    /// current abundances, in the order of species()
+    pub x: Vec<f32>,
+}
+
+impl GOW17 {
+    /// Create a network for a cloud, with elemental abundances scaled
+    /// by the cloud's dust abundance
+    ///
+    /// Parameters
+    ///    cloud : Cloud
+    ///       the cloud to which the network will be attached
+    ///
+    /// Returns
+    ///    a network with H2 and H+ taken from the cloud's composition,
+    ///    all carbon in C+, all oxygen in O, and all silicon in Si+
+    pub fn new(cloud: &Cloud) -> Self {
+        let Zd = cloud.dust.Zd as f64;
+        let (xC, xO, xSi) = (1.6e-4 * Zd, 3.2e-4 * Zd, 1.7e-6 * Zd);
+        let mut x = vec![0.0; SPECIES.len()];
+        x[H2] = cloud.comp.xH2();
+        x[HP] = cloud.comp.xHplus;
+        x[CP] = xC as f32;
+        x[SIP] = xSi as f32;
+        Self {
+            xHe: cloud.comp.xHe as f64,
+            xC,
+            xO,
+            xSi,
+            cr_photo: true,
+            x,
+        }
+    }
+
+    /// Abundances of the species not evolved explicitly, from
+    /// conservation of H, He, C, O, and Si nuclei and of charge
+    ///
+    /// Returns
+    ///    abundances of H, He, C, O, Si, and e
+    fn derived(&self, x: &[f64]) -> [f64; 6] {
+        let xHI = 1.0 - 2.0 * x[H2] - x[HP] - 3.0 * x[H3P];
+        let xHe = self.xHe - x[HEP];
+        let xC = self.xC - x[CP] - x[CHX] - x[CO] - x[HCOP];
+        let xO = self.xO - x[OHX] - x[CO] - x[HCOP];
+        let xSi = self.xSi - x[SIP];
+        let xe = x[HP] + x[H3P] + x[HEP] + x[CP] + x[HCOP] + x[SIP];
+        [xHI, xHe, xC, xO, xSi, xe].map(|v| v.max(0.0))
+    }
+
+    /// Abundance of a species, evolved or derived, by name
+    fn abundance_of(&self, name: &str) -> Option<f64> {
+        let x: Vec<f64> = self.x.iter().map(|&v| v as f64).collect();
+        if let Some(i) = SPECIES.iter().position(|s| s.eq_ignore_ascii_case(name)) {
+            return Some(x[i]);
+        }
+        let d = self.derived(&x);
+        ["H", "He", "C", "O", "Si", "e-"]
+            .iter()
+            .position(|s| s.eq_ignore_ascii_case(name))
+            .map(|i| d[i])
+    }
+}
+
+impl ChemNetwork for GOW17 {
+    fn species(&self) -> &[&str] {
+        &SPECIES
+    }
+
+    fn abundances(&self) -> &[f32] {
+        &self.x
+    }
+
+    fn abundances_mut(&mut self) -> &mut [f32] {
+        &mut self.x
+    }
+
+    fn dxdt(&self, x: &[f64], cloud: &Cloud) -> Vec<f64> {
+        let T = cloud.Tg as f64;
+        let nH = cloud.nH as f64;
+        let Zd = cloud.dust.Zd as f64;
+        let chi = cloud.rad.chi as f64;
+        let zeta = cloud.rad.cr_rates();
+        let colDen = cloud.colDen as f64;
+        let AV = cloud.a_v();
+        let [xHI, xHe, xC, xO, xSi, xe] = self.derived(x);
+        let photo = |k0: f64, gamma: f64| k0 * chi * (-gamma * AV).exp();
+        let log10T = T.log10();
+
+        // Cosmic ray ionization; ionized H2 goes to H3+ by
+        // H2+ + H2 -> H3+ + H
+        let c1 = zeta.H * xHI; // H + cr -> H+ + e
+        let c2 = zeta.H2 * x[H2]; // 2 H2 + cr -> H3+ + H + e
+        let c3 = zeta.He * xHe; // He + cr -> He+ + e
+        let c4 = 3.85 * zeta.H * xC; // C + cr -> C+ + e
+
+        // Two-body reactions
+        let r1 = 2.0e-9 * nH * x[H3P] * xC; // H3+ + C -> CHx + H2
+        let r2 = 1.99e-9 * T.powf(-0.19) * nH * x[H3P] * xO; // H3+ + O -> OHx + H2
+        let r3 = 1.7e-9 * nH * x[H3P] * x[CO]; // H3+ + CO -> HCO+ + H2
+        let r4 = 3.7e-14 * (-35.0 / T).exp() * nH * x[HEP] * x[H2]; // He+ + H2 -> H+ + He + H
+        let r5 = 1.6e-9 * nH * x[HEP] * x[CO]; // He+ + CO -> C+ + O + He
+        let r6 = 2.31e-13 * T.powf(-1.3) * (-23.0 / T).exp() * nH * x[CP] * x[H2]; // C+ + H2 -> CHx + H
+        let r7 = 9.15e-10 * (0.62 + 2.62 * (300.0 / T).sqrt()) * nH * x[CP] * x[OHX]; // C+ + OHx -> HCO+
+        let r8 = 7.7e-11 * nH * x[CHX] * xO; // CHx + O -> CO + H
+        let r9 = 7.95e-10 * T.powf(-0.339) * nH * x[OHX] * xC; // OHx + C -> CO + H
+        let r10 = 1.0e-11 / T.sqrt()
+            * (11.19 - 1.676 * log10T - 0.2852 * log10T.powi(2) + 0.04433 * log10T.powi(3))
+            * nH
+            * x[HEP]
+            * xe; // He+ + e -> He
+        let r11 = 4.54e-7 * T.powf(-0.52) * nH * x[H3P] * xe; // H3+ + e -> H2 + H or 3H
+        let r12 = 1.4e-10 * T.powf(-0.61) * nH * x[CP] * xe; // C+ + e -> C
+        let r13 = 1.06e-5 * T.powf(-0.64) * nH * x[HCOP] * xe; // HCO+ + e -> CO + H
+        let r14 = 2.753e-14
+            * (315614.0 / T).powf(1.5)
+            * (1.0 + (115188.0 / T).powf(0.407)).powf(-2.242)
+            * nH
+            * x[HP]
+            * xe; // H+ + e -> H
+        let r15 = 6.64e-10 * (-11700.0 / T).exp() * nH * xC * x[H2]; // C + H2 -> CHx + H
+        let r16 = 2.81e-11 * T.powf(0.26) * nH * x[CHX] * xHI; // CHx + H -> C + H2
+        let r17 = 1.46e-10 * T.powf(-0.62) * nH * x[SIP] * xe; // Si+ + e -> Si
+
+        // Reactions on grains
+        let psi = if xe > 0.0 {
+            1.7 * chi * (-1.87 * AV).exp() * T.sqrt() / (xe * nH)
+        } else {
+            f64::INFINITY
+        };
+        let grain = |C: &[f64; 7], xi: f64| grain_recombination(C, psi, T) * Zd * nH * xi;
+        let g1 = R_H2_GRAIN * Zd * nH * xHI; // H + H + gr -> H2
+        let g2 = grain(&GR_HP, x[HP]); // H+ + e + gr -> H
+        let g3 = grain(&GR_CP, x[CP]); // C+ + e + gr -> C
+        let g4 = grain(&GR_HEP, x[HEP]); // He+ + e + gr -> He
+        let g5 = grain(&GR_SIP, x[SIP]); // Si+ + e + gr -> Si
+
+        // Photoreactions
+        let p1 = h2_photodissoc_rate(chi, AV, x[H2] * colDen) * x[H2]; // H2 + gamma -> 2H
+        let p2 = co_photodissoc_rate(chi, AV, x[CO] * colDen, x[H2] * colDen) * x[CO]; // CO + gamma -> C + O
+        let p3 = photo(3.5e-10, 3.76) * xC; // C + gamma -> C+ + e
+        let p4 = photo(9.1e-10, 2.12) * x[CHX]; // CHx + gamma -> C + H
+        let p5 = photo(3.8e-10, 2.66) * x[OHX]; // OHx + gamma -> O + H
+        let p6 = photo(1.5e-10, 2.5) * x[HCOP]; // HCO+ + gamma -> CO + H+
+        let p7 = photo(3.1e-9, 2.3) * xSi; // Si + gamma -> Si+ + e
+
+        // Photodissociation by UV photons generated by cosmic rays
+        let p8 = if self.cr_photo {
+            cr_induced_photo_rate(cloud.rad.ionRate as f64, x[H2]) * x[CO] // CO + cr gamma -> C + O
+        } else {
+            0.0
+        };
+
+        let mut dx = vec![0.0; SPECIES.len()];
+        dx[H2] = g1 - 2.0 * c2 + r1 + r2 + r3 - r4 - r6 + 0.25 * r11 - r15 + r16 - p1;
+        dx[HP] = c1 + r4 - r14 - g2 + p6;
+        dx[H3P] = c2 - r1 - r2 - r3 - r11;
+        dx[HEP] = c3 - r4 - r5 - r10 - g4;
+        dx[CP] = c4 + r5 - r6 - r7 - r12 - g3 + p3;
+        dx[CHX] = r1 + r6 + r15 - r8 - r16 - p4;
+        dx[OHX] = r2 - r7 - r9 - p5;
+        dx[CO] = r8 + r9 + r13 - r3 - r5 - p2 + p6 - p8;
+        dx[HCOP] = r3 + r7 - r13 - p6;
+        dx[SIP] = p7 - r17 - g5;
+        dx
+    }
+
+    fn apply_abundances(&self, cloud: &mut Cloud) {
+        for (name, em) in cloud.emitters.iter_mut() {
+            if let Some(x) = self.abundance_of(name) {
+                em.abundance = Abundance::Constant(x as f32);
+            }
+        }
+
+        // The network evolves the hydrogen chemistry, so update the
+        // cloud's composition to match, keeping the H2 ortho-to-para
+        // ratio unchanged
+        let x: Vec<f64> = self.x.iter().map(|&v| v as f64).collect();
+        let [xHI, _, _, _, _, xe] = self.derived(&x);
+        let comp = &mut cloud.comp;
+        let xH2Old = comp.xH2();
+        if xH2Old > 0.0 {
+            let scale = self.x[H2] / xH2Old;
+            comp.xpH2 *= scale;
+            comp.xoH2 *= scale;
+        } else {
+            comp.set_xH2(self.x[H2]);
+        }
+        comp.xHplus = self.x[HP];
+        comp.xHI = xHI as f32;
+        comp.xe = xe as f32;
+        comp.compute_derived(cloud.nH);
+        if cloud.Tg > 0.0 {
+            comp.compute_cv(cloud.Tg);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chemistry::NL99;
+    use crate::testutil::co_cloud;
+
+    /// Fraction of carbon in CO at equilibrium under a network
+    fn co_fraction(net: Box<dyn ChemNetwork>, xC: f64) -> f64 {
+        let mut cloud = co_cloud();
+        cloud.set_nh(1.0e4);
+        cloud.chemnetwork = Some(net);
+        cloud.set_chem_eq(1.0e-4, 1.0e16, &[]).unwrap();
+        let net = cloud.chemnetwork.as_ref().unwrap();
+        let i = net.species().iter().position(|s| *s == "CO").unwrap();
+        net.abundances()[i] as f64 / xC
+    }
+
+    #[test]
+    fn co_fraction_agrees_with_nl99() {
+        let cloud = co_cloud();
+        let gow = GOW17::new(&cloud);
+        let nl = NL99::new(&cloud);
+        let (xCgow, xCnl) = (gow.xC, nl.xC);
+        let fGow = co_fraction(Box::new(gow), xCgow);
+        let fNl = co_fraction(Box::new(nl), xCnl);
+        assert!(fGow > 0.3 && fNl > 0.3, "GOW17 {fGow}, NL99 {fNl}");
+        assert!(
+            (fGow / fNl).ln().abs() < 3.0_f64.ln(),
+            "GOW17 {fGow}, NL99 {fNl}"
+        );
+    }
+}
@@ -0,0 +1,992 @@
+//! Chemical networks and time-dependent chemistry
+
+mod gow17;
+mod nl99;
+
+pub use gow17::GOW17;
+pub use nl99::NL99;
+
+use crate::cloud::Cloud;
+use crate::linalg::solve;
+use crate::roots::{bracket_outward, brent};
+use crate::DespoticError;
+use std::collections::HashMap;
+use std::ops::ControlFlow;
+
+/// Relative tolerance of the chemical integrator
+const RTOL: f64 = 1.0e-5;
+/// Absolute tolerance of the chemical integrator, in abundance per H
+const ATOL: f64 = 1.0e-20;
+/// Maximum number of Newton iterations per implicit step
+const NEWTON_ITER: usize = 10;
+/// Initial time step of the chemical integrator, in s (about one year)
+const DT_INIT: f64 = 3.0e7;
+/// Initial evolution time used when searching for equilibrium, in s
+/// (about a thousand years)
+const T_EQ_INIT: f64 = 3.0e10;
+/// Range of log10 abundance per H searched for single-species
+/// equilibria
+const LOG_X_MIN: f64 = -30.0;
+const LOG_X_MAX: f64 = 0.0;
+/// Maximum evolution time allowed for each chemical equilibrium
+/// solve when iterating chemistry and temperature together, in s
+/// (about a billion years)
+const T_EQ_MAX: f64 = 3.0e16;
+
+/// Shielding of CO photodissociation by CO and H2 line absorption
+///
+/// Parameters
+///    NCO : float
+///       CO column density, in cm^-2
+///    NH2 : float
+///       H2 column density, in cm^-2
+///
+/// Remarks
+///    This is our own power-law fit to the shielding factors that
+///    van Dishoeck & Black (1988, ApJ, 334, 771) tabulate against
+///    both columns; CO lines saturate first, and the H2 Lyman and
+///    Werner lines, which overlap many CO bands, take over at depth.
+///    It is written as a product of a CO and an H2 factor, as Gong,
+///    Ostriker & Wolfire (2017) do with the updated tables of Visser,
+///    van Dishoeck & Black (2009). The fit follows the tables to
+///    within a few tens of percent, which is adequate next to the
+///    uncertainty in the unshielded rates.
+fn co_shielding(NCO: f64, NH2: f64) -> f64 {
+    (1.0 + NCO / 3.0e14).powf(-0.75) * (1.0 + NH2 / 3.0e21).powf(-1.3)
+}
+
+/// Self-shielding of H2 photodissociation (Draine & Bertoldi 1996)
+///
+/// Parameters
+///    NH2 : float
+///       H2 column density, in cm^-2
+///    b5 : float
+///       Doppler parameter of the H2 lines, in km s^-1
+fn h2_shielding(NH2: f64, b5: f64) -> f64 {
+    let x = NH2 / 5.0e14;
+    0.965 / (1.0 + x / b5).powi(2) + 0.035 / (1.0 + x).sqrt() * (-8.5e-4 * (1.0 + x).sqrt()).exp()
+}
+
+/// Doppler parameter assumed for H2 self-shielding, in km s^-1
+const B_H2: f64 = 3.0;
+
+/// CO photodissociations per primary cosmic ray ionization in fully
+/// molecular gas, including the dependence on grain albedo; Gredel et
+/// al. (1987) find values of a few
+const CR_PHOTO_CO: f64 = 5.0;
+
+/// Photodissociation rate of CO in a cloud, including attenuation of
+/// the FUV field by dust and shielding by CO and H2 line absorption
+///
+/// Parameters
+///    chi : float
+///       strength of the unattenuated ISRF, normalized to the solar
+///       neighborhood value
+///    av : float
+///       visual extinction to the point of interest, in mag
+///    n_co : float
+///       CO column density to the point of interest, in cm^-2
+///    n_h2 : float
+///       H2 column density to the point of interest, in cm^-2
+///
+/// Returns
+///    photodissociation rate per CO molecule, in s^-1
+///
+/// Remarks
+///    The unshielded rate and its dust attenuation exponent are
+///    those of Gong, Ostriker & Wolfire (2017). The H2 column is
+///    needed because H2 lines overlap those of CO and, deep in a
+///    cloud, do most of the shielding; see co_shielding for the
+///    shielding factors.
+pub fn co_photodissoc_rate(chi: f64, av: f64, n_co: f64, n_h2: f64) -> f64 {
+    2.0e-10 * chi * (-3.53 * av).exp() * co_shielding(n_co, n_h2)
+}
+
+/// Photodissociation rate of H2 in a cloud, including attenuation of
+/// the FUV field by dust and self-shielding
+///
+/// Parameters
+///    chi : float
+///       strength of the unattenuated ISRF, normalized to the solar
+///       neighborhood value
+///    av : float
+///       visual extinction to the point of interest, in mag
+///    n_h2 : float
+///       H2 column density to the point of interest, in cm^-2
+///
+/// Returns
+///    photodissociation rate per H2 molecule, in s^-1
+///
+/// Remarks
+///    The unshielded rate and its dust attenuation exponent are
+///    those of Gong, Ostriker & Wolfire (2017), and self-shielding
+///    follows Draine & Bertoldi (1996) for a Doppler parameter of
+///    3 km s^-1
+pub fn h2_photodissoc_rate(chi: f64, av: f64, n_h2: f64) -> f64 {
+    5.7e-11 * chi * (-3.74 * av).exp() * h2_shielding(n_h2, B_H2)
+}
+
+/// Rate of CO photodissociation by the UV photons that cosmic rays
+/// generate inside a cloud
+///
+/// Parameters
+///    ion_rate : float
+///       primary ionization rate, in s^-1 H^-1
+///    x_h2 : float
+///       abundance of H2 per H nucleus
+///
+/// Returns
+///    photodissociation rate per CO molecule, in s^-1
+///
+/// Remarks
+///    Secondary electrons from cosmic ray ionizations excite H2,
+///    which then emits in the Lyman and Werner bands (Prasad &
+///    Tarafdar 1983). The photons are produced in proportion to the
+///    H2 fraction, and since they are generated within the cloud the
+///    rate does not fall off with extinction, so it dominates CO
+///    destruction deep in a cloud.
+pub fn cr_induced_photo_rate(ion_rate: f64, x_h2: f64) -> f64 {
+    CR_PHOTO_CO * ion_rate * 2.0 * x_h2
+}
+
+/// A chemical reaction network that can be attached to a cloud
+pub trait ChemNetwork: std::fmt::Debug + Send + Sync {
+    /// Names of the species whose abundances the network evolves
+    fn species(&self) -> &[&str];
+
+    /// Current abundances per H nucleus, in the order of species()
+    fn abundances(&self) -> &[f32];
+
+    /// Mutable access to the current abundances
+    fn abundances_mut(&mut self) -> &mut [f32];
+
+    /// Time derivatives of the abundances
+    ///
+    /// Parameters
+    ///    x : array
+    ///       abundances per H nucleus, in the order of species()
+    ///    cloud : Cloud
+    ///       the cloud whose physical conditions set the reaction
+    ///       rates
+    ///
+    /// Returns
+    ///    dx/dt for each species, in s^-1
+    fn dxdt(&self, x: &[f64], cloud: &Cloud) -> Vec<f64>;
+
+    /// Copy the network's abundances into the matching emitters of a
+    /// cloud
+    fn apply_abundances(&self, cloud: &mut Cloud);
+}
+
+/// Time derivatives of a network's abundances, with those of the
+/// species at the indices in fixed set to zero
+fn held_dxdt(net: &dyn ChemNetwork, x: &[f64], cloud: &Cloud, fixed: &[usize]) -> Vec<f64> {
+    let mut dx = net.dxdt(x, cloud);
+    for &i in fixed {
+        dx[i] = 0.0;
+    }
+    dx
+}
+
+/// Advance abundances through one implicit (backward) Euler step,
+/// solving the implicit equations by Newton iteration with a fixed
+/// Jacobian
+fn implicit_step(
+    x: &[f64],
+    dt: f64,
+    f: &impl Fn(&[f64]) -> Vec<f64>,
+    jac: &[Vec<f64>],
+) -> Option<Vec<f64>> {
+    let n = x.len();
+    let m: Vec<Vec<f64>> = (0..n)
+        .map(|i| {
+            (0..n)
+                .map(|j| if i == j { 1.0 } else { 0.0 } - dt * jac[i][j])
+                .collect()
+        })
+        .collect();
+    let mut y = x.to_vec();
+    for _ in 0..NEWTON_ITER {
+        let fy = f(&y);
+        let resid: Vec<f64> = (0..n).map(|i| x[i] + dt * fy[i] - y[i]).collect();
+        let delta = solve(m.clone(), resid)?;
+        let mut converged = true;
+        for (yi, di) in y.iter_mut().zip(&delta) {
+            *yi += di;
+            converged &= di.abs() <= 1.0e-3 * (ATOL + RTOL * yi.abs());
+        }
+        if converged {
+            return Some(y);
+        }
+    }
+    None
+}
+
+/// Finite-difference Jacobian of f at x, with element [i][j] giving
+/// d f_i / d x_j
+fn jacobian(x: &[f64], f: &impl Fn(&[f64]) -> Vec<f64>) -> Vec<Vec<f64>> {
+    let n = x.len();
+    let f0 = f(x);
+    let mut jac = vec![vec![0.0; n]; n];
+    let mut xp = x.to_vec();
+    for j in 0..n {
+        let dx = 1.0e-7 * x[j].abs().max(1.0e-14);
+        xp[j] = x[j] + dx;
+        let fp = f(&xp);
+        for i in 0..n {
+            jac[i][j] = (fp[i] - f0[i]) / dx;
+        }
+        xp[j] = x[j];
+    }
+    jac
+}
+
+/// Integrate the stiff system dx/dt = f(x) over a time span
+///
+/// Parameters
+///    x : array
+///       initial values; overwritten with the final values
+///    span : float
+///       time over which to integrate, in s
+///    dt : float
+///       initial step size guess, in s; updated with the step size
+///       the integrator would take next
+///    f : function
+///       right-hand side of the system
+///    on_step : function
+///       called after each accepted step with the time since the
+///       start of the span and the current values; integration stops
+///       early if it returns Break
+///
+/// Returns
+///    Break if on_step stopped the integration, otherwise Continue
+///
+/// Remarks
+///    Steps use implicit Euler with the error estimated by step
+///    doubling. Values are clipped at zero after each step, since
+///    they are abundances.
+fn integrate(
+    x: &mut [f64],
+    span: f64,
+    dt: &mut f64,
+    f: impl Fn(&[f64]) -> Vec<f64>,
+    on_step: &mut impl FnMut(f64, &[f64]) -> ControlFlow<()>,
+) -> Result<ControlFlow<()>, DespoticError> {
+    let mut t = 0.0;
+    while t < span {
+        let h = dt.min(span - t);
+        if h <= 1.0e-12 * span {
+            return Err(DespoticError::NoConvergence(
+                "chemical integration step size underflow".to_string(),
+            ));
+        }
+        let jac = jacobian(x, &f);
+        let full = implicit_step(x, h, &f, &jac);
+        let half =
+            implicit_step(x, h / 2.0, &f, &jac).and_then(|y| implicit_step(&y, h / 2.0, &f, &jac));
+        let (Some(full), Some(half)) = (full, half) else {
+            *dt = h / 4.0;
+            continue;
+        };
+        let err = full
+            .iter()
+            .zip(&half)
+            .map(|(a, b)| (a - b).abs() / (ATOL + RTOL * b.abs()))
+            .fold(0.0, f64::max);
+        let factor = if err > 0.0 { 0.9 / err.sqrt() } else { 5.0 };
+        if err <= 1.0 {
+            for (xi, yi) in x.iter_mut().zip(&half) {
+                *xi = yi.max(0.0);
+            }
+            t += h;
+            *dt = h * factor.min(5.0);
+            if on_step(t, x).is_break() {
+                return Ok(ControlFlow::Break(()));
+            }
+        } else {
+            *dt = h * factor.max(0.2);
+        }
+    }
+    Ok(ControlFlow::Continue(()))
+}
+
+impl Cloud {
+    /// Take the attached chemical network, or fail if there is none
+    fn take_network(&mut self) -> Result<Box<dyn ChemNetwork>, DespoticError> {
+        self.chemnetwork
+            .take()
+            .ok_or_else(|| DespoticError::MissingField("chemnetwork".to_string()))
+    }
+
+    /// Indices in the attached network of the named species
+    fn species_indices(
+        net: &dyn ChemNetwork,
+        names: &[String],
+    ) -> Result<Vec<usize>, DespoticError> {
+        names
+            .iter()
+            .map(|name| {
+                net.species()
+                    .iter()
+                    .position(|s| s == name)
+                    .ok_or_else(|| DespoticError::UnknownSpecies(name.clone()))
+            })
+            .collect()
+    }
+
+    /// Set the abundances of species in the attached chemical network
+    ///
+    /// Parameters
+    ///    abundances : dict
+    ///       abundances per H nucleus, keyed by species name; species
+    ///       not listed keep their current abundances
+    ///
+    /// Returns
+    ///    Nothing
+    ///
+    /// Remarks
+    ///    The new abundances are the starting point for subsequent
+    ///    calls to evolve and set_chem_eq. An error is returned, and
+    ///    nothing is changed, if no network is attached or if any
+    ///    name is not a species of the network.
+    pub fn set_chem_abundances(
+        &mut self,
+        abundances: &HashMap<String, f32>,
+    ) -> Result<(), DespoticError> {
+        let net = self
+            .chemnetwork
+            .as_mut()
+            .ok_or_else(|| DespoticError::MissingField("chemnetwork".to_string()))?;
+        let mut idx = Vec::with_capacity(abundances.len());
+        for (name, &x) in abundances {
+            let i = net
+                .species()
+                .iter()
+                .position(|s| s == name)
+                .ok_or_else(|| DespoticError::UnknownSpecies(name.clone()))?;
+            idx.push((i, x));
+        }
+        let xs = net.abundances_mut();
+        for (i, x) in idx {
+            xs[i] = x;
+        }
+        Ok(())
+    }
+
+    /// Evolve the chemical abundances in time
+    ///
+    /// Parameters
+    ///    t_end : float
+    ///       time to which to evolve, in s
+    ///    output_times : array
+    ///       times, in increasing order and no later than t_end, at
+    ///       which to record the abundances
+    ///    fixed : list of strings
+    ///       species whose abundances are held at their current values
+    ///
+    /// Returns
+    ///    the abundances of the network species at each output time
+    ///
+    /// Remarks
+    ///    Evolution starts at time zero from the network's current
+    ///    abundances and holds the cloud's physical properties fixed.
+    ///    On return the network holds the abundances at t_end, and
+    ///    these are also copied into the cloud's emitters.
+    pub fn evolve(
+        &mut self,
+        t_end: f64,
+        output_times: &[f64],
+        fixed: &[String],
+    ) -> Result<Vec<Vec<f32>>, DespoticError> {
+        self.evolve_with(t_end, output_times, fixed, |_, _| ControlFlow::Continue(()))
+    }
+
+    /// Evolve the chemical abundances in time, reporting progress
+    ///
+    /// Parameters
+    ///    t_end : float
+    ///       time to which to evolve, in s
+    ///    output_times : array
+    ///       times, in increasing order and no later than t_end, at
+    ///       which to record the abundances
+    ///    fixed : list of strings
+    ///       species whose abundances are held at their current values
+    ///    on_step : function
+    ///       called after each step the integrator accepts, with the
+    ///       time in s and the abundances of the network species;
+    ///       returning Break stops the evolution
+    ///
+    /// Returns
+    ///    the abundances of the network species at each output time
+    ///    reached
+    ///
+    /// Remarks
+    ///    As evolve, except that if on_step returns Break the
+    ///    evolution stops there: the history holds only the output
+    ///    times already passed, and the network and emitters hold the
+    ///    abundances at the time of the break.
+    pub fn evolve_with(
+        &mut self,
+        t_end: f64,
+        output_times: &[f64],
+        fixed: &[String],
+        mut on_step: impl FnMut(f64, &[f32]) -> ControlFlow<()>,
+    ) -> Result<Vec<Vec<f32>>, DespoticError> {
+        self.check_density()?;
+        let mut net = self.take_network()?;
+        let fixedIdx = match Self::species_indices(net.as_ref(), fixed) {
+            Ok(idx) => idx,
+            Err(e) => {
+                self.chemnetwork = Some(net);
+                return Err(e);
+            }
+        };
+        let rhs = |x: &[f64]| held_dxdt(net.as_ref(), x, self, &fixedIdx);
+        let mut x: Vec<f64> = net.abundances().iter().map(|&v| v as f64).collect();
+        let mut dt = DT_INIT;
+        let mut t = 0.0;
+        let mut history = Vec::with_capacity(output_times.len());
+        let mut result = Ok(());
+        for &tout in output_times.iter().chain(std::iter::once(&t_end)) {
+            let (t0, span) = (t, tout - t);
+            let mut reached = false;
+            let mut report = |s: f64, x: &[f64]| {
+                reached = s >= span;
+                let xs: Vec<f32> = x.iter().map(|&v| v as f32).collect();
+                on_step(t0 + s, &xs)
+            };
+            match integrate(&mut x, span, &mut dt, rhs, &mut report) {
+                Ok(ControlFlow::Continue(())) => {}
+                Ok(ControlFlow::Break(())) => {
+                    // A break on the step that reaches an output time
+                    // still records that output
+                    if reached {
+                        history.push(x.iter().map(|&v| v as f32).collect());
+                    }
+                    break;
+                }
+                Err(e) => {
+                    result = Err(e);
+                    break;
+                }
+            }
+            t = tout;
+            history.push(x.iter().map(|&v| v as f32).collect());
+        }
+        history.truncate(output_times.len());
+        for (xs, v) in net.abundances_mut().iter_mut().zip(&x) {
+            *xs = *v as f32;
+        }
+        net.apply_abundances(self);
+        self.chemnetwork = Some(net);
+        result.map(|_| history)
+    }
+
+    /// Evolve the chemistry to equilibrium
+    ///
+    /// Parameters
+    ///    tol : float
+    ///       relative change in abundances below which the chemistry
+    ///       is considered to have reached equilibrium
+    ///    max_time : float
+    ///       maximum evolution time, in s
+    ///    fixed : list of strings
+    ///       species whose abundances are held at their current values
+    ///
+    /// Returns
+    ///    Nothing
+    ///
+    /// Remarks
+    ///    The chemistry is evolved over successively doubling time
+    ///    intervals until the abundances change by less than tol over
+    ///    an interval. The cloud's physical properties are held fixed.
+    ///    On return the network holds the equilibrium abundances, and
+    ///    these are also copied into the cloud's emitters.
+    pub fn set_chem_eq(
+        &mut self,
+        tol: f64,
+        max_time: f64,
+        fixed: &[String],
+    ) -> Result<(), DespoticError> {
+        self.check_density()?;
+        let mut net = self.take_network()?;
+        let fixedIdx = match Self::species_indices(net.as_ref(), fixed) {
+            Ok(idx) => idx,
+            Err(e) => {
+                self.chemnetwork = Some(net);
+                return Err(e);
+            }
+        };
+        let rhs = |x: &[f64]| held_dxdt(net.as_ref(), x, self, &fixedIdx);
+        let mut x: Vec<f64> = net.abundances().iter().map(|&v| v as f64).collect();
+        let mut dt = DT_INIT;
+        let mut t = 0.0;
+        let mut span = T_EQ_INIT;
+        let result = loop {
+            let xOld = x.clone();
+            let mut no_report = |_: f64, _: &[f64]| ControlFlow::Continue(());
+            if let Err(e) = integrate(&mut x, span, &mut dt, rhs, &mut no_report) {
+                break Err(e);
+            }
+            t += span;
+            let change = x
+                .iter()
+                .zip(&xOld)
+                .map(|(n, o)| (n - o).abs() / (n.abs() + ATOL))
+                .fold(0.0, f64::max);
+            if change < tol {
+                break Ok(());
+            }
+            if t >= max_time {
+                break Err(DespoticError::NoConvergence(format!(
+                    "chemistry not in equilibrium after {t:e} s"
+                )));
+            }
+            span = (2.0 * span).min(max_time - t);
+        };
+        for (xs, v) in net.abundances_mut().iter_mut().zip(&x) {
+            *xs = *v as f32;
+        }
+        net.apply_abundances(self);
+        self.chemnetwork = Some(net);
+        result
+    }
+
+    /// Equilibrium abundance of one species, holding all others fixed
+    ///
+    /// Parameters
+    ///    species : string
+    ///       name of a species of the attached network
+    ///
+    /// Returns
+    ///    the abundance per H nucleus at which the species' rate of
+    ///    change vanishes
+    ///
+    /// Remarks
+    ///    All other species are held at the network's current
+    ///    abundances, so this is a quick estimate rather than a
+    ///    substitute for set_chem_eq; the two agree only if the other
+    ///    species are already in equilibrium. The abundance is found
+    ///    by root finding between 1e-30 and 1; if the species is
+    ///    destroyed faster than it forms throughout that range the
+    ///    equilibrium abundance is zero. Neither the network nor the
+    ///    cloud is changed.
+    pub fn species_equilibrium(&self, species: &str) -> Result<f32, DespoticError> {
+        self.check_density()?;
+        let net = self
+            .chemnetwork
+            .as_deref()
+            .ok_or_else(|| DespoticError::MissingField("chemnetwork".to_string()))?;
+        let i = net
+            .species()
+            .iter()
+            .position(|s| *s == species)
+            .ok_or_else(|| DespoticError::UnknownSpecies(species.to_string()))?;
+        let mut x: Vec<f64> = net.abundances().iter().map(|&v| v as f64).collect();
+        let x0 = if x[i] > 0.0 { x[i].log10() } else { LOG_X_MIN };
+        let mut resid = |logx: f64| -> Result<f64, DespoticError> {
+            x[i] = 10.0_f64.powf(logx);
+            Ok(net.dxdt(&x, self)[i])
+        };
+        match bracket_outward(&mut resid, x0, 1.0, (LOG_X_MIN, LOG_X_MAX))? {
+            Some((lo, hi)) => match brent(resid, lo, hi, 1.0e-6, 100)? {
+                Some(logx) => Ok(10.0_f64.powf(logx) as f32),
+                None => Err(DespoticError::NoConvergence(format!(
+                    "equilibrium abundance of {species}"
+                ))),
+            },
+            None if resid(LOG_X_MIN)? <= 0.0 => Ok(0.0),
+            None => Err(DespoticError::NoConvergence(format!(
+                "{species} forms faster than it is destroyed at all abundances"
+            ))),
+        }
+    }
+
+    /// Set the chemistry and temperatures to a mutually consistent
+    /// equilibrium
+    ///
+    /// Parameters
+    ///    tol : float
+    ///       relative change in the temperatures and abundances below
+    ///       which the solution is considered converged
+    ///    max_iter : int
+    ///       maximum number of alternating chemistry and temperature
+    ///       solves
+    ///    acceleration : Acceleration
+    ///       how each iterate is formed from the result of the last
+    ///       chemistry and temperature solve
+    ///
+    /// Returns
+    ///    Nothing
+    ///
+    /// Remarks
+    ///    Abundances change the cooling, and the temperature changes
+    ///    the reaction rates, so the two are found by alternately
+    ///    calling set_chem_eq, with tolerance tol, and set_temp_eq,
+    ///    until an iteration changes neither Tg, Td nor any abundance
+    ///    by more than a relative tol. Changes in abundance are
+    ///    measured relative to the abundance plus 1e-20, so that
+    ///    trace species do not hold up convergence. If max_iter
+    ///    iterations do not suffice, a NoConvergence error reports
+    ///    the last changes; the cloud holds the final iterate.
+    pub fn set_chem_temp_eq(
+        &mut self,
+        tol: f64,
+        max_iter: usize,
+        acceleration: Acceleration,
+    ) -> Result<(), DespoticError> {
+        let change = |new: f64, old: f64| (new - old).abs() / (new.abs() + ATOL);
+        let mut residuals = (0.0, 0.0, 0.0);
+        let mut history = AndersonHistory::default();
+        for _ in 0..max_iter {
+            let s = self.chem_temp_state()?;
+            self.set_chem_eq(tol, T_EQ_MAX, &[])?;
+            self.set_temp_eq()?;
+            let g = self.chem_temp_state()?;
+            let dx = g[2..]
+                .iter()
+                .zip(&s[2..])
+                .map(|(&n, &o)| change(n, o))
+                .fold(0.0, f64::max);
+            residuals = (change(g[0], s[0]), change(g[1], s[1]), dx);
+            if residuals.0 < tol && residuals.1 < tol && residuals.2 < tol {
+                return Ok(());
+            }
+            let next = match acceleration {
+                Acceleration::None => continue,
+                Acceleration::UnderRelax(w) => s
+                    .iter()
+                    .zip(&g)
+                    .map(|(si, gi)| si + w * (gi - si))
+                    .collect(),
+                Acceleration::Anderson(depth) => history.next(&s, &g, depth),
+            };
+            self.set_chem_temp_state(&next, &g)?;
+        }
+        let (dTg, dTd, dx) = residuals;
+        Err(DespoticError::NoConvergence(format!(
+            "chemistry and temperature after {max_iter} iterations: relative changes \
+             Tg {dTg:e}, Td {dTd:e}, abundances {dx:e}"
+        )))
+    }
+
+    /// Gas and dust temperatures followed by the network abundances,
+    /// the state iterated by set_chem_temp_eq
+    fn chem_temp_state(&self) -> Result<Vec<f64>, DespoticError> {
+        let net = self
+            .chemnetwork
+            .as_deref()
+            .ok_or_else(|| DespoticError::MissingField("chemnetwork".to_string()))?;
+        let mut state = vec![self.Tg as f64, self.Td as f64];
+        state.extend(net.abundances().iter().map(|&v| v as f64));
+        Ok(state)
+    }
+
+    /// Set the temperatures and abundances from a state laid out as
+    /// by chem_temp_state
+    ///
+    /// Remarks
+    ///    An extrapolated state can be unphysical, so temperatures
+    ///    that are not positive are replaced by those of fallback, and
+    ///    negative abundances by zero
+    fn set_chem_temp_state(
+        &mut self,
+        state: &[f64],
+        fallback: &[f64],
+    ) -> Result<(), DespoticError> {
+        let temp = |i: usize| {
+            if state[i] > 0.0 {
+                state[i]
+            } else {
+                fallback[i]
+            }
+        };
+        self.set_tg(temp(0));
+        self.set_td(temp(1));
+        let mut net = self.take_network()?;
+        for (xs, v) in net.abundances_mut().iter_mut().zip(&state[2..]) {
+            *xs = v.max(0.0) as f32;
+        }
+        net.apply_abundances(self);
+        self.chemnetwork = Some(net);
+        Ok(())
+    }
+}
+
+/// How set_chem_temp_eq forms each iterate of the coupled chemistry
+/// and temperature solve
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Acceleration {
+    /// plain fixed-point iteration; each iterate is the result of the
+    /// last solve
+    #[default]
+    None,
+    /// move only the given fraction, between 0 and 1, of the way from
+    /// the last iterate to the result of the last solve
+    UnderRelax(f64),
+    /// Anderson mixing over at most the given number of previous
+    /// iterates
+    Anderson(usize),
+}
+
+/// Previous iterates retained for Anderson mixing
+#[derive(Debug, Default)]
+struct AndersonHistory {
+    /// last iterate and the result of solving from it
+    last: Option<(Vec<f64>, Vec<f64>)>,
+    /// differences between successive residuals g - s
+    dF: Vec<Vec<f64>>,
+    /// differences between successive solve results g
+    dG: Vec<Vec<f64>>,
+}
+
+impl AndersonHistory {
+    /// Next iterate given the iterate s and the result g of solving
+    /// from it, mixing in at most depth previous iterates
+    ///
+    /// Remarks
+    ///    The combination of previous residuals is chosen to minimize
+    ///    the residual measured relative to |g| + 1e-20, the same
+    ///    measure used to test for convergence. If the least-squares
+    ///    problem is singular the iterate is g, as for plain
+    ///    fixed-point iteration.
+    fn next(&mut self, s: &[f64], g: &[f64], depth: usize) -> Vec<f64> {
+        let f: Vec<f64> = g.iter().zip(s).map(|(gi, si)| gi - si).collect();
+        if let Some((sOld, gOld)) = self.last.take() {
+            let fOld = gOld.iter().zip(&sOld).map(|(gi, si)| gi - si);
+            self.dF
+                .push(f.iter().zip(fOld).map(|(n, o)| n - o).collect());
+            self.dG
+                .push(g.iter().zip(&gOld).map(|(n, o)| n - o).collect());
+        }
+        self.last = Some((s.to_vec(), g.to_vec()));
+        while self.dF.len() > depth {
+            self.dF.remove(0);
+            self.dG.remove(0);
+        }
+        if self.dF.is_empty() {
+            return g.to_vec();
+        }
+        let w2: Vec<f64> = g.iter().map(|gi| (gi.abs() + ATOL).powi(-2)).collect();
+        let dot = |a: &[f64], b: &[f64]| -> f64 {
+            a.iter()
+                .zip(b)
+                .zip(&w2)
+                .map(|((ai, bi), wi)| ai * bi * wi)
+                .sum()
+        };
+        let a: Vec<Vec<f64>> = self
+            .dF
+            .iter()
+            .map(|di| self.dF.iter().map(|dj| dot(di, dj)).collect())
+            .collect();
+        let b: Vec<f64> = self.dF.iter().map(|di| dot(di, &f)).collect();
+        let Some(gamma) = solve(a, b) else {
+            return g.to_vec();
+        };
+        let mut next = g.to_vec();
+        for (dg, gam) in self.dG.iter().zip(gamma) {
+            for (n, d) in next.iter_mut().zip(dg) {
+                *n -= gam * d;
+            }
+        }
+        next
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::co_cloud;
+
+    /// A cold molecular cloud with the NL99 network attached
+    fn nl99_cloud() -> Cloud {
+        let mut cloud = co_cloud();
+        cloud.chemnetwork = Some(Box::new(NL99::new(&cloud)));
+        cloud
+    }
+
+    fn index(cloud: &Cloud, species: &str) -> usize {
+        let net = cloud.chemnetwork.as_ref().unwrap();
+        net.species().iter().position(|s| *s == species).unwrap()
+    }
+
+    #[test]
+    fn evolve_starts_from_seeded_abundances() {
+        let mut cloud = nl99_cloud();
+        let seed = HashMap::from([
+            ("CO".to_string(), 1.5e-4),
+            ("C+".to_string(), 0.5e-4),
+            ("O".to_string(), 2.5e-4),
+        ]);
+        cloud.set_chem_abundances(&seed).unwrap();
+        let co = index(&cloud, "CO");
+        assert_eq!(cloud.chemnetwork.as_ref().unwrap().abundances()[co], 1.5e-4);
+
+        // After a second the abundances have barely moved
+        let history = cloud.evolve(1.0, &[1.0], &[]).unwrap();
+        let x = history[0][co];
+        assert!((x - 1.5e-4).abs() < 1.0e-3 * 1.5e-4, "x(CO) = {x:e}");
+    }
+
+    #[test]
+    fn seeding_checks_species_and_network() {
+        let mut cloud = nl99_cloud();
+        let bad = HashMap::from([("XYZ".to_string(), 1.0e-6)]);
+        assert!(matches!(
+            cloud.set_chem_abundances(&bad),
+            Err(DespoticError::UnknownSpecies(s)) if s == "XYZ"
+        ));
+        cloud.chemnetwork = None;
+        assert!(matches!(
+            cloud.set_chem_abundances(&HashMap::new()),
+            Err(DespoticError::MissingField(_))
+        ));
+    }
+
+    #[test]
+    fn fixed_species_are_held() {
+        let mut cloud = co_cloud();
+        cloud.set_nh(1.0e4);
+        cloud.chemnetwork = Some(Box::new(GOW17::new(&cloud)));
+        let seed = HashMap::from([("H2".to_string(), 0.3)]);
+        cloud.set_chem_abundances(&seed).unwrap();
+        let (h2, co) = (index(&cloud, "H2"), index(&cloud, "CO"));
+        cloud
+            .set_chem_eq(1.0e-4, 1.0e16, &["H2".to_string()])
+            .unwrap();
+        let x = cloud.chemnetwork.as_ref().unwrap().abundances();
+        assert_eq!(x[h2], 0.3);
+        assert!(x[co] > 1.0e-6, "x(CO) = {:e}", x[co]);
+    }
+
+    #[test]
+    fn species_equilibrium_agrees_with_network_equilibrium() {
+        let mut cloud = nl99_cloud();
+        cloud.set_chem_eq(1.0e-6, 1.0e16, &[]).unwrap();
+        let xCO = cloud.chemnetwork.as_ref().unwrap().abundances()[index(&cloud, "CO")];
+        let estimate = cloud.species_equilibrium("CO").unwrap();
+        assert!((estimate / xCO - 1.0).abs() < 1.0e-3, "{estimate} vs {xCO}");
+        assert!(matches!(
+            cloud.species_equilibrium("HCN"),
+            Err(DespoticError::UnknownSpecies(_))
+        ));
+    }
+
+    #[test]
+    fn photodissociation_falls_off_with_depth() {
+        // Column densities grow in proportion to A_V, with N(H) =
+        // 1.9e21 A_V, half the H in H2 and 1e-4 of it in CO
+        let columns = |av: f64| (0.95e21 * av, 1.9e17 * av);
+        let co = |av: f64| {
+            let (NH2, NCO) = columns(av);
+            co_photodissoc_rate(1.0, av, NCO, NH2)
+        };
+        let h2 = |av: f64| h2_photodissoc_rate(1.0, av, columns(av).0);
+        for rate in [&co as &dyn Fn(f64) -> f64, &h2] {
+            assert!(rate(0.5) < rate(0.1));
+            assert!(rate(2.0) < 1.0e-3 * rate(0.1));
+            assert!(rate(5.0) < 1.0e-5 * rate(2.0));
+        }
+
+        // H2 lines shield CO even where the CO column is small
+        let av = 2.0;
+        assert!(
+            co_photodissoc_rate(1.0, av, 1.0e13, 1.0e22)
+                < 0.2 * co_photodissoc_rate(1.0, av, 1.0e13, 0.0)
+        );
+    }
+
+    #[test]
+    fn cr_photodissociation_dominates_at_depth() {
+        let ionRate = 2.0e-17;
+        let cr = cr_induced_photo_rate(ionRate, 0.5);
+        let uv = |av: f64| co_photodissoc_rate(1.0, av, 1.9e17 * av, 0.95e21 * av);
+        assert!(uv(0.5) > 100.0 * cr);
+        assert!(cr > 100.0 * uv(5.0));
+
+        // In the network, CO is destroyed faster with the term on
+        let mut cloud = nl99_cloud();
+        cloud.colDen = 1.0e23;
+        let mut net = NL99::new(&cloud);
+        let mut x = vec![0.0; net.species().len()];
+        x[index(&cloud, "CO")] = 1.0e-4;
+        let with = net.dxdt(&x, &cloud)[index(&cloud, "CO")];
+        net.cr_photo = false;
+        let without = net.dxdt(&x, &cloud)[index(&cloud, "CO")];
+        assert!(with < 0.0 && without > 2.0 * with);
+    }
+
+    #[test]
+    fn break_stops_evolution() {
+        let mut cloud = nl99_cloud();
+        let outputs = [1.0, 10.0, 1.0e15];
+        let mut steps = Vec::new();
+        let history = cloud
+            .evolve_with(1.0e15, &outputs, &[], |t, x| {
+                steps.push((t, x.to_vec()));
+                if steps.len() == 3 {
+                    ControlFlow::Break(())
+                } else {
+                    ControlFlow::Continue(())
+                }
+            })
+            .unwrap();
+        assert_eq!(steps.len(), 3);
+        let (tBreak, xBreak) = steps.last().unwrap();
+        assert!(*tBreak < 1.0e15);
+        let passed = outputs.iter().filter(|&&t| t <= *tBreak).count();
+        assert_eq!(passed, 2);
+        assert_eq!(history.len(), passed);
+        assert_eq!(history[1], *xBreak);
+        assert_eq!(
+            cloud.chemnetwork.as_ref().unwrap().abundances(),
+            &xBreak[..]
+        );
+    }
+
+    #[test]
+    fn dense_core_reaches_cold_molecular_state() {
+        let mut cloud = nl99_cloud();
+        cloud.set_nh(1.0e5);
+        cloud
+            .set_chem_temp_eq(1.0e-3, 50, Acceleration::None)
+            .unwrap();
+        let co = index(&cloud, "CO");
+        let xCO = cloud.chemnetwork.as_ref().unwrap().abundances()[co];
+        let Tg = cloud.Tg;
+        assert!(Tg < 15.0 && cloud.Td < 15.0, "Tg = {Tg}, Td = {}", cloud.Td);
+        assert!(xCO > 1.0e-4, "x(CO) = {xCO:e}");
+
+        // One more round of each solve leaves the state unchanged
+        cloud.set_chem_eq(1.0e-3, T_EQ_MAX, &[]).unwrap();
+        cloud.set_temp_eq().unwrap();
+        let xAfter = cloud.chemnetwork.as_ref().unwrap().abundances()[co];
+        assert!(((cloud.Tg - Tg) / Tg).abs() < 1.0e-3);
+        assert!(((xAfter - xCO) / xCO).abs() < 1.0e-3);
+    }
+
+    #[test]
+    fn anderson_converges_in_fewer_iterations() {
+        // In diffuse gas plain alternation between the chemistry and
+        // temperature solves converges slowly
+        let solve = |max_iter, acceleration| {
+            let mut cloud = nl99_cloud();
+            cloud.set_nh(1.0e2);
+            cloud.set_tg(100.0);
+            cloud.set_td(100.0);
+            cloud
+                .set_chem_temp_eq(1.0e-4, max_iter, acceleration)
+                .map(|_| cloud.Tg)
+        };
+        let Tg = solve(10, Acceleration::Anderson(3)).unwrap();
+        assert!(matches!(
+            solve(10, Acceleration::None),
+            Err(DespoticError::NoConvergence(_))
+        ));
+
+        // Given enough iterations both reach the same state
+        let plain = solve(40, Acceleration::None).unwrap();
+        assert!(((plain - Tg) / Tg).abs() < 1.0e-3, "Tg {plain} vs {Tg}");
+    }
+}
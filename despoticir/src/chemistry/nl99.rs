@@ -0,0 +1,154 @@
+use super::{co_shielding, cr_induced_photo_rate, ChemNetwork};
+use crate::cloud::Cloud;
+use crate::emitter::Abundance;
+
+/// Species evolved by the network
+const SPECIES: [&str; 10] = [
+    "He+", "H3+", "OHx", "CHx", "CO", "C", "C+", "HCO+", "O", "M+",
+];
+const HEP: usize = 0;
+const H3P: usize = 1;
+const OHX: usize = 2;
+const CHX: usize = 3;
+const CO: usize = 4;
+const C: usize = 5;
+const CP: usize = 6;
+const HCOP: usize = 7;
+const O: usize = 8;
+const MP: usize = 9;
+
+/// The reduced carbon-oxygen network of Nelson & Langer (1999)
+///
+/// CHx and OHx stand for all hydrocarbons and oxygen hydrides, and M
+/// for the low ionization potential metals. Neutral He and neutral
+/// metals follow from conservation, electrons from charge neutrality,
+/// and the H2 abundance is taken from the cloud's composition.
+#[derive(Debug, Clone)]
+pub struct NL99 {
+    /// total abundance of C nuclei per H nucleus
+    pub xC: f64,
+    /// total abundance of O nuclei per H nucleus
+    pub xO: f64,
+    /// total abundance of low ionization potential metals per H
+    /// nucleus
+    pub xM: f64,
+    /// if true, CO is also photodissociated by cosmic-ray-induced
+    /// UV photons; on by default
+    pub cr_photo: bool,
+    /// current abundances, in the order of species()
+    pub x: Vec<f32>,
+}
+
+impl NL99 {
+    /// Create a network for a cloud, with elemental abundances scaled
+    /// by the cloud's dust abundance
+    ///
+    /// Parameters
+    ///    cloud : Cloud
+    ///       the cloud to which the network will be attached
+    ///
+    /// Returns
+    ///    a network with all carbon in C+, all oxygen in O, and all
+    ///    metals in M+
+    pub fn new(cloud: &Cloud) -> Self {
+        let Zd = cloud.dust.Zd as f64;
+        let (xC, xO, xM) = (2.0e-4 * Zd, 4.0e-4 * Zd, 2.0e-7 * Zd);
+        let mut x = vec![0.0; SPECIES.len()];
+        x[CP] = xC as f32;
+        x[O] = xO as f32;
+        x[MP] = xM as f32;
+        Self {
+            xC,
+            xO,
+            xM,
+            cr_photo: true,
+            x,
+        }
+    }
+}
+
+impl ChemNetwork for NL99 {
+    fn species(&self) -> &[&str] {
+        &SPECIES
+    }
+
+    fn abundances(&self) -> &[f32] {
+        &self.x
+    }
+
+    fn abundances_mut(&mut self) -> &mut [f32] {
+        &mut self.x
+    }
+
+    fn dxdt(&self, x: &[f64], cloud: &Cloud) -> Vec<f64> {
+        let T = cloud.Tg as f64;
+        let nH = cloud.nH as f64;
+        let xH2 = cloud.comp.xH2() as f64;
+        let xHe = (cloud.comp.xHe as f64 - x[HEP]).max(0.0);
+        let xM = (self.xM - x[MP]).max(0.0);
+        let xe = x[HEP] + x[H3P] + x[CP] + x[HCOP] + x[MP];
+        let zeta = cloud.rad.cr_rates();
+        let chi = cloud.rad.chi as f64;
+        let colDen = cloud.colDen as f64;
+        let AV = cloud.a_v();
+        let photo = |k0: f64, gamma: f64| k0 * chi * (-gamma * AV).exp();
+        let fShieldCO = co_shielding(x[CO] * colDen, xH2 * colDen);
+
+        // Cosmic ray ionization
+        let r1 = zeta.H2 * xH2; // H2 + cr -> H3+ + e
+        let r2 = zeta.He * xHe; // He + cr -> He+ + e
+
+        // Two-body reactions
+        let r3 = 2.0e-9 * nH * x[H3P] * x[C]; // H3+ + C -> CHx + H2
+        let r4 = 8.0e-10 * nH * x[H3P] * x[O]; // H3+ + O -> OHx + H2
+        let r5 = 1.7e-9 * nH * x[H3P] * x[CO]; // H3+ + CO -> HCO+ + H2
+        let r6 = 7.0e-15 * nH * x[HEP] * xH2; // He+ + H2 -> He + H + H+
+        let r7 = 1.6e-9 * nH * x[HEP] * x[CO]; // He+ + CO -> C+ + O + He
+        let r8 = 4.0e-16 * nH * x[CP] * xH2; // C+ + H2 -> CHx + H
+        let r9 = 1.0e-9 * nH * x[CP] * x[OHX]; // C+ + OHx -> HCO+
+        let r10 = 2.0e-10 * nH * x[O] * x[CHX]; // O + CHx -> CO + H
+        let r11 = 5.8e-12 * T.sqrt() * nH * x[C] * x[OHX]; // C + OHx -> CO + H
+        let r12 = 9.0e-11 * T.powf(-0.64) * nH * x[HEP] * xe; // He+ + e -> He
+        let r13 = 1.9e-6 * T.powf(-0.54) * nH * x[H3P] * xe; // H3+ + e -> H + H2
+        let r14 = 1.4e-10 * T.powf(-0.61) * nH * x[CP] * xe; // C+ + e -> C
+        let r15 = 3.3e-5 * T.powi(-1) * nH * x[HCOP] * xe; // HCO+ + e -> CO + H
+        let r16 = 3.8e-10 * T.powf(-0.65) * nH * x[MP] * xe; // M+ + e -> M
+        let r17 = 2.0e-9 * nH * x[H3P] * xM; // H3+ + M -> M+ + H + H2
+
+        // Photoreactions
+        let r18 = photo(3.0e-10, 3.0) * x[C]; // C + gamma -> C+ + e
+        let r19 = photo(1.0e-9, 1.5) * x[CHX]; // CHx + gamma -> C + H
+        let r20 = photo(1.0e-10, 3.0) * fShieldCO * x[CO]; // CO + gamma -> C + O
+        let r21 = photo(5.0e-10, 1.7) * x[OHX]; // OHx + gamma -> O + H
+        let r22 = photo(2.0e-10, 1.9) * xM; // M + gamma -> M+ + e
+        let r23 = photo(1.5e-10, 2.5) * x[HCOP]; // HCO+ + gamma -> CO + H+
+
+        // Photodissociation by UV photons generated by cosmic rays
+        let r24 = if self.cr_photo {
+            cr_induced_photo_rate(cloud.rad.ionRate as f64, xH2) * x[CO] // CO + cr gamma -> C + O
+        } else {
+            0.0
+        };
+
+        let mut dx = vec![0.0; SPECIES.len()];
+        dx[HEP] = r2 - r6 - r7 - r12;
+        dx[H3P] = r1 - r3 - r4 - r5 - r13 - r17;
+        dx[OHX] = r4 - r9 - r11 - r21;
+        dx[CHX] = r3 + r8 - r10 - r19;
+        dx[CO] = -r5 - r7 + r10 + r11 + r15 - r20 + r23 - r24;
+        dx[C] = -r3 - r11 + r14 - r18 + r19 + r20 + r24;
+        dx[CP] = r7 - r8 - r9 - r14 + r18;
+        dx[HCOP] = r5 + r9 - r15 - r23;
+        dx[O] = -r4 + r7 - r10 + r20 + r21 + r24;
+        dx[MP] = r17 + r22 - r16;
+        dx
+    }
+
+    fn apply_abundances(&self, cloud: &mut Cloud) {
+        for (name, em) in cloud.emitters.iter_mut() {
+            if let Some(i) = SPECIES.iter().position(|s| s.eq_ignore_ascii_case(name)) {
+                em.abundance = Abundance::Constant(self.x[i]);
+            }
+        }
+    }
+}
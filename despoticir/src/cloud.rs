@@ -1,5 +1,81 @@
-use crate::{ChemNetwork, Composition, DustProp, Emitter, Radiation};
-use std::collections::HashMap;
+use crate::consts::{mH, pc, Msun};
+use crate::dust::DUST_TO_GAS_MW;
+use crate::emitter::Abundance;
+use crate::energy::{CoolingMode, CustomRate};
+use crate::memo::PopMemo;
+use crate::parse::parse_float;
+use crate::radiation::Isrf;
+use crate::units::{ColumnDensity, NumberDensity, Temperature};
+use crate::{ChemNetwork, Composition, DespoticError, DustProp, Emitter, Radiation};
+use std::collections::{HashMap, HashSet};
+use std::io::BufRead;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// How a cloud file keyword that appears more than once is treated
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DuplicatePolicy {
+    /// the last value given is used, as in the Python DESPOTIC
+    #[default]
+    LastWins,
+    /// the first value given is used and later ones are ignored
+    FirstWins,
+    /// a repeated keyword is an error
+    Error,
+}
+
+/// Options controlling how a cloud description is read
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ReadOptions {
+    /// how to treat a keyword that appears more than once
+    pub duplicates: DuplicatePolicy,
+    /// if true, the description may omit nH and the hydrogen
+    /// composition, which are otherwise required
+    pub allow_partial: bool,
+}
+
+/// State shared between a cloud file and the files it includes
+#[derive(Default)]
+struct ParseState {
+    /// every keyword given so far, in any file
+    keys: HashSet<String>,
+    /// surface density given by SIGMA, in Msun pc^-2; converting it
+    /// to colDen needs muH, so this is deferred until the composition
+    /// is known
+    surfDen: Option<f64>,
+    /// canonical paths of the files being read, outermost first
+    stack: Vec<PathBuf>,
+}
+
+/// Open a cloud file, looking first relative to dir (or the working
+/// directory if dir is None), then in the data directory
+fn open_cloud_file(
+    file_name: &Path,
+    dir: Option<&Path>,
+) -> Result<(std::fs::File, PathBuf), DespoticError> {
+    let local = dir.map_or_else(|| file_name.to_path_buf(), |d| d.join(file_name));
+    if let Ok(fp) = std::fs::File::open(&local) {
+        return Ok((fp, local));
+    }
+    let path = crate::data_dir().join(file_name);
+    let fp = std::fs::File::open(&path).map_err(|e| {
+        std::io::Error::new(
+            e.kind(),
+            format!("cannot open file {}: {e}", file_name.display()),
+        )
+    })?;
+    Ok((fp, path))
+}
+
+/// Properties of a cloud as read from its file, restored by reset
+#[derive(Debug, Clone)]
+struct InitialState {
+    Tg: f32,
+    Td: f32,
+    comp: Composition,
+    abundances: HashMap<String, Abundance>,
+}
 
 #[derive(Debug)]
 pub struct Cloud {
@@ -15,6 +91,8 @@ pub struct Cloud {
     pub Tg: f32,
     /// dust temperature, in K
     pub Td: f32,
+    /// magnetic field strength, in G; None for an unmagnetized cloud
+    pub b_field: Option<f32>,
     /// a class that stores information about the chemical
     /// composition of the cloud
     pub comp: Composition,
@@ -29,10 +107,128 @@ pub struct Cloud {
     /// a chemical network that is to be used to perform
     /// time-dependent chemical evolution calcualtions for this
     /// cloud
-    pub chemnetwork: Option<ChemNetwork>,
+    pub chemnetwork: Option<Box<dyn ChemNetwork>>,
     /// if set to True, warning messages about convergence of
     /// emitters attached to this cloud are suppressed
     pub noWarn: bool,
+    /// user-supplied heating and cooling terms for the gas
+    pub customRates: Vec<CustomRate>,
+    /// how level populations are found when computing line cooling
+    pub coolingMode: CoolingMode,
+    /// state of the cloud when it was last read
+    initialState: Option<InitialState>,
+    /// set when a property has been changed through a setter since
+    /// the level populations were last solved
+    dirty: AtomicBool,
+}
+
+/// Builder for a Cloud whose properties are set in code rather than
+/// read from a file
+///
+/// Setters for densities and temperatures accept the typed quantities
+/// of the units module as well as plain numbers in CGS units.
+#[derive(Debug)]
+pub struct CloudBuilder {
+    cloud: Cloud,
+}
+
+impl Default for CloudBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CloudBuilder {
+    /// Start from a cloud with every property zero or at its default
+    pub fn new() -> Self {
+        Self {
+            cloud: Cloud::blank(false),
+        }
+    }
+
+    /// Set the number density of H nuclei
+    pub fn nH(mut self, nH: impl Into<NumberDensity>) -> Self {
+        self.cloud.nH = nH.into().value() as f32;
+        self
+    }
+
+    /// Set the center-to-edge column density of H nuclei
+    pub fn colDen(mut self, colDen: impl Into<ColumnDensity>) -> Self {
+        self.cloud.colDen = colDen.into().value() as f32;
+        self
+    }
+
+    /// Set the non-thermal velocity dispersion, in cm s^-1
+    pub fn sigmaNT(mut self, sigmaNT: f32) -> Self {
+        self.cloud.sigmaNT = sigmaNT;
+        self
+    }
+
+    /// Set the radial velocity gradient, in s^-1
+    pub fn dVdr(mut self, dVdr: f32) -> Self {
+        self.cloud.dVdr = dVdr;
+        self
+    }
+
+    /// Set the gas kinetic temperature
+    pub fn Tg(mut self, Tg: impl Into<Temperature>) -> Self {
+        self.cloud.Tg = Tg.into().value() as f32;
+        self
+    }
+
+    /// Set the dust temperature
+    pub fn Td(mut self, Td: impl Into<Temperature>) -> Self {
+        self.cloud.Td = Td.into().value() as f32;
+        self
+    }
+
+    /// Set the magnetic field strength, in G
+    pub fn b_field(mut self, b_field: f32) -> Self {
+        self.cloud.b_field = Some(b_field);
+        self
+    }
+
+    /// Set the chemical composition
+    pub fn comp(mut self, comp: Composition) -> Self {
+        self.cloud.comp = comp;
+        self
+    }
+
+    /// Set the dust properties
+    pub fn dust(mut self, dust: DustProp) -> Self {
+        self.cloud.dust = dust;
+        self
+    }
+
+    /// Set the radiation field
+    pub fn rad(mut self, rad: Radiation) -> Self {
+        self.cloud.rad = rad;
+        self
+    }
+
+    /// Suppress warnings about convergence
+    pub fn noWarn(mut self, noWarn: bool) -> Self {
+        self.cloud.noWarn = noWarn;
+        self
+    }
+
+    /// Set how level populations are found when computing line
+    /// cooling
+    pub fn coolingMode(mut self, coolingMode: CoolingMode) -> Self {
+        self.cloud.coolingMode = coolingMode;
+        self
+    }
+
+    /// Finish building, computing the quantities derived from the
+    /// composition
+    pub fn build(self) -> Cloud {
+        let mut cloud = self.cloud;
+        cloud.comp.compute_derived(cloud.nH);
+        if cloud.Tg > 0.0 {
+            cloud.comp.compute_cv(cloud.Tg);
+        }
+        cloud
+    }
 }
 
 /// Parameters
@@ -48,43 +244,115 @@ impl Cloud {
         file_name: Option<impl AsRef<std::path::Path>>,
         noWarn: bool,
         verbose: bool,
-    ) -> Self {
-        match file_name {
-            None => Self {
-                nH: 0.,
-                colDen: 0.,
-                sigmaNT: 0.,
-                dVdr: 0.,
-                Tg: 0.,
-                Td: 0.,
-                comp: Composition,
-                dust: DustProp,
-                rad: Radiation,
-                emitters: HashMap::new(),
-                chemnetwork: None,
-                noWarn,
-            },
-            Some(file_name) => {
-                let mut res = Self {
-                    nH: 0.,
-                    colDen: 0.,
-                    sigmaNT: 0.,
-                    dVdr: 0.,
-                    Tg: 0.,
-                    Td: 0.,
-                    comp: Composition,
-                    dust: DustProp,
-                    rad: Radiation,
-                    emitters: HashMap::new(),
-                    chemnetwork: None,
-                    noWarn,
-                };
-                res.read(file_name, verbose);
-                res
-            }
+    ) -> Result<Self, DespoticError> {
+        let mut res = Self::blank(noWarn);
+        if let Some(file_name) = file_name {
+            res.read(file_name, verbose)?;
+        }
+        Ok(res)
+    }
+
+    /// A cloud with every property zero or at its default
+    fn blank(noWarn: bool) -> Self {
+        Self {
+            nH: 0.,
+            colDen: 0.,
+            sigmaNT: 0.,
+            dVdr: 0.,
+            Tg: 0.,
+            Td: 0.,
+            b_field: None,
+            comp: Composition::default(),
+            dust: DustProp::default(),
+            rad: Radiation::default(),
+            emitters: HashMap::new(),
+            chemnetwork: None,
+            noWarn,
+            customRates: Vec::new(),
+            coolingMode: CoolingMode::default(),
+            initialState: None,
+            dirty: AtomicBool::new(false),
+        }
+    }
+
+    /// Copy of the cloud's properties
+    ///
+    /// Remarks
+    ///    Everything is copied except the attached chemical network
+    ///    and the custom rates, which cannot be copied; the copy has
+    ///    none
+    pub(crate) fn duplicate(&self) -> Self {
+        Self {
+            nH: self.nH,
+            colDen: self.colDen,
+            sigmaNT: self.sigmaNT,
+            dVdr: self.dVdr,
+            Tg: self.Tg,
+            Td: self.Td,
+            b_field: self.b_field,
+            comp: self.comp.clone(),
+            dust: self.dust.clone(),
+            rad: self.rad.clone(),
+            emitters: self.emitters.clone(),
+            chemnetwork: None,
+            noWarn: self.noWarn,
+            customRates: Vec::new(),
+            coolingMode: self.coolingMode,
+            initialState: self.initialState.clone(),
+            dirty: AtomicBool::new(self.is_dirty()),
         }
     }
 
+    /// Start building a cloud from scratch rather than from a file
+    pub fn builder() -> CloudBuilder {
+        CloudBuilder::new()
+    }
+
+    /// A molecular cloud with the given emitters, for quick line
+    /// predictions
+    ///
+    /// Parameters
+    ///    nH : float
+    ///       number density of H nuclei, in cm^-3
+    ///    tg : float
+    ///       gas kinetic temperature, in K; the dust temperature is
+    ///       set to the same value
+    ///    species_abundances : list of (string, float)
+    ///       name and abundance per H nucleus of each emitter
+    ///
+    /// Returns
+    ///    the cloud
+    ///
+    /// Remarks
+    ///    The hydrogen is entirely H2 with an ortho-to-para ratio of
+    ///    0.25, and xHe = 0.1. The column density is 1e22 cm^-2 and
+    ///    the non-thermal velocity dispersion 1 km s^-1. Dust and
+    ///    radiation take their defaults. Each emitter's data are read
+    ///    from its LAMDA file, as for an EMITTER line in a cloud file,
+    ///    and an error is returned if one cannot be found.
+    pub fn simple(
+        nH: f64,
+        tg: f64,
+        species_abundances: &[(&str, f64)],
+    ) -> Result<Cloud, DespoticError> {
+        let mut comp = Composition::default();
+        comp.set_H2OPR(0.25);
+        comp.set_xH2(0.5);
+        comp.xHe = 0.1;
+        let mut cloud = Cloud::builder()
+            .nH(nH)
+            .colDen(1.0e22)
+            .sigmaNT(1.0e5)
+            .Tg(tg)
+            .Td(tg)
+            .comp(comp)
+            .build();
+        for &(name, abundance) in species_abundances {
+            cloud.add_emitter(name, abundance as f32, false, true, None, None)?;
+        }
+        Ok(cloud)
+    }
+
     /// Read the composition from a file
     ///
     /// Pamameters
@@ -98,317 +366,1462 @@ impl Cloud {
     ///
     /// Remarks
     ///    For the format of cloud files, see the documentation
-    pub fn read(&mut self, file_name: impl AsRef<std::path::Path>, verbose: bool) {
-
-        // # Read file
-        // try:
-        //     # First look for the file locally
-        //     try:
-        //         fp = open(fileName, 'r')
-        //     except IOError:
-        //         # Look for file in despotic directory
-        //         import os.path
-        //         # Hack to compute the path to the installed module
-        //         # root, so we can load files even when installed
-        //         module_dir = os.path.dirname(os.path.realpath(__file__))
-        //         fp = open(os.path.join(module_dir, fileName), 'r')
-        //         if verbose:
-        //             print("Reading from file "+fileName+"...")
-        // except IOError:
-        //     raise despoticError("cannot open file "+fileName)
-        // for line in fp:
-        //
-        //     # Skip empty and comment lines
-        //     if line=='\n':
-        //         continue
-        //     if line.strip()[0] == "#":
-        //         continue
-        //
-        //     # Break line up based on equal sign
-        //     linesplit = line.split("=")
-        //     if len(linesplit) < 2:
-        //         raise despoticError("Error parsing input line: "+line)
-        //     if linesplit[1] == '':
-        //         raise despoticError("Error parsing input line: "+line)
-        //
-        //     # Trim trailing comments from portion after equal sign
-        //     linesplit2 = linesplit[1].split('#')
-        //
-        //     # Proceed based on the token that precedes the equal sign
-        //     if linesplit[0].upper().strip() == 'NH':
-        //
-        //         self.nH = float(linesplit2[0])
-        //         if verbose:
-        //             print("Setting nH = "+str(self.nH))
-        //
-        //     elif linesplit[0].upper().strip() == 'COLDEN':
-        //
-        //         self.colDen = float(linesplit2[0])
-        //         if verbose:
-        //             print("Setting column density = " +
-        //                   str(self.colDen) + " H cm^-2")
-        //
-        //     elif linesplit[0].upper().strip() == 'SIGMANT':
-        //
-        //         self.sigmaNT = float(linesplit2[0])
-        //         if verbose:
-        //             print("Setting sigmaNT = " +
-        //                   str(self.sigmaNT) + " cm s^-1")
-        //
-        //     elif linesplit[0].upper().strip() == 'DVDR':
-        //
-        //         self.dVdr = float(linesplit2[0])
-        //         if verbose:
-        //             print("Setting dVdr = " +
-        //                   str(self.dVdr) + " cm s^-1 cm^-1")
-        //
-        //     elif linesplit[0].upper().strip() == 'TG':
-        //
-        //         self.Tg = float(linesplit2[0])
-        //         if verbose:
-        //             print("Setting Tg = "+str(self.Tg) + " K")
-        //
-        //     elif linesplit[0].upper().strip() == 'TD':
-        //
-        //         self.Td = float(linesplit2[0])
-        //         if verbose:
-        //             print("Setting Td = "+str(self.Td) + " K")
-        //
-        //     elif linesplit[0].upper().strip() == 'ALPHAGD':
-        //
-        //         self.dust.alphaGD = float(linesplit2[0])
-        //         if verbose:
-        //             print("Setting alpha_GD = " +
-        //                   str(self.dust.alphaGD) +
-        //                   " erg cm^3 K^-3/2")
-        //
-        //     elif linesplit[0].upper().strip() == 'SIGMAD10':
-        //
-        //         self.dust.sigma10 = float(linesplit2[0])
-        //         if verbose:
-        //             print("Setting sigma_d,10 = " +
-        //                   str(self.dust.sigma10) +
-        //                   " cm^2 g^-1")
-        //
-        //     elif linesplit[0].upper().strip() == 'SIGMADPE':
-        //
-        //         self.dust.sigmaPE = float(linesplit2[0])
-        //         if verbose:
-        //             print("Setting sigma_d,PE = " +
-        //                   str(self.dust.sigmaPE) +
-        //                   " cm^2 H^-1")
-        //
-        //     elif linesplit[0].upper().strip() == 'SIGMADISRF':
-        //
-        //         self.dust.sigmaISRF = float(linesplit2[0])
-        //         if verbose:
-        //             print("Setting sigma_d,ISRF = " +
-        //                   str(self.dust.sigmaISRF) +
-        //                   " cm^2 H^-1")
-        //
-        //     elif linesplit[0].upper().strip() == 'ZDUST':
-        //
-        //         self.dust.Zd = float(linesplit2[0])
-        //         if verbose:
-        //             print("Setting Z'_d = " +
-        //                   str(self.dust.Zd))
-        //
-        //     elif linesplit[0].upper().strip() == 'BETADUST':
-        //
-        //         self.dust.beta = float(linesplit2[0])
-        //         if verbose:
-        //             print("Setting beta_dust = "+str(self.dust.beta))
-        //
-        //     elif linesplit[0].upper().strip() == 'XHI':
-        //
-        //         self.comp.xHI = float(linesplit2[0])
-        //         if verbose:
-        //             print("Setting xHI = "+str(self.comp.xHI))
-        //
-        //     elif linesplit[0].upper().strip() == 'XPH2':
-        //
-        //         self.comp.xpH2 = float(linesplit2[0])
-        //         if verbose:
-        //             print("Setting xpH2 = "+str(self.comp.xpH2))
-        //
-        //     elif linesplit[0].upper().strip() == 'XOH2':
-        //
-        //         self.comp.xoH2 = float(linesplit2[0])
-        //         if verbose:
-        //             print("Setting xoH2 = "+str(self.comp.xoH2))
-        //
-        //     elif linesplit[0].upper().strip() == 'H2OPR':
-        //
-        //         self.comp.H2OPR = float(linesplit2[0])
-        //         if verbose:
-        //             print("Setting H2 ortho-para ratio = "+
-        //                   str(self.comp.H2OPR))
-        //
-        //     elif linesplit[0].upper().strip() == 'XH2':
-        //
-        //         self.comp.xH2 = float(linesplit2[0])
-        //         if self.comp.H2OPR is None:
-        //             self.comp.H2OPR = 0.25
-        //             print("Warning: H2 OPR unspecified, assuming 0.25")
-        //         if verbose:
-        //             print("Setting xpH2 = "+str(self.comp.xpH2))
-        //             print("Setting xoH2 = "+str(self.comp.xoH2))
-        //
-        //     elif linesplit[0].upper().strip() == 'XHE':
-        //
-        //         self.comp.xHe = float(linesplit2[0])
-        //         if verbose:
-        //             print("Setting xHe = "+str(self.comp.xHe))
-        //
-        //     elif linesplit[0].upper().strip() == 'XE':
-        //
-        //         self.comp.xe = float(linesplit2[0])
-        //         if verbose:
-        //             print("Setting xe = "+str(self.comp.xe))
-        //
-        //     elif linesplit[0].upper().strip() == 'XH+':
-        //
-        //         self.comp.xe = float(linesplit2[0])
-        //         if verbose:
-        //             print("Setting xH+ = "+str(self.comp.xe))
-        //
-        //     elif linesplit[0].upper().strip() == 'TCMB':
-        //
-        //         self.rad.TCMB = float(linesplit2[0])
-        //         if verbose:
-        //             print("Setting T_CMB = "+str(self.rad.TCMB)+" K")
-        //
-        //     elif linesplit[0].upper().strip() == 'TRADDUST':
-        //
-        //         self.rad.TradDust = float(linesplit2[0])
-        //         if verbose:
-        //             print("Setting T_radDust = " +
-        //                   str(self.rad.TradDust)+" K")
-        //
-        //     elif linesplit[0].upper().strip() == 'RADDUTDILUTION':
-        //
-        //         self.rad.fdDilute = float(linesplit2[0])
-        //         if verbose:
-        //             print("Setting radDust dilution factor = " +
-        //                   str(self.rad.fdDilute))
-        //
-        //     elif linesplit[0].upper().strip() == 'IONRATE':
-        //
-        //         self.rad.ionRate = float(linesplit2[0])
-        //         if verbose:
-        //             print("Setting primary ionization rate = " +
-        //                   str(self.rad.ionRate)+" s^-1 H^-1")
-        //
-        //     elif linesplit[0].upper().strip() == 'CHI':
-        //
-        //         self.rad.chi = float(linesplit2[0])
-        //         if verbose:
-        //             print("Setting chi = " +
-        //                   str(self.rad.chi))
-        //
-        //     elif linesplit[0].upper().strip() == 'EMITTER':
-        //
-        //         # Emitter lines are complicated. There are two
-        //         # required elements, a name and an abundance, that
-        //         # must come first. There are also four optional
-        //         # elements: energySkip, noExtrap, file:FileName,
-        //         # and URL:url
-        //
-        //         # Split up the tokens after the equal sign
-        //         linesplit3 = linesplit2[0].split()
-        //
-        //         # Make sure the number of tokens is acceptable
-        //         if len(linesplit3) < 2 or len(linesplit3) > 6:
-        //             raise despoticError("Error parsing input line: "+line)
-        //
-        //         # Do we have optional tokens?
-        //         if len(linesplit3) == 2:
-        //
-        //             # Handle case of just two tokens
-        //             if verbose:
-        //                 print("Adding emitter "+linesplit3[0]+
-        //                       " with abundance "+linesplit3[1])
-        //             self.addEmitter(linesplit3[0], \
-        //                                 float(linesplit3[1]))
-        //
-        //         else:
-        //
-        //             # We have optional tokens; initialize various
-        //             # options to their defaults, then alter them based
-        //             # on the tokens we've been given
-        //             energySkip=False
-        //             extrap=True
-        //             emitterFile=None
-        //             emitterURL=None
-        //             for token in linesplit3[2:]:
-        //                 if token.upper().strip() == 'ENERGYSKIP':
-        //                     energySkip=True
-        //                 elif token.upper().strip() == 'EXTRAPOLATE':
-        //                     # Allowed to maintain backward compatibility
-        //                     pass
-        //                 elif token.upper().strip() == 'NOEXTRAP':
-        //                     extrap=False
-        //                 elif token.upper().strip()[0:5] == 'FILE:':
-        //                     emitterFile=token[5:].strip()
-        //                 elif token.upper().strip()[0:4] == 'URL:':
-        //                     emitterURL=token[4:].strip()
-        //                 else:
-        //                     raise despoticError(
-        //                         'unrecognized token "' +
-        //                         token.strip()+'" in line: '
-        //                         + line)
-        //
-        //             # Now print message and add emitter
-        //             if verbose:
-        //                 msg = "Adding emitter "+linesplit3[0]+ \
-        //                           " with abundance "+linesplit3[1]
-        //                 if energySkip:
-        //                     msg += "; setting energySkip"
-        //                 if not extrap:
-        //                     msg += "; disallowing extrapolation"
-        //                 if emitterFile != None:
-        //                     msg += "; using file name "+emitterFile
-        //                 if emitterURL != None:
-        //                     msg += "; using URL "+emitterURL
-        //                 print(msg)
-        //             self.addEmitter(linesplit3[0],
-        //                             float(linesplit3[1]),
-        //                             energySkip=energySkip,
-        //                             extrap=extrap,
-        //                             emitterFile=emitterFile,
-        //                             emitterURL=emitterURL)
-        //
-        //     else:
-        //         # Line does not correspond to any known keyword, so
-        //         # throw an error
-        //         raise despoticError("unrecognized token " +
-        //             linesplit[0].strip() + " in file " + fileName)
-        //
-        // # Close file
-        // fp.close()
-        //
-        // # Check that the hydrogen adds up. If not, raise error
-        // if self.comp.xHI + self.comp.xHplus + \
-        //         2.0*(self.comp.xpH2 + self.comp.xoH2) != 1:
-        //     raise despoticError(
-        //         "total hydrogen abundance xHI + xH+ + 2 xH2 != 1")
-        //
-        // # Set derived properties based on composition, temperature
-        // self.comp.computeDerived(self.nH)
-        // if self.Tg > 0.0:
-        //     self.comp.computeCv(self.Tg)
-        //
-        // # If verbose, print results for derived quantities
-        // if verbose:
-        //     print("Derived quantities:")
-        //     print("   ===> mean mass per particle = " +
-        //           str(self.comp.mu) + " mH")
-        //     print("   ===> mean mass per H = " +
-        //           str(self.comp.muH) + " mH")
-        //     print("   ===> energy added per ionization = " +
-        //           str(self.comp.qIon/1.6e-12) + " eV")
-        //     if self.Tg > 0.0:
-        //         print("   ===> c_v/(k_B n_H mu_H) = " +
-        //               str(self.comp.cv))
+    pub fn read(
+        &mut self,
+        file_name: impl AsRef<std::path::Path>,
+        verbose: bool,
+    ) -> Result<(), DespoticError> {
+        self.read_with(file_name, verbose, &ReadOptions::default())
+    }
+
+    /// Read the composition from a file, with options
+    ///
+    /// Parameters
+    ///    fileName : string
+    ///       string giving the name of the composition file
+    ///    verbose : Boolean
+    ///       print out information about the cloud as it is read
+    ///    opts : ReadOptions
+    ///       options controlling how the file is read
+    ///
+    /// Returns
+    ///    Nothing
+    ///
+    /// Remarks
+    ///    Paths named by INCLUDE lines are taken relative to the
+    ///    directory of the file that includes them
+    pub fn read_with(
+        &mut self,
+        file_name: impl AsRef<std::path::Path>,
+        verbose: bool,
+        opts: &ReadOptions,
+    ) -> Result<(), DespoticError> {
+        let file_name = file_name.as_ref();
+
+        // First look for the file locally, then in the data directory
+        let (fp, path) = open_cloud_file(file_name, None)?;
+        if verbose {
+            println!("Reading from file {}...", file_name.display());
+        }
+        let mut state = ParseState::default();
+        state.stack.push(path.canonicalize()?);
+        let dir = path.parent().map(Path::to_path_buf);
+        self.parse_lines(
+            std::io::BufReader::new(fp),
+            verbose,
+            opts,
+            &mut state,
+            dir.as_deref(),
+        )?;
+        self.finish_read(state, verbose, opts)
+    }
+
+    /// Read a cloud description from any buffered reader
+    ///
+    /// Parameters
+    ///    reader : BufRead
+    ///       source of the cloud description, in cloud file format
+    ///    verbose : Boolean
+    ///       print out information about the cloud as it is read
+    ///    opts : ReadOptions
+    ///       options controlling how the input is read
+    ///
+    /// Returns
+    ///    Nothing
+    ///
+    /// Remarks
+    ///    The input is consumed one line at a time. Line numbers
+    ///    reported in errors are the 1-based physical line numbers of
+    ///    the input, counting blank and comment lines. EMITTER lines
+    ///    are never treated as duplicates, since each adds a species.
+    ///    Unless opts.allow_partial is set, a MissingField error is
+    ///    returned if the input does not set nH or any hydrogen
+    ///    abundance.
+    ///
+    ///    A line INCLUDE = path reads the named cloud file in place
+    ///    of that line, so a file that includes a base file first can
+    ///    override any of its properties; a keyword given in both is
+    ///    not a duplicate. Here paths are taken relative to the
+    ///    working directory, falling back to the data directory.
+    ///    Line numbers in errors from an included file refer to that
+    ///    file. A file that includes itself, directly or indirectly,
+    ///    is an IncludeCycle error.
+    pub fn read_from(
+        &mut self,
+        reader: impl BufRead,
+        verbose: bool,
+        opts: &ReadOptions,
+    ) -> Result<(), DespoticError> {
+        let mut state = ParseState::default();
+        self.parse_lines(reader, verbose, opts, &mut state, None)?;
+        self.finish_read(state, verbose, opts)
+    }
+
+    /// Override properties of a cloud that has been read
+    ///
+    /// Parameters
+    ///    overrides : list of (string, string)
+    ///       keyword and value pairs, written as they would be on
+    ///       either side of the equal sign in a cloud file
+    ///
+    /// Returns
+    ///    Nothing
+    ///
+    /// Remarks
+    ///    Each pair is applied as if it were a line appended to the
+    ///    cloud file, in order, so a front end can turn an option
+    ///    such as --set nH=1e4 into the pair ("nH", "1e4"). Line
+    ///    numbers in errors are 1-based positions in overrides. Derived
+    ///    quantities are recomputed, and the composition is checked if
+    ///    any hydrogen abundance is overridden. The overridden cloud
+    ///    becomes the state that reset restores, and the cloud is
+    ///    marked dirty. If any pair fails, the error is returned and
+    ///    the cloud is left as it was.
+    pub fn apply_overrides(&mut self, overrides: &[(&str, &str)]) -> Result<(), DespoticError> {
+        let mut text = String::new();
+        for (idx, (key, value)) in overrides.iter().enumerate() {
+            let line = format!("{key} = {value}");
+            if key.contains('=') || line.contains(['\n', '\r']) {
+                return Err(DespoticError::ParseLine {
+                    line_number: idx + 1,
+                    line,
+                });
+            }
+            text += &line;
+            text.push('\n');
+        }
+        let opts = ReadOptions {
+            allow_partial: true,
+            ..ReadOptions::default()
+        };
+        // Apply the pairs to a copy, so that a failure part way
+        // through leaves the cloud unchanged
+        let mut trial = self.duplicate();
+        let mut state = ParseState::default();
+        trial.parse_lines(text.as_bytes(), false, &opts, &mut state, None)?;
+        trial.finish_read(state, false, &opts)?;
+        trial.chemnetwork = self.chemnetwork.take();
+        trial.customRates = std::mem::take(&mut self.customRates);
+        *self = trial;
+        self.mark_dirty();
+        Ok(())
+    }
+
+    /// Apply the lines of one cloud file, and of any files it
+    /// includes, to the cloud
+    fn parse_lines(
+        &mut self,
+        reader: impl BufRead,
+        verbose: bool,
+        opts: &ReadOptions,
+        state: &mut ParseState,
+        dir: Option<&Path>,
+    ) -> Result<(), DespoticError> {
+        let mut seen: HashMap<String, usize> = HashMap::new();
+        for (idx, line) in reader.lines().enumerate() {
+            let line = line?;
+            let line_number = idx + 1;
+            let parse_error = || DespoticError::ParseLine {
+                line_number,
+                line: line.clone(),
+            };
+
+            // Skip empty and comment lines
+            let trimmed = line.trim();
+            if trimmed.is_empty() || trimmed.starts_with('#') {
+                continue;
+            }
+
+            // Break line up based on equal sign, and trim trailing
+            // comments from the portion after it
+            let (key, rest) = line.split_once('=').ok_or_else(parse_error)?;
+            let value = rest.split('#').next().unwrap_or("").trim();
+            if value.is_empty() {
+                return Err(parse_error());
+            }
+            let float = || {
+                parse_float(value)
+                    .map(|v| v as f32)
+                    .map_err(|_| parse_error())
+            };
+
+            // Check whether this keyword has been set before
+            let keyword = key.trim().to_uppercase();
+            state.keys.insert(keyword.clone());
+            if keyword != "EMITTER" && keyword != "INCLUDE" {
+                if let Some(&first_line) = seen.get(&keyword) {
+                    match opts.duplicates {
+                        DuplicatePolicy::LastWins => {}
+                        DuplicatePolicy::FirstWins => {
+                            if verbose {
+                                println!(
+                                    "Ignoring {} on line {line_number}, already set on line {first_line}",
+                                    key.trim()
+                                );
+                            }
+                            continue;
+                        }
+                        DuplicatePolicy::Error => {
+                            return Err(DespoticError::DuplicateKeyword {
+                                line_number,
+                                keyword: key.trim().to_string(),
+                                first_line,
+                            });
+                        }
+                    }
+                } else {
+                    seen.insert(keyword.clone(), line_number);
+                }
+            }
+
+            // Proceed based on the token that precedes the equal sign
+            match keyword.as_str() {
+                "NH" => {
+                    self.nH = float()?;
+                    if verbose {
+                        println!("Setting nH = {:e}", self.nH);
+                    }
+                }
+                "COLDEN" => {
+                    self.colDen = float()?;
+                    state.surfDen = None;
+                    if verbose {
+                        println!("Setting column density = {:e} H cm^-2", self.colDen);
+                    }
+                }
+                "SIGMA" => {
+                    state.surfDen = Some(parse_float(value).map_err(|_| parse_error())?);
+                    if verbose {
+                        println!("Setting surface density = {value} Msun pc^-2");
+                    }
+                }
+                "SIGMANT" => {
+                    self.sigmaNT = float()?;
+                    if verbose {
+                        println!("Setting sigmaNT = {:e} cm s^-1", self.sigmaNT);
+                    }
+                }
+                "DVDR" => {
+                    self.dVdr = float()?;
+                    if verbose {
+                        println!("Setting dVdr = {:e} cm s^-1 cm^-1", self.dVdr);
+                    }
+                }
+                "TG" => {
+                    self.Tg = float()?;
+                    if verbose {
+                        println!("Setting Tg = {} K", self.Tg);
+                    }
+                }
+                "TD" => {
+                    self.Td = float()?;
+                    if verbose {
+                        println!("Setting Td = {} K", self.Td);
+                    }
+                }
+                // Sets the gas and dust temperatures together; a TG or
+                // TD later in the file overrides the corresponding one
+                "TEMP" => {
+                    self.Tg = float()?;
+                    self.Td = self.Tg;
+                    if verbose {
+                        println!("Setting Tg = Td = {} K", self.Tg);
+                    }
+                }
+                "ALPHAGD" => {
+                    self.dust.alphaGD = float()?;
+                    if verbose {
+                        println!("Setting alpha_GD = {:e} erg cm^3 K^-3/2", self.dust.alphaGD);
+                    }
+                }
+                "SIGMAD10" => {
+                    self.dust.sigma10 = float()?;
+                    if verbose {
+                        println!("Setting sigma_d,10 = {:e} cm^2 H^-1", self.dust.sigma10);
+                    }
+                }
+                "SIGMADPE" => {
+                    self.dust.sigmaPE = float()?;
+                    if verbose {
+                        println!("Setting sigma_d,PE = {:e} cm^2 H^-1", self.dust.sigmaPE);
+                    }
+                }
+                "SIGMADISRF" => {
+                    self.dust.sigmaISRF = float()?;
+                    if verbose {
+                        println!("Setting sigma_d,ISRF = {:e} cm^2 H^-1", self.dust.sigmaISRF);
+                    }
+                }
+                "ZDUST" => {
+                    self.dust.Zd = float()?;
+                    if verbose {
+                        println!("Setting Z'_d = {}", self.dust.Zd);
+                    }
+                }
+                "DUSTTOGAS" => {
+                    self.dust.Zd =
+                        (parse_float(value).map_err(|_| parse_error())? / DUST_TO_GAS_MW) as f32;
+                    if verbose {
+                        println!("Setting Z'_d = {}", self.dust.Zd);
+                    }
+                }
+                "BETADUST" => {
+                    self.dust.beta = float()?;
+                    if verbose {
+                        println!("Setting beta_dust = {}", self.dust.beta);
+                    }
+                }
+                "XHI" => {
+                    self.comp.xHI = float()?;
+                    if verbose {
+                        println!("Setting xHI = {}", self.comp.xHI);
+                    }
+                }
+                "XPH2" => {
+                    self.comp.xpH2 = float()?;
+                    if verbose {
+                        println!("Setting xpH2 = {}", self.comp.xpH2);
+                    }
+                }
+                "XOH2" => {
+                    self.comp.xoH2 = float()?;
+                    if verbose {
+                        println!("Setting xoH2 = {}", self.comp.xoH2);
+                    }
+                }
+                "H2OPR" => {
+                    self.comp.set_H2OPR(float()?);
+                    if verbose {
+                        println!("Setting H2 ortho-para ratio = {}", float()?);
+                    }
+                }
+                "XH2" => {
+                    let xH2 = float()?;
+                    if self.comp.H2OPR.is_none() {
+                        self.comp.H2OPR = Some(0.25);
+                        eprintln!("Warning: H2 OPR unspecified, assuming 0.25");
+                    }
+                    self.comp.set_xH2(xH2);
+                    if verbose {
+                        println!("Setting xpH2 = {}", self.comp.xpH2);
+                        println!("Setting xoH2 = {}", self.comp.xoH2);
+                    }
+                }
+                "XHE" => {
+                    self.comp.xHe = float()?;
+                    if verbose {
+                        println!("Setting xHe = {}", self.comp.xHe);
+                    }
+                }
+                "XE" => {
+                    self.comp.xe = float()?;
+                    if verbose {
+                        println!("Setting xe = {}", self.comp.xe);
+                    }
+                }
+                "XHPLUS" => {
+                    self.comp.xHplus = float()?;
+                    if verbose {
+                        println!("Setting xH+ = {}", self.comp.xHplus);
+                    }
+                }
+                // Legacy keyword: older files use XH+ for the
+                // ionization fraction, so it sets both xH+ and xe
+                "XH+" => {
+                    self.comp.xHplus = float()?;
+                    self.comp.xe = self.comp.xHplus;
+                    if verbose {
+                        println!("Setting xH+ = xe = {}", self.comp.xHplus);
+                    }
+                }
+                "TCMB" => {
+                    self.rad.TCMB = float()?;
+                    if verbose {
+                        println!("Setting T_CMB = {} K", self.rad.TCMB);
+                    }
+                }
+                "TRADDUST" => {
+                    self.rad.TradDust = float()?;
+                    if verbose {
+                        println!("Setting T_radDust = {} K", self.rad.TradDust);
+                    }
+                }
+                // RADDUTDILUTION is the misspelling accepted by older
+                // versions, kept so that existing files still parse
+                "RADDUSTDILUTION" | "RADDUTDILUTION" => {
+                    self.rad.fdDilute = float()?;
+                    if verbose {
+                        println!("Setting radDust dilution factor = {}", self.rad.fdDilute);
+                    }
+                }
+                "IONRATE" => {
+                    self.rad.ionRate = float()?;
+                    if verbose {
+                        println!(
+                            "Setting primary ionization rate = {:e} s^-1 H^-1",
+                            self.rad.ionRate
+                        );
+                    }
+                }
+                "CHI" => {
+                    self.rad.chi = float()?;
+                    if verbose {
+                        println!("Setting chi = {}", self.rad.chi);
+                    }
+                }
+                "RADSPECTRUM" => {
+                    // Pairs of frequency and intensity, separated by
+                    // a colon; see Isrf for what the spectrum affects
+                    let points = value
+                        .split_whitespace()
+                        .map(|pair| {
+                            let (nu, I) = pair.split_once(':')?;
+                            Some((parse_float(nu).ok()?, parse_float(I).ok()?))
+                        })
+                        .collect::<Option<Vec<_>>>()
+                        .ok_or_else(parse_error)?;
+                    self.rad.isrf = Isrf::custom(&points)?;
+                    if verbose {
+                        println!("Setting ISRF spectrum from {} points", points.len());
+                    }
+                }
+                "EMITTER" => {
+                    // Emitter lines are complicated. There are two
+                    // required elements, a name and an abundance, that
+                    // must come first. There are also four optional
+                    // elements: energySkip, noExtrap, file:FileName,
+                    // and URL:url
+                    let tokens: Vec<&str> = value.split_whitespace().collect();
+                    if tokens.len() < 2 || tokens.len() > 6 {
+                        return Err(parse_error());
+                    }
+                    let abundance = parse_float(tokens[1]).map_err(|_| parse_error())? as f32;
+
+                    // Initialize the options to their defaults, then
+                    // alter them based on the tokens we've been given
+                    let mut energySkip = false;
+                    let mut extrap = true;
+                    let mut emitterFile = None;
+                    let mut emitterURL = None;
+                    for token in &tokens[2..] {
+                        let prefix = |n: usize| token.get(..n).map(str::to_uppercase);
+                        if token.eq_ignore_ascii_case("ENERGYSKIP") {
+                            energySkip = true;
+                        } else if token.eq_ignore_ascii_case("EXTRAPOLATE") {
+                            // Allowed to maintain backward compatibility
+                        } else if token.eq_ignore_ascii_case("NOEXTRAP") {
+                            extrap = false;
+                        } else if prefix(5).as_deref() == Some("FILE:") {
+                            emitterFile = Some(&token[5..]);
+                        } else if prefix(4).as_deref() == Some("URL:") {
+                            emitterURL = Some(&token[4..]);
+                        } else {
+                            return Err(DespoticError::UnknownKeyword {
+                                line_number,
+                                keyword: token.to_string(),
+                            });
+                        }
+                    }
+
+                    // Now print message and add emitter
+                    if verbose {
+                        let mut msg =
+                            format!("Adding emitter {} with abundance {}", tokens[0], tokens[1]);
+                        if energySkip {
+                            msg += "; setting energySkip";
+                        }
+                        if !extrap {
+                            msg += "; disallowing extrapolation";
+                        }
+                        if let Some(f) = emitterFile {
+                            msg += &format!("; using file name {f}");
+                        }
+                        if let Some(u) = emitterURL {
+                            msg += &format!("; using URL {u}");
+                        }
+                        println!("{msg}");
+                    }
+                    self.add_emitter(
+                        tokens[0],
+                        abundance,
+                        energySkip,
+                        extrap,
+                        emitterFile,
+                        emitterURL,
+                    )?;
+                }
+                "INCLUDE" => {
+                    let (fp, path) = open_cloud_file(Path::new(value), dir)?;
+                    let path = path.canonicalize()?;
+                    if state.stack.contains(&path) {
+                        return Err(DespoticError::IncludeCycle(path));
+                    }
+                    if verbose {
+                        println!("Including file {}...", path.display());
+                    }
+                    state.stack.push(path.clone());
+                    self.parse_lines(
+                        std::io::BufReader::new(fp),
+                        verbose,
+                        opts,
+                        state,
+                        path.parent(),
+                    )?;
+                    state.stack.pop();
+                }
+                _ => {
+                    // Line does not correspond to any known keyword, so
+                    // throw an error
+                    return Err(DespoticError::UnknownKeyword {
+                        line_number,
+                        keyword: key.trim().to_string(),
+                    });
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Check a cloud once all its lines have been read, and compute
+    /// its derived properties
+    fn finish_read(
+        &mut self,
+        state: ParseState,
+        verbose: bool,
+        opts: &ReadOptions,
+    ) -> Result<(), DespoticError> {
+        // Check that the essential properties have been given
+        let hydrogenKeys = ["XHI", "XPH2", "XOH2", "XH2", "XHPLUS", "XH+"];
+        let hasHydrogen = hydrogenKeys.iter().any(|k| state.keys.contains(*k));
+        if !opts.allow_partial {
+            if !state.keys.contains("NH") {
+                return Err(DespoticError::MissingField("nH".to_string()));
+            }
+            if !hasHydrogen {
+                return Err(DespoticError::MissingField(
+                    "hydrogen composition (xHI, xH2, or xH+)".to_string(),
+                ));
+            }
+        }
+
+        // Check that the abundances are non-negative and that the
+        // hydrogen adds up; a partial cloud may leave the composition
+        // to be set later
+        if hasHydrogen {
+            self.comp.validate()?;
+        }
+
+        // Set derived properties based on composition, temperature
+        self.comp.compute_derived(self.nH);
+        if self.Tg > 0.0 {
+            self.comp.compute_cv(self.Tg);
+        }
+
+        // Convert a surface density to a column density of H nuclei
+        if let Some(Sigma) = state.surfDen {
+            self.colDen = (Sigma * Msun / (pc * pc) / (self.comp.muH as f64 * mH)) as f32;
+            if verbose {
+                println!("Setting column density = {:e} H cm^-2", self.colDen);
+            }
+        }
+
+        // If verbose, print results for derived quantities
+        if verbose {
+            println!("Derived quantities:");
+            println!("   ===> mean mass per particle = {} mH", self.comp.mu);
+            println!("   ===> mean mass per H = {} mH", self.comp.muH);
+            println!(
+                "   ===> energy added per ionization = {:.3} eV",
+                self.comp.qIon as f64 / crate::consts::eV
+            );
+            if let Some(cv) = self.comp.cv {
+                println!("   ===> c_v/(k_B n_H) = {cv}");
+            }
+        }
+
+        // Remember the state as read, so that reset can restore it
+        self.initialState = Some(InitialState {
+            Tg: self.Tg,
+            Td: self.Td,
+            comp: self.comp.clone(),
+            abundances: self
+                .emitters
+                .iter()
+                .map(|(name, em)| (name.clone(), em.abundance.clone()))
+                .collect(),
+        });
+        Ok(())
+    }
+
+    /// Return the cloud to its state as last read
+    ///
+    /// Parameters
+    ///    None
+    ///
+    /// Returns
+    ///    Nothing
+    ///
+    /// Remarks
+    ///    Restores the gas and dust temperatures, the composition, and
+    ///    the abundances of the emitters present when the cloud was
+    ///    read, and clears every emitter's cached level populations.
+    ///    Other properties, emitters added since, and the abundances
+    ///    held by an attached chemical network are not changed. If
+    ///    the cloud has never been read, only the caches are cleared.
+    pub fn reset(&mut self) {
+        if let Some(init) = &self.initialState {
+            self.Tg = init.Tg;
+            self.Td = init.Td;
+            self.comp = init.comp.clone();
+            for (name, abundance) in &init.abundances {
+                if let Some(em) = self.emitters.get_mut(name) {
+                    em.abundance = abundance.clone();
+                }
+            }
+        }
+        for em in self.emitters.values() {
+            em.clear_pop_cache();
+        }
+        *self.dirty.get_mut() = false;
+    }
+
+    /// Add an emitting species to the cloud
+    ///
+    /// Parameters
+    ///    name : string
+    ///       name of the emitting species
+    ///    abundance : float
+    ///       abundance of the species per H nucleus
+    ///    energySkip : Boolean
+    ///       if true, the emitter is ignored in heating and cooling
+    ///       calculations
+    ///    extrap : Boolean
+    ///       if true, collision rates may be extrapolated beyond the
+    ///       tabulated temperature range
+    ///    emitterFile : string
+    ///       name of the LAMDA file holding the emitter's data
+    ///    emitterURL : string
+    ///       URL from which the LAMDA file can be obtained
+    ///
+    /// Returns
+    ///    Nothing
+    ///
+    /// Remarks
+    ///    If another emitter of the cloud was read from the same file,
+    ///    its data are shared rather than read again; this lets, for
+    ///    example, ortho- and para-H2O emitters with independent
+    ///    abundances use one LAMDA file
+    pub fn add_emitter(
+        &mut self,
+        name: &str,
+        abundance: f32,
+        energySkip: bool,
+        extrap: bool,
+        emitterFile: Option<&str>,
+        emitterURL: Option<&str>,
+    ) -> Result<(), DespoticError> {
+        let path = Emitter::data_path(name, emitterFile, emitterURL)?;
+        let canonical = path.canonicalize().unwrap_or_else(|_| path.clone());
+        let shared = self
+            .emitters
+            .values()
+            .find(|em| em.data_file() == Some(canonical.as_path()))
+            .map(|em| em.data.clone());
+        let emitter = Emitter::from_path(name, abundance, energySkip, extrap, path, shared)?;
+        self.emitters.insert(name.to_string(), emitter);
+        Ok(())
+    }
+
+    /// Deplete an emitter onto grains if the cloud is dense enough
+    ///
+    /// Parameters
+    ///    species : string
+    ///       name of the emitter
+    ///    n_threshold : float
+    ///       density nH above which the species freezes out, in cm^-3
+    ///    factor : float
+    ///       factor by which freeze-out reduces the gas-phase
+    ///       abundance
+    ///
+    /// Returns
+    ///    Nothing
+    ///
+    /// Remarks
+    ///    If nH exceeds n_threshold the emitter's abundance is divided
+    ///    by factor, as in the step-function depletion model used for
+    ///    CO in cold cores; otherwise it is unchanged. Only the
+    ///    emitter is changed, not an attached chemical network.
+    pub fn apply_depletion(
+        &mut self,
+        species: &str,
+        n_threshold: f64,
+        factor: f64,
+    ) -> Result<(), DespoticError> {
+        let dense = self.nH as f64 > n_threshold;
+        let em = self
+            .emitters
+            .get_mut(species)
+            .ok_or_else(|| DespoticError::UnknownEmitter(species.to_string()))?;
+        if dense {
+            em.abundance = em.abundance.scaled_down(factor);
+            self.mark_dirty();
+        }
+        Ok(())
+    }
+
+    /// Solve for the level populations of every emitter
+    ///
+    /// Parameters
+    ///    None
+    ///
+    /// Returns
+    ///    the level populations of each emitter, keyed by name
+    ///
+    /// Remarks
+    ///    Each emitter's populations are also cached, so later
+    ///    calculations under the same conditions start from them
+    pub fn solve_all_pops(&self) -> Result<HashMap<String, Vec<f64>>, DespoticError> {
+        self.refresh_if_dirty();
+        self.emitters
+            .iter()
+            .map(|(name, em)| Ok((name.clone(), em.level_pops(self)?)))
+            .collect()
+    }
+
+    /// Solve for the level populations of every emitter, solving the
+    /// emitters in parallel
+    ///
+    /// Parameters
+    ///    None
+    ///
+    /// Returns
+    ///    the level populations of each emitter, keyed by name
+    ///
+    /// Remarks
+    ///    The results are identical to those of solve_all_pops, since
+    ///    the emitters' solves are independent and the cloud is only
+    ///    read while they run. The solves are shared among the
+    ///    threads of rayon's global pool, so this pays off only for
+    ///    clouds with several expensive emitters.
+    #[cfg(feature = "parallel")]
+    pub fn solve_all_pops_parallel(&self) -> Result<HashMap<String, Vec<f64>>, DespoticError> {
+        use rayon::prelude::*;
+        self.refresh_if_dirty();
+        self.emitters
+            .par_iter()
+            .map(|(name, em)| Ok((name.clone(), em.level_pops(self)?)))
+            .collect()
+    }
+
+    /// Share solved level populations with other clouds
+    ///
+    /// Parameters
+    ///    memo : PopMemo
+    ///       memo in which every emitter of the cloud looks up and
+    ///       stores its populations
+    ///
+    /// Returns
+    ///    Nothing
+    ///
+    /// Remarks
+    ///    Clouds in a grid that share one memo solve each set of
+    ///    conditions only once; see PopMemo for which properties the
+    ///    memo distinguishes. Emitters added later are not attached.
+    pub fn set_pop_memo(&mut self, memo: Arc<PopMemo>) {
+        for em in self.emitters.values_mut() {
+            em.set_pop_memo(Some(memo.clone()));
+        }
+    }
+
+    /// Scale the density of the cloud at fixed size
+    ///
+    /// Parameters
+    ///    factor : float
+    ///       factor by which to multiply the volume density
+    ///
+    /// Returns
+    ///    Nothing
+    ///
+    /// Remarks
+    ///    Both nH and colDen are multiplied by factor, so their ratio,
+    ///    the physical size of the cloud, is unchanged. Quantities
+    ///    derived from the density are recomputed.
+    pub fn scale_density(&mut self, factor: f64) {
+        self.nH = (self.nH as f64 * factor) as f32;
+        self.colDen = (self.colDen as f64 * factor) as f32;
+        self.comp.compute_derived(self.nH);
+        self.mark_dirty();
+    }
+
+    /// Scale the strength of the incident radiation field
+    ///
+    /// Parameters
+    ///    factor : float
+    ///       factor by which to multiply the field
+    ///
+    /// Returns
+    ///    Nothing
+    ///
+    /// Remarks
+    ///    The ISRF strength chi, any tabulated ISRF spectrum, the
+    ///    dilution factor fdDilute of the dust-reprocessed IR field,
+    ///    and the cosmic ray ionization rates, including any set per
+    ///    species, are multiplied by factor. The CMB is unchanged.
+    pub fn scale_radiation(&mut self, factor: f64) {
+        let scale = |v: f32| (v as f64 * factor) as f32;
+        let rad = &mut self.rad;
+        rad.chi = scale(rad.chi);
+        rad.fdDilute = scale(rad.fdDilute);
+        rad.ionRate = scale(rad.ionRate);
+        rad.cr_rate_h2 = rad.cr_rate_h2.map(scale);
+        rad.cr_rate_he = rad.cr_rate_he.map(scale);
+        if let Isrf::Custom(table) = &mut rad.isrf {
+            table.scale(factor);
+        }
+        self.mark_dirty();
+    }
+
+    /// Center-to-edge column density of H2 molecules
+    ///
+    /// Parameters
+    ///    None
+    ///
+    /// Returns
+    ///    (xpH2 + xoH2) colDen, in cm^-2
+    pub fn n_h2(&self) -> f64 {
+        self.comp.xH2() as f64 * self.colDen as f64
+    }
+
+    /// Center-to-edge column density of atomic hydrogen
+    ///
+    /// Parameters
+    ///    None
+    ///
+    /// Returns
+    ///    xHI colDen, in cm^-2
+    pub fn n_hi(&self) -> f64 {
+        self.comp.xHI as f64 * self.colDen as f64
+    }
+
+    /// Ratio of the abundances of two species
+    ///
+    /// Parameters
+    ///    a : string
+    ///       species in the numerator
+    ///    b : string
+    ///       species in the denominator
+    ///
+    /// Returns
+    ///    x(a) / x(b), with abundances per H nucleus
+    ///
+    /// Remarks
+    ///    Each species is looked up first among the emitters, by the
+    ///    name used for them in the cloud, and then in the bulk
+    ///    composition as "HI", "H2", "pH2", "oH2", "He", "e" or "H+".
+    ///    An UnknownSpecies error is returned if either cannot be
+    ///    found, and an InvalidComposition error if x(b) is zero.
+    pub fn abundance_ratio(&self, a: &str, b: &str) -> Result<f64, DespoticError> {
+        let xa = self.abundance_of(a)?;
+        let xb = self.abundance_of(b)?;
+        if xb == 0.0 {
+            return Err(DespoticError::InvalidComposition(format!(
+                "abundance of {b} is zero"
+            )));
+        }
+        Ok(xa / xb)
+    }
+
+    /// Abundance per H nucleus of an emitter or a bulk species
+    fn abundance_of(&self, species: &str) -> Result<f64, DespoticError> {
+        if let Some(em) = self.emitters.get(species) {
+            return Ok(em.abundance_in(self));
+        }
+        let comp = &self.comp;
+        let x = match species {
+            "HI" => comp.xHI,
+            "H2" => comp.xH2(),
+            "pH2" => comp.xpH2,
+            "oH2" => comp.xoH2,
+            "He" => comp.xHe,
+            "e" => comp.xe,
+            "H+" => comp.xHplus,
+            _ => return Err(DespoticError::UnknownSpecies(species.to_string())),
+        };
+        Ok(x as f64)
+    }
+
+    /// Fail with ZeroDensity unless nH is positive; physics routines
+    /// call this first rather than letting NaNs propagate
+    pub(crate) fn check_density(&self) -> Result<(), DespoticError> {
+        if self.nH > 0.0 {
+            Ok(())
+        } else {
+            Err(DespoticError::ZeroDensity)
+        }
+    }
+
+    /// Whether a property has been changed through a setter since a
+    /// calculation using level populations last began
+    ///
+    /// Parameters
+    ///    None
+    ///
+    /// Returns
+    ///    true if populations cached for the old properties are stale
+    ///    and will be discarded before the next solve
+    ///
+    /// Remarks
+    ///    The flag is cleared when the stale populations are
+    ///    discarded, at the start of the next calculation, whether or
+    ///    not its solve then succeeds
+    pub fn is_dirty(&self) -> bool {
+        self.dirty.load(Ordering::Relaxed)
+    }
+
+    /// Record that the cloud's properties have changed
+    fn mark_dirty(&mut self) {
+        *self.dirty.get_mut() = true;
+    }
+
+    /// Discard results cached for the cloud's old properties, if it
+    /// has changed since they were computed
+    ///
+    /// Remarks
+    ///    Called before any calculation that uses level populations,
+    ///    so that a solve never starts from populations cached under
+    ///    different conditions
+    pub(crate) fn refresh_if_dirty(&self) {
+        if self.dirty.swap(false, Ordering::Relaxed) {
+            for em in self.emitters.values() {
+                em.clear_pop_cache();
+            }
+        }
+    }
+
+    /// Set the number density of H nuclei
+    ///
+    /// Parameters
+    ///    nH : float
+    ///       number density of H nuclei, in cm^-3
+    ///
+    /// Returns
+    ///    Nothing
+    ///
+    /// Remarks
+    ///    Quantities derived from the density are recomputed, and the
+    ///    cloud is marked dirty so that cached level populations are
+    ///    not reused. colDen is unchanged, so the cloud's size
+    ///    changes; use scale_density to keep the size fixed.
+    pub fn set_nh(&mut self, nH: impl Into<NumberDensity>) {
+        self.nH = nH.into().value() as f32;
+        self.comp.compute_derived(self.nH);
+        self.mark_dirty();
+    }
+
+    /// Set the column density of H nuclei
+    ///
+    /// Parameters
+    ///    colDen : float
+    ///       center-to-edge column density of H nuclei, in cm^-2
+    ///
+    /// Returns
+    ///    Nothing
+    ///
+    /// Remarks
+    ///    The cloud is marked dirty
+    pub fn set_col_den(&mut self, colDen: impl Into<ColumnDensity>) {
+        self.colDen = colDen.into().value() as f32;
+        self.mark_dirty();
+    }
+
+    /// Set the gas kinetic temperature
+    ///
+    /// Parameters
+    ///    Tg : float
+    ///       gas kinetic temperature, in K
+    ///
+    /// Returns
+    ///    Nothing
+    ///
+    /// Remarks
+    ///    The specific heat is recomputed, and the cloud is marked
+    ///    dirty
+    pub fn set_tg(&mut self, Tg: impl Into<Temperature>) {
+        self.Tg = Tg.into().value() as f32;
+        self.comp.compute_cv(self.Tg);
+        self.mark_dirty();
+    }
+
+    /// Set the dust temperature
+    ///
+    /// Parameters
+    ///    Td : float
+    ///       dust temperature, in K
+    ///
+    /// Returns
+    ///    Nothing
+    ///
+    /// Remarks
+    ///    The cloud is marked dirty
+    pub fn set_td(&mut self, Td: impl Into<Temperature>) {
+        self.Td = Td.into().value() as f32;
+        self.mark_dirty();
+    }
+
+    /// Set the non-thermal velocity dispersion
+    ///
+    /// Parameters
+    ///    sigmaNT : float
+    ///       non-thermal velocity dispersion, in cm s^-1
+    ///
+    /// Returns
+    ///    Nothing
+    ///
+    /// Remarks
+    ///    The cloud is marked dirty
+    pub fn set_sigma_nt(&mut self, sigmaNT: f32) {
+        self.sigmaNT = sigmaNT;
+        self.mark_dirty();
+    }
+
+    /// Set the radial velocity gradient
+    ///
+    /// Parameters
+    ///    dVdr : float
+    ///       radial velocity gradient, in s^-1
+    ///
+    /// Returns
+    ///    Nothing
+    ///
+    /// Remarks
+    ///    The cloud is marked dirty
+    pub fn set_dvdr(&mut self, dVdr: f32) {
+        self.dVdr = dVdr;
+        self.mark_dirty();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::{co_cloud, init, read_cloud};
+
+    #[test]
+    fn parse_error_reports_physical_line() {
+        let text = "# a cloud\n\nnH = 100\nxH2 = 0.5\nTg = ten\n";
+        match read_cloud(text) {
+            Err(DespoticError::ParseLine { line_number, line }) => {
+                assert_eq!(line_number, 5);
+                assert_eq!(line, "Tg = ten");
+            }
+            other => panic!("expected a parse error, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn unknown_keyword_reports_physical_line() {
+        let text = "nH = 100\n# comment\nxH2 = 0.5\n\nfoo = 1\n";
+        assert!(matches!(
+            read_cloud(text),
+            Err(DespoticError::UnknownKeyword { line_number: 5, .. })
+        ));
+    }
+
+    #[test]
+    fn xhplus_and_legacy_xh_plus() {
+        let cloud = read_cloud("nH = 10\nxHI = 0.9\nxHplus = 0.1\nxe = 0.15\n").unwrap();
+        assert_eq!(cloud.comp.xHplus, 0.1);
+        assert_eq!(cloud.comp.xe, 0.15);
+
+        let cloud = read_cloud("nH = 10\nxHI = 0.9\nxH+ = 0.1\n").unwrap();
+        assert_eq!(cloud.comp.xHplus, 0.1);
+        assert_eq!(cloud.comp.xe, 0.1);
+    }
+
+    #[test]
+    fn electrons_do_not_count_toward_hydrogen() {
+        assert!(matches!(
+            read_cloud("nH = 10\nxHI = 0.9\nxe = 0.1\n"),
+            Err(DespoticError::HydrogenBudget(_))
+        ));
+    }
+
+    #[test]
+    fn scale_density_keeps_size() {
+        let mut cloud = co_cloud();
+        let size = cloud.colDen / cloud.nH;
+        let muH = cloud.comp.muH;
+        cloud.scale_density(10.0);
+        assert_eq!(cloud.nH, 1.0e4);
+        assert!((cloud.colDen / cloud.nH / size - 1.0).abs() < 1.0e-6);
+        assert_eq!(cloud.comp.muH, muH);
+    }
+
+    #[test]
+    fn duplicate_policies() {
+        let text = "nH = 10\nxHI = 1\nTg = 50\n# again\nTg = 80\n";
+        let read = |duplicates| {
+            let mut cloud = Cloud::new(None::<&str>, true, false).unwrap();
+            let opts = ReadOptions {
+                duplicates,
+                ..ReadOptions::default()
+            };
+            cloud
+                .read_from(text.as_bytes(), false, &opts)
+                .map(|_| cloud.Tg)
+        };
+        assert_eq!(read(DuplicatePolicy::LastWins).unwrap(), 80.0);
+        assert_eq!(read(DuplicatePolicy::FirstWins).unwrap(), 50.0);
+        match read(DuplicatePolicy::Error) {
+            Err(DespoticError::DuplicateKeyword {
+                line_number,
+                keyword,
+                first_line,
+            }) => {
+                assert_eq!((line_number, first_line), (5, 3));
+                assert_eq!(keyword, "Tg");
+            }
+            other => panic!("expected a duplicate keyword error, got {other:?}"),
+        }
+        assert_eq!(read_cloud(text).unwrap().Tg, 80.0);
+    }
+
+    #[test]
+    fn surface_density_sets_column_density() {
+        // 100 Msun pc^-2 is 0.0209 g cm^-2, or 8.92e21 H cm^-2 for
+        // muH = 1.4; the keyword may precede the composition
+        let cloud = read_cloud("SIGMA = 100\nnH = 100\nxH2 = 0.5\nxHe = 0.1\n").unwrap();
+        assert!((cloud.comp.muH - 1.4).abs() < 1.0e-3);
+        assert!((cloud.colDen / 8.92e21 - 1.0).abs() < 1.0e-3);
+    }
+
+    #[test]
+    fn reset_restores_file_values() {
+        let text = "nH = 1000\ncolDen = 1e22\nsigmaNT = 1e5\nTg = 10\nTd = 10\n\
+                    xH2 = 0.5\nxHe = 0.1\nH2OPR = 0.25\nemitter = co 1e-4\n";
+        let mut cloud = read_cloud(text).unwrap();
+        cloud.set_temp_eq().unwrap();
+        cloud.emitters.get_mut("co").unwrap().abundance = 1.0e-5.into();
+        assert_ne!(cloud.Tg, 10.0);
+        assert_ne!(cloud.Td, 10.0);
+        assert!(cloud.emitters["co"].last_iterations() > 0);
+
+        cloud.reset();
+        assert_eq!((cloud.Tg, cloud.Td), (10.0, 10.0));
+        assert_eq!(cloud.emitters["co"].abundance_in(&cloud), 1.0e-4_f32 as f64);
+        assert_eq!(cloud.emitters["co"].last_iterations(), 0);
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn parallel_pops_match_serial() {
+        crate::testutil::init();
+        let species = [("co", 1.0e-4), ("c+", 1.0e-5), ("nocoll", 1.0e-8)];
+        let serial = Cloud::simple(1.0e3, 20.0, &species).unwrap();
+        let parallel = Cloud::simple(1.0e3, 20.0, &species).unwrap();
+        assert_eq!(
+            serial.solve_all_pops().unwrap(),
+            parallel.solve_all_pops_parallel().unwrap()
+        );
+    }
+
+    #[test]
+    fn temp_sets_both_temperatures() {
+        let cloud = read_cloud("nH = 10\nxHI = 1\nTEMP = 15\n").unwrap();
+        assert_eq!((cloud.Tg, cloud.Td), (15.0, 15.0));
+
+        let cloud = read_cloud("nH = 10\nxHI = 1\nTEMP = 15\nTD = 20\n").unwrap();
+        assert_eq!((cloud.Tg, cloud.Td), (15.0, 20.0));
+    }
+
+    #[test]
+    fn missing_essentials_are_errors_unless_partial() {
+        assert!(matches!(
+            read_cloud("xH2 = 0.5\nTg = 10\n"),
+            Err(DespoticError::MissingField(f)) if f == "nH"
+        ));
+        assert!(matches!(
+            read_cloud("nH = 100\nTg = 10\n"),
+            Err(DespoticError::MissingField(f)) if f.starts_with("hydrogen")
+        ));
+
+        let mut cloud = Cloud::new(None::<&str>, true, false).unwrap();
+        let opts = ReadOptions {
+            allow_partial: true,
+            ..Default::default()
+        };
+        cloud
+            .read_from("Tg = 10\n".as_bytes(), false, &opts)
+            .unwrap();
+        assert_eq!((cloud.nH, cloud.Tg), (0.0, 10.0));
+    }
+
+    #[test]
+    fn set_nh_marks_dirty_and_resolves() {
+        let mut cloud = co_cloud();
+        let before = cloud.emitters["co"].line_lum(&cloud).unwrap()[2].intTB;
+        assert!(!cloud.is_dirty());
+        cloud.set_nh(1.0e5);
+        assert!(cloud.is_dirty());
+        let after = cloud.emitters["co"].line_lum(&cloud).unwrap()[2].intTB;
+        let iterations = cloud.emitters["co"].last_iterations();
+        assert!(!cloud.is_dirty());
+
+        // The solve after set_nh starts cold, exactly as in a cloud
+        // built at the new density, rather than from the populations
+        // cached at the old one
+        let mut fresh = co_cloud();
+        fresh.nH = 1.0e5;
+        fresh.comp.compute_derived(fresh.nH);
+        let expected = fresh.emitters["co"].line_lum(&fresh).unwrap()[2].intTB;
+        assert_eq!(iterations, fresh.emitters["co"].last_iterations());
+        assert_eq!(after, expected);
+        assert!((after / before - 1.0).abs() > 0.1);
+
+        // A solve that is not preceded by a change starts warm
+        cloud.emitters["co"].line_lum(&cloud).unwrap();
+        assert!(cloud.emitters["co"].last_iterations() < iterations);
+    }
+
+    /// Write cloud files into a fresh scratch directory
+    fn scratch(name: &str, files: &[(&str, &str)]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("despoticir-{name}-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        for (file, text) in files {
+            std::fs::write(dir.join(file), text).unwrap();
+        }
+        dir
+    }
+
+    #[test]
+    fn include_merges_base_and_overrides() {
+        let dir = scratch(
+            "include",
+            &[
+                ("base.desp", "nH = 300\nxH2 = 0.5\nTg = 10\n"),
+                ("point.desp", "INCLUDE = base.desp\nTg = 25\n"),
+            ],
+        );
+        init();
+        let mut cloud = Cloud::new(None::<&str>, true, false).unwrap();
+        cloud
+            .read_with(dir.join("point.desp"), false, &Default::default())
+            .unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!((cloud.nH, cloud.Tg), (300.0, 25.0));
+        assert_eq!(cloud.comp.xH2(), 0.5);
+    }
+
+    #[test]
+    fn self_include_is_a_cycle() {
+        let dir = scratch("cycle", &[("loop.desp", "nH = 300\nINCLUDE = loop.desp\n")]);
+        init();
+        let mut cloud = Cloud::new(None::<&str>, true, false).unwrap();
+        let result = cloud.read_with(dir.join("loop.desp"), false, &Default::default());
+        std::fs::remove_dir_all(&dir).unwrap();
+        match result {
+            Err(DespoticError::IncludeCycle(path)) => assert!(path.ends_with("loop.desp")),
+            other => panic!("expected an include cycle, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn simple_co_cloud_predicts_co_1_0() {
+        let cloud = co_cloud();
+        assert_eq!((cloud.nH, cloud.Tg, cloud.Td), (1.0e3, 20.0, 20.0));
+        assert_eq!(cloud.comp.xH2(), 0.5);
+
+        let co10 = &cloud.emitters["co"].line_lum(&cloud).unwrap()[0];
+        assert_eq!((co10.upper, co10.lower), (1, 0));
+        assert!((co10.freq / 115.27e9 - 1.0).abs() < 1.0e-4);
+        // At this column CO(1-0) is optically thick and close to
+        // thermalized
+        assert!(co10.tau > 1.0, "tau = {}", co10.tau);
+        assert!((co10.Tex / 20.0 - 1.0).abs() < 0.2, "Tex = {}", co10.Tex);
+        assert!(co10.intTB > 1.0 && co10.intTB < 100.0);
+    }
+
+    #[test]
+    fn dust_to_gas_sets_zd() {
+        let text = format!("nH = 100\nxH2 = 0.5\nDUSTTOGAS = {DUST_TO_GAS_MW}\n");
+        let cloud = read_cloud(&text).unwrap();
+        assert_eq!(cloud.dust.Zd, 1.0);
+        assert!((cloud.dust.dust_to_gas() / DUST_TO_GAS_MW - 1.0).abs() < 1.0e-6);
+
+        let cloud = read_cloud("nH = 100\nxH2 = 0.5\nDUSTTOGAS = 0.005\n").unwrap();
+        assert!((cloud.dust.Zd as f64 - 0.005 / DUST_TO_GAS_MW).abs() < 1.0e-6);
+    }
+
+    #[test]
+    fn emitters_share_data_but_solve_independently() {
+        let mut cloud = co_cloud();
+        cloud
+            .add_emitter("rareCO", 1.0e-8, false, false, Some("co.dat"), None)
+            .unwrap();
+        cloud.emitters.get_mut("rareCO").unwrap().escapeProbGeom =
+            crate::emitter::EscapeGeometry::Sphere;
+        let (co, rare) = (&cloud.emitters["co"], &cloud.emitters["rareCO"]);
+        assert!(Arc::ptr_eq(&co.data, &rare.data));
+
+        // The rare emitter is optically thin, so its populations and
+        // line optical depths differ from those of the abundant one
+        let (coPops, rarePops) = (
+            co.level_pops(&cloud).unwrap(),
+            rare.level_pops(&cloud).unwrap(),
+        );
+        assert!(coPops
+            .iter()
+            .zip(&rarePops)
+            .any(|(a, b)| (a - b).abs() > 1.0e-3));
+        let (coLines, rareLines) = (co.line_lum(&cloud).unwrap(), rare.line_lum(&cloud).unwrap());
+        assert!(coLines[0].tau > 1.0 && rareLines[0].tau < 0.1);
+
+        // and each keeps the escape probabilities of its own solve
+        let (coBeta, rareBeta) = (
+            co.escape_probabilities().unwrap(),
+            rare.escape_probabilities().unwrap(),
+        );
+        assert!(coBeta[0] < 0.5 && rareBeta[0] > 0.9);
+    }
+
+    #[test]
+    fn radspectrum_weights_only_the_dust_opacity() {
+        let base = "nH = 100\nxH2 = 0.5\nTg = 20\nTd = 20\n";
+        let standard = read_cloud(base).unwrap();
+        let custom = read_cloud(&format!(
+            "{base}RADSPECTRUM = 1e12:1e-18 1e13:1e-17 1e14:1e-19\n"
+        ))
+        .unwrap();
+
+        // Between points the intensity is interpolated as a power law
+        let isrf = &custom.rad.isrf;
+        let mid = 1.0e12 * 10.0_f64.sqrt();
+        let expected = 1.0e-18 * 10.0_f64.sqrt();
+        assert!((isrf.intensity(mid).unwrap() / expected - 1.0).abs() < 1.0e-10);
+        assert_eq!(isrf.intensity(1.0e13), Some(1.0e-17));
+        assert_eq!(isrf.intensity(1.0e15), Some(0.0));
+
+        let opacity = |cloud: &Cloud| cloud.dust.mean_opacity(&cloud.rad);
+        assert!((opacity(&custom) / opacity(&standard) - 1.0).abs() > 0.1);
+        let (a, b) = (standard.dedt().unwrap(), custom.dedt().unwrap());
+        assert_eq!(a.GammaPE, b.GammaPE);
+        assert_eq!(a.GammaDustISRF, b.GammaDustISRF);
+    }
+
+    #[test]
+    fn scale_radiation_leaves_cmb() {
+        let mut cloud = co_cloud();
+        cloud.rad.cr_rate_h2 = Some(5.0e-17);
+        let rad = cloud.rad.clone();
+        cloud.scale_radiation(10.0);
+        let close = |a: f32, b: f32| ((a / b) - 1.0).abs() < 1.0e-6;
+        assert!(close(cloud.rad.chi, 10.0 * rad.chi));
+        assert!(close(cloud.rad.ionRate, 10.0 * rad.ionRate));
+        assert!(close(cloud.rad.fdDilute, 10.0 * rad.fdDilute));
+        assert!(close(cloud.rad.cr_rate_h2.unwrap(), 5.0e-16));
+        assert_eq!(cloud.rad.TCMB, rad.TCMB);
+    }
+
+    #[test]
+    fn depletion_above_threshold_only() {
+        let mut cloud = co_cloud();
+        let x = |cloud: &Cloud| cloud.emitters["co"].abundance_in(cloud);
+        cloud.apply_depletion("co", 1.0e4, 10.0).unwrap();
+        assert!((x(&cloud) / 1.0e-4 - 1.0).abs() < 1.0e-6);
+
+        cloud.apply_depletion("co", 1.0e2, 10.0).unwrap();
+        assert!((x(&cloud) / 1.0e-5 - 1.0).abs() < 1.0e-6);
+        assert!(matches!(
+            cloud.apply_depletion("hcn", 1.0e2, 10.0),
+            Err(DespoticError::UnknownEmitter(_))
+        ));
+    }
+
+    #[test]
+    fn half_molecular_columns() {
+        // Half the H nuclei are in H2 and half are atomic
+        let cloud =
+            read_cloud("nH = 100\ncolDen = 1e21\nxHI = 0.5\nxpH2 = 0.05\nxoH2 = 0.2\n").unwrap();
+        let (NH2, NHI) = (cloud.n_h2(), cloud.n_hi());
+        assert!((NHI / 5.0e20 - 1.0).abs() < 1.0e-6);
+        assert!((NH2 / (0.5 * NHI) - 1.0).abs() < 1.0e-6);
+        assert!(((2.0 * NH2 + NHI) / cloud.colDen as f64 - 1.0).abs() < 1.0e-6);
+    }
+
+    #[test]
+    fn nan_spectrum_frequency_is_invalid() {
+        let result = read_cloud("nH = 100\nxH2 = 0.5\nRADSPECTRUM = nan:1e-18 1e13:1e-17\n");
+        assert!(
+            matches!(result, Err(DespoticError::InvalidSpectrum(_))),
+            "{result:?}"
+        );
+    }
+
+    #[test]
+    fn empty_and_blank_lines() {
+        // Blank lines, including whitespace-only ones, are skipped
+        let cloud = read_cloud("\n   \n\t\nnH = 100\n\nxH2 = 0.5\n  \n").unwrap();
+        assert_eq!(cloud.nH, 100.0);
+
+        // Lines with an empty keyword or value are errors, not panics
+        for line in ["=", " = 5", "nH =", "=\t=", "#\n="] {
+            let text = format!("nH = 100\nxH2 = 0.5\n{line}\n");
+            assert!(read_cloud(&text).is_err(), "{line:?} was accepted");
+        }
+        assert!(read_cloud("").is_err());
+    }
+
+    #[test]
+    fn overrides_replace_file_values() {
+        let dir = scratch(
+            "overrides",
+            &[("base.desp", "nH = 300\nxH2 = 0.5\nTg = 10\n")],
+        );
+        init();
+        let mut cloud = Cloud::new(None::<&str>, true, false).unwrap();
+        cloud
+            .read_with(dir.join("base.desp"), false, &Default::default())
+            .unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        cloud
+            .apply_overrides(&[("nH", "1e4"), ("Tg", "15")])
+            .unwrap();
+        assert_eq!((cloud.nH, cloud.Tg, cloud.comp.xH2()), (1.0e4, 15.0, 0.5));
+        cloud.set_tg(50.0);
+        cloud.reset();
+        assert_eq!(cloud.Tg, 15.0);
+
+        assert!(matches!(
+            cloud.apply_overrides(&[("Tg", "20"), ("nH", "dense")]),
+            Err(DespoticError::ParseLine { line_number: 2, .. })
+        ));
+        assert_eq!(cloud.Tg, 15.0);
+
+        // The hydrogen no longer adds up, so nothing is applied
+        assert!(cloud
+            .apply_overrides(&[("nH", "100"), ("xHI", "0.2")])
+            .is_err());
+        assert_eq!((cloud.nH, cloud.comp.xHI), (1.0e4, 0.0));
+    }
+
+    #[test]
+    fn co_to_h2_ratio() {
+        // All hydrogen is in H2, so xH2 = 0.5
+        let cloud = co_cloud();
+        let ratio = cloud.abundance_ratio("co", "H2").unwrap();
+        assert!((ratio / 2.0e-4 - 1.0).abs() < 1.0e-6);
+        let ratio = cloud.abundance_ratio("H2", "co").unwrap();
+        assert!((ratio / 5.0e3 - 1.0).abs() < 1.0e-6);
+
+        assert!(matches!(
+            cloud.abundance_ratio("hco+", "co"),
+            Err(DespoticError::UnknownSpecies(s)) if s == "hco+"
+        ));
+        assert!(matches!(
+            cloud.abundance_ratio("co", "HI"),
+            Err(DespoticError::InvalidComposition(_))
+        ));
     }
 }
@@ -0,0 +1,147 @@
+//! Field-by-field comparison of clouds
+
+use crate::cloud::Cloud;
+use std::collections::BTreeSet;
+
+/// A scalar property that differs between two clouds
+#[derive(Debug, Clone, PartialEq)]
+pub struct FieldDiff {
+    /// name of the property, e.g. "Tg" or "comp.xHI"
+    pub name: String,
+    /// value in the first cloud; NaN if it is not set there
+    pub a: f64,
+    /// value in the second cloud; NaN if it is not set there
+    pub b: f64,
+    /// |a - b| / max(|a|, |b|); infinite if the property is set in
+    /// only one of the clouds
+    pub relDiff: f64,
+}
+
+impl Cloud {
+    /// Scalar properties of the cloud, by name; None for optional
+    /// properties that are not set
+    pub(crate) fn scalars(&self) -> Vec<(String, Option<f64>)> {
+        let comp = &self.comp;
+        let dust = &self.dust;
+        let rad = &self.rad;
+        let mut fields: Vec<(String, Option<f32>)> = [
+            ("nH", Some(self.nH)),
+            ("colDen", Some(self.colDen)),
+            ("sigmaNT", Some(self.sigmaNT)),
+            ("dVdr", Some(self.dVdr)),
+            ("Tg", Some(self.Tg)),
+            ("Td", Some(self.Td)),
+            ("b_field", self.b_field),
+            ("comp.xHI", Some(comp.xHI)),
+            ("comp.xpH2", Some(comp.xpH2)),
+            ("comp.xoH2", Some(comp.xoH2)),
+            ("comp.xHe", Some(comp.xHe)),
+            ("comp.xe", Some(comp.xe)),
+            ("comp.xHplus", Some(comp.xHplus)),
+            ("comp.H2OPR", comp.H2OPR),
+            ("comp.mu", Some(comp.mu)),
+            ("comp.muH", Some(comp.muH)),
+            ("comp.qIon", Some(comp.qIon)),
+            ("comp.cv", comp.cv),
+            ("dust.alphaGD", Some(dust.alphaGD)),
+            ("dust.sigma10", Some(dust.sigma10)),
+            ("dust.sigmaPE", Some(dust.sigmaPE)),
+            ("dust.sigmaISRF", Some(dust.sigmaISRF)),
+            ("dust.Zd", Some(dust.Zd)),
+            ("dust.beta", Some(dust.beta)),
+            ("rad.TCMB", Some(rad.TCMB)),
+            ("rad.TradDust", Some(rad.TradDust)),
+            ("rad.fdDilute", Some(rad.fdDilute)),
+            ("rad.ionRate", Some(rad.ionRate)),
+            ("rad.cr_rate_h2", rad.cr_rate_h2),
+            ("rad.cr_rate_he", rad.cr_rate_he),
+            ("rad.chi", Some(rad.chi)),
+        ]
+        .into_iter()
+        .map(|(name, v)| (name.to_string(), v))
+        .collect();
+        fields.extend(self.emitters.iter().map(|(name, em)| {
+            (
+                format!("emitters.{name}.abundance"),
+                Some(em.abundance_in(self) as f32),
+            )
+        }));
+        fields
+            .into_iter()
+            .map(|(name, v)| (name, v.map(f64::from)))
+            .collect()
+    }
+
+    /// Compare the scalar properties of two clouds
+    ///
+    /// Parameters
+    ///    other : Cloud
+    ///       the cloud to compare against
+    ///    rtol : float
+    ///       relative tolerance; properties whose relative difference
+    ///       is no greater than this are not reported
+    ///
+    /// Returns
+    ///    the properties that differ, sorted by name
+    ///
+    /// Remarks
+    ///    The properties compared are the cloud's physical
+    ///    quantities, every field of its composition, dust and
+    ///    radiation field, and the abundance of each emitter. An
+    ///    optional property, or an emitter, present in only one of
+    ///    the clouds is always reported. Level populations, chemical
+    ///    networks and custom rates are not compared.
+    pub fn diff(&self, other: &Cloud, rtol: f64) -> Vec<FieldDiff> {
+        let mine = self.scalars();
+        let theirs = other.scalars();
+        let names: BTreeSet<&String> = mine.iter().chain(&theirs).map(|(n, _)| n).collect();
+        let lookup = |fields: &[(String, Option<f64>)], name: &str| {
+            fields.iter().find(|(n, _)| n == name).and_then(|(_, v)| *v)
+        };
+        names
+            .into_iter()
+            .filter_map(|name| {
+                let (a, b) = (lookup(&mine, name), lookup(&theirs, name));
+                let relDiff = match (a, b) {
+                    (None, None) => return None,
+                    (Some(a), Some(b)) if a == b => 0.0,
+                    (Some(a), Some(b)) => (a - b).abs() / a.abs().max(b.abs()),
+                    _ => f64::INFINITY,
+                };
+                (relDiff > rtol || relDiff.is_nan()).then(|| FieldDiff {
+                    name: name.clone(),
+                    a: a.unwrap_or(f64::NAN),
+                    b: b.unwrap_or(f64::NAN),
+                    relDiff,
+                })
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testutil::co_cloud;
+
+    #[test]
+    fn reports_only_changed_fields() {
+        let a = co_cloud();
+        let mut b = co_cloud();
+        assert!(a.diff(&b, 0.0).is_empty());
+
+        b.Tg = 22.0;
+        let diff = a.diff(&b, 1.0e-6);
+        assert_eq!(diff.len(), 1);
+        assert_eq!(diff[0].name, "Tg");
+        assert_eq!((diff[0].a, diff[0].b), (20.0, 22.0));
+        assert!((diff[0].relDiff - 2.0 / 22.0).abs() < 1.0e-12);
+        assert!(a.diff(&b, 0.1).is_empty());
+
+        // A property set in only one cloud is always reported
+        b.Tg = a.Tg;
+        b.b_field = Some(1.0e-5);
+        let diff = a.diff(&b, 1.0);
+        assert_eq!(diff.len(), 1);
+        assert!(diff[0].a.is_nan() && diff[0].relDiff.is_infinite());
+    }
+}
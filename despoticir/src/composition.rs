@@ -0,0 +1,335 @@
+use crate::consts::eV;
+use crate::DespoticError;
+use std::collections::HashMap;
+
+/// Characteristic rotational temperature of H2, B/k_B, in K
+const THETA_ROT_H2: f64 = 85.3;
+/// Tolerance on the total hydrogen abundance, allowing for the
+/// precision of single-precision floats
+const H_BUDGET_TOL: f32 = 1.0e-5;
+
+/// Chemical composition of a cloud
+///
+/// All abundances are per H nucleus.
+#[derive(Debug, Clone, Default)]
+pub struct Composition {
+    /// abundance of atomic hydrogen
+    pub xHI: f32,
+    /// abundance of para-H2
+    pub xpH2: f32,
+    /// abundance of ortho-H2
+    pub xoH2: f32,
+    /// abundance of helium
+    pub xHe: f32,
+    /// abundance of free electrons
+    pub xe: f32,
+    /// abundance of H+
+    pub xHplus: f32,
+    /// ortho-to-para ratio of H2; None until it has been specified
+    pub H2OPR: Option<f32>,
+    /// mean mass per free particle, in units of H masses
+    pub mu: f32,
+    /// mean mass per H nucleus, in units of H masses
+    pub muH: f32,
+    /// energy added to the gas per primary ionization, in erg
+    pub qIon: f32,
+    /// specific heat at constant volume per H nucleus, in units of
+    /// k_B; None until it has been computed for some temperature
+    pub cv: Option<f32>,
+}
+
+impl Composition {
+    /// Total H2 abundance, summing ortho and para
+    pub fn xH2(&self) -> f32 {
+        self.xpH2 + self.xoH2
+    }
+
+    /// Set the total H2 abundance, splitting it between ortho- and
+    /// para-H2 according to the current ortho-to-para ratio
+    ///
+    /// Parameters
+    ///    xH2 : float
+    ///       total abundance of H2 per H nucleus
+    ///
+    /// Remarks
+    ///    If no ortho-to-para ratio has been set, the H2 is assumed
+    ///    to be entirely para
+    pub fn set_xH2(&mut self, xH2: f32) {
+        let opr = self.H2OPR.unwrap_or(0.0);
+        self.xpH2 = xH2 / (1.0 + opr);
+        self.xoH2 = xH2 * opr / (1.0 + opr);
+    }
+
+    /// Set the H2 ortho-to-para ratio, redistributing any H2 already
+    /// present between the ortho and para states
+    pub fn set_H2OPR(&mut self, opr: f32) {
+        let xH2 = self.xH2();
+        self.H2OPR = Some(opr);
+        self.set_xH2(xH2);
+    }
+
+    /// Set several abundances at once
+    ///
+    /// Parameters
+    ///    map : dict
+    ///       abundances per H nucleus, keyed by species: "HI", "pH2",
+    ///       "oH2", "He", "e" or "H+"; species not listed keep their
+    ///       current abundances
+    ///
+    /// Returns
+    ///    Nothing
+    ///
+    /// Remarks
+    ///    The new composition is checked with validate. If a key is
+    ///    not one of the species above, or the check fails, an error
+    ///    is returned and nothing is changed. If "pH2" or "oH2" is
+    ///    given and xpH2 is positive, H2OPR is set to xoH2 / xpH2, so
+    ///    that later calls to set_xH2 keep the new ratio. Derived
+    ///    quantities are not recomputed; call compute_derived and
+    ///    compute_cv afterward.
+    pub fn set_abundances(&mut self, map: &HashMap<String, f64>) -> Result<(), DespoticError> {
+        let mut comp = self.clone();
+        for (name, &x) in map {
+            let field = match name.as_str() {
+                "HI" => &mut comp.xHI,
+                "pH2" => &mut comp.xpH2,
+                "oH2" => &mut comp.xoH2,
+                "He" => &mut comp.xHe,
+                "e" => &mut comp.xe,
+                "H+" => &mut comp.xHplus,
+                _ => return Err(DespoticError::UnknownSpecies(name.clone())),
+            };
+            *field = x as f32;
+        }
+        if (map.contains_key("pH2") || map.contains_key("oH2")) && comp.xpH2 > 0.0 {
+            comp.H2OPR = Some(comp.xoH2 / comp.xpH2);
+        }
+        comp.validate()?;
+        *self = comp;
+        Ok(())
+    }
+
+    /// Compute the derived quantities mu, muH, and qIon
+    ///
+    /// Parameters
+    ///    nH : float
+    ///       number density of H nuclei, in cm^-3; this is needed
+    ///       because the heating per ionization in molecular gas
+    ///       depends on density
+    ///
+    /// Returns
+    ///    Nothing
+    ///
+    /// Remarks
+    ///    Heating per ionization in molecular gas follows the
+    ///    tabulation of Glassgold, Galli, & Padovani (2012); in atomic
+    ///    gas it follows Dalgarno, Yan, & Liu (1999)
+    pub fn compute_derived(&mut self, nH: f32) {
+        let mass = self.xHI + self.xHplus + 2.0 * self.xH2() + 4.0 * self.xHe;
+        let particles = self.xHI + self.xHplus + self.xH2() + self.xHe + self.xe;
+        self.muH = mass;
+        self.mu = if particles > 0.0 {
+            mass / particles
+        } else {
+            0.0
+        };
+
+        let qAtom = (6.5 + 26.4 * (self.xe as f64 / (self.xe as f64 + 0.07)).sqrt()) * eV;
+        let logn = (nH as f64).max(1.0e-30).log10();
+        let qMol = if logn < 2.0 {
+            10.0
+        } else if logn < 4.0 {
+            10.0 + 3.0 * (logn - 2.0) / 2.0
+        } else if logn < 7.0 {
+            13.0 + 4.0 * (logn - 4.0) / 3.0
+        } else if logn < 10.0 {
+            17.0 + (logn - 7.0) / 3.0
+        } else {
+            18.0
+        } * eV;
+        let fAtom = self.xHI as f64;
+        let fMol = 2.0 * self.xH2() as f64;
+        self.qIon = if fAtom + fMol > 0.0 {
+            ((fAtom * qAtom + fMol * qMol) / (fAtom + fMol)) as f32
+        } else {
+            qAtom as f32
+        };
+    }
+
+    /// Compute the specific heat at constant volume
+    ///
+    /// Parameters
+    ///    T : float
+    ///       gas kinetic temperature, in K
+    ///
+    /// Returns
+    ///    Nothing; the result is stored in cv
+    ///
+    /// Remarks
+    ///    Every free particle contributes 3/2 k_B from its
+    ///    translational degrees of freedom. Ortho- and para-H2 are
+    ///    treated as separate species that do not interconvert, and
+    ///    each contributes the rotational heat capacity computed from
+    ///    its own partition function. Vibrational excitation is
+    ///    neglected.
+    pub fn compute_cv(&mut self, T: f32) {
+        let particles = self.xHI + self.xHplus + self.xH2() + self.xHe + self.xe;
+        let cv = 1.5 * particles as f64
+            + self.xpH2 as f64 * h2_rot_cv(T as f64, 0)
+            + self.xoH2 as f64 * h2_rot_cv(T as f64, 1);
+        self.cv = Some(cv as f32);
+    }
+
+    /// Check that the composition is physically consistent
+    ///
+    /// Parameters
+    ///    None
+    ///
+    /// Returns
+    ///    Nothing
+    ///
+    /// Remarks
+    ///    An error is returned if any abundance is negative or not
+    ///    finite, if the ortho-to-para ratio is negative, or if the
+    ///    hydrogen abundances xHI + xH+ + 2 xH2 do not sum to unity
+    pub fn validate(&self) -> Result<(), DespoticError> {
+        let abundances = [
+            ("xHI", self.xHI),
+            ("xpH2", self.xpH2),
+            ("xoH2", self.xoH2),
+            ("xHe", self.xHe),
+            ("xe", self.xe),
+            ("xHplus", self.xHplus),
+        ];
+        for (name, x) in abundances {
+            if !x.is_finite() || x < 0.0 {
+                return Err(DespoticError::InvalidComposition(format!("{name} = {x}")));
+            }
+        }
+        if let Some(opr) = self.H2OPR {
+            if !opr.is_finite() || opr < 0.0 {
+                return Err(DespoticError::InvalidComposition(format!("H2OPR = {opr}")));
+            }
+        }
+        let xHtot = self.xHI + self.xHplus + 2.0 * self.xH2();
+        if (xHtot - 1.0).abs() > H_BUDGET_TOL {
+            return Err(DespoticError::HydrogenBudget(xHtot));
+        }
+        Ok(())
+    }
+}
+
+/// Rotational heat capacity of a single H2 molecule, in units of k_B,
+/// summing over rotational levels J = J0, J0 + 2, ...
+fn h2_rot_cv(T: f64, J0: usize) -> f64 {
+    let E0 = (J0 * (J0 + 1)) as f64 * THETA_ROT_H2;
+    let (mut Z, mut E, mut E2) = (0.0, 0.0, 0.0);
+    for J in (J0..40).step_by(2) {
+        // Level energies in K, measured relative to the lowest level
+        let EJ = (J * (J + 1)) as f64 * THETA_ROT_H2 - E0;
+        let w = (2 * J + 1) as f64 * (-EJ / T).exp();
+        Z += w;
+        E += w * EJ;
+        E2 += w * EJ * EJ;
+    }
+    let Emean = E / Z;
+    (E2 / Z - Emean * Emean) / (T * T)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::co_cloud;
+
+    fn molecular() -> Composition {
+        Composition {
+            xpH2: 0.125,
+            xoH2: 0.375,
+            xHe: 0.1,
+            H2OPR: Some(3.0),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn validate_failure_modes() {
+        assert!(molecular().validate().is_ok());
+
+        let comp = Composition {
+            xe: -1.0e-4,
+            ..molecular()
+        };
+        assert!(
+            matches!(comp.validate(), Err(DespoticError::InvalidComposition(m)) if m.starts_with("xe"))
+        );
+        let comp = Composition {
+            xHe: f32::NAN,
+            ..molecular()
+        };
+        assert!(
+            matches!(comp.validate(), Err(DespoticError::InvalidComposition(m)) if m.starts_with("xHe"))
+        );
+        let comp = Composition {
+            H2OPR: Some(-0.25),
+            ..molecular()
+        };
+        assert!(
+            matches!(comp.validate(), Err(DespoticError::InvalidComposition(m)) if m.starts_with("H2OPR"))
+        );
+        let comp = Composition {
+            xHI: 0.5,
+            ..molecular()
+        };
+        assert!(matches!(comp.validate(), Err(DespoticError::HydrogenBudget(x)) if x == 1.5));
+    }
+
+    #[test]
+    fn physics_rejects_invalid_composition() {
+        let mut cloud = co_cloud();
+        cloud.comp.xHI = 0.5;
+        assert!(matches!(
+            cloud.dedt(),
+            Err(DespoticError::HydrogenBudget(_))
+        ));
+    }
+
+    #[test]
+    fn set_abundances_from_map() {
+        let map = |pairs: &[(&str, f64)]| {
+            pairs
+                .iter()
+                .map(|&(k, v)| (k.to_string(), v))
+                .collect::<HashMap<_, _>>()
+        };
+        let mut comp = Composition::default();
+        comp.set_abundances(&map(&[
+            ("pH2", 0.125),
+            ("oH2", 0.375),
+            ("He", 0.1),
+            ("e", 1.0e-8),
+        ]))
+        .unwrap();
+        assert_eq!(
+            (comp.xpH2, comp.xoH2, comp.xHe, comp.xe),
+            (0.125, 0.375, 0.1, 1.0e-8)
+        );
+        assert!(comp.validate().is_ok());
+
+        // The ortho-to-para ratio follows the new H2 abundances
+        assert_eq!(comp.H2OPR, Some(3.0));
+        let mut halved = comp.clone();
+        halved.set_xH2(0.25);
+        assert_eq!((halved.xpH2, halved.xoH2), (0.0625, 0.1875));
+
+        // A bad key or a broken budget leaves the composition alone
+        assert!(matches!(
+            comp.set_abundances(&map(&[("CO", 1.0e-4)])),
+            Err(DespoticError::UnknownSpecies(_))
+        ));
+        assert!(matches!(
+            comp.set_abundances(&map(&[("HI", 0.5)])),
+            Err(DespoticError::HydrogenBudget(_))
+        ));
+        assert_eq!((comp.xHI, comp.xoH2), (0.0, 0.375));
+    }
+}
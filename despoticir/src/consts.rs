@@ -0,0 +1,22 @@
+//! Physical constants, in CGS units
+
+#![allow(non_upper_case_globals)]
+
+/// Boltzmann constant, in erg K^-1
+pub const kB: f64 = 1.380649e-16;
+/// Planck constant, in erg s
+pub const h: f64 = 6.62607015e-27;
+/// speed of light, in cm s^-1
+pub const c: f64 = 2.99792458e10;
+/// mass of a hydrogen atom, in g
+pub const mH: f64 = 1.6735575e-24;
+/// gravitational constant, in cm^3 g^-1 s^-2
+pub const G: f64 = 6.6743e-8;
+/// radiation constant, in erg cm^-3 K^-4
+pub const a: f64 = 7.565733e-15;
+/// electron volt, in erg
+pub const eV: f64 = 1.602176634e-12;
+/// solar mass, in g
+pub const Msun: f64 = 1.98841e33;
+/// parsec, in cm
+pub const pc: f64 = 3.0856776e18;
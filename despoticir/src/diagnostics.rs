@@ -0,0 +1,354 @@
+//! Derived physical properties of a cloud
+
+use crate::cloud::Cloud;
+use crate::consts::{kB, mH, G};
+use crate::DespoticError;
+
+/// Column density of H nuclei per magnitude of visual extinction for
+/// Milky Way dust, in cm^-2 (Bohlin, Savage & Drake 1978)
+const NH_PER_AV: f64 = 1.87e21;
+
+/// Rate coefficient for conversion of ortho- to para-H2 by proton
+/// exchange with H+, in cm^3 s^-1 (Gerlich 1990)
+const K_OP_HPLUS: f64 = 2.2e-10;
+/// Energy of the lowest ortho-H2 level above the para-H2 ground
+/// state, in K
+const E_ORTHO_K: f64 = 170.5;
+
+impl Cloud {
+    /// Mass density of the gas
+    ///
+    /// Parameters
+    ///    None
+    ///
+    /// Returns
+    ///    rho = muH mH nH, in g cm^-3
+    ///
+    /// Remarks
+    ///    muH is a derived quantity of the composition; if it has not
+    ///    been computed, the density is zero
+    pub fn rho(&self) -> f64 {
+        self.comp.muH as f64 * mH * self.nH as f64
+    }
+
+    /// Thermal pressure of the gas
+    ///
+    /// Parameters
+    ///    None
+    ///
+    /// Returns
+    ///    thermal pressure, in dyn cm^-2
+    ///
+    /// Remarks
+    ///    The number density of free particles is nH muH / mu, so the
+    ///    composition's derived quantities must have been computed;
+    ///    if they have not, the pressure is zero.
+    pub fn thermal_pressure(&self) -> f64 {
+        let comp = &self.comp;
+        if comp.mu <= 0.0 {
+            return 0.0;
+        }
+        let n = self.nH as f64 * comp.muH as f64 / comp.mu as f64;
+        n * kB * self.Tg as f64
+    }
+
+    /// Turbulent pressure of the gas
+    ///
+    /// Parameters
+    ///    None
+    ///
+    /// Returns
+    ///    turbulent pressure rho sigmaNT^2, in dyn cm^-2
+    pub fn turbulent_pressure(&self) -> f64 {
+        self.rho() * self.sigmaNT as f64 * self.sigmaNT as f64
+    }
+
+    /// Ratio of thermal to turbulent pressure
+    ///
+    /// Parameters
+    ///    None
+    ///
+    /// Returns
+    ///    thermal_pressure / turbulent_pressure; infinite if the gas
+    ///    has no nonthermal velocity dispersion
+    ///
+    /// Remarks
+    ///    A ZeroDensity error is returned if nH is not positive, since
+    ///    both pressures are then zero
+    pub fn pressure_ratio(&self) -> Result<f64, DespoticError> {
+        self.check_density()?;
+        Ok(self.thermal_pressure() / self.turbulent_pressure())
+    }
+
+    /// Mach number of the non-thermal motions
+    ///
+    /// Parameters
+    ///    None
+    ///
+    /// Returns
+    ///    three-dimensional Mach number sqrt(3) sigmaNT / c_s, where
+    ///    c_s = sqrt(kB Tg / (mu mH)) is the isothermal sound speed
+    ///
+    /// Remarks
+    ///    sigmaNT is a one-dimensional velocity dispersion, hence the
+    ///    factor sqrt(3). As for thermal_pressure, the composition's
+    ///    derived quantities must have been computed; if they have
+    ///    not, the result is NaN.
+    pub fn mach_number(&self) -> f64 {
+        if self.comp.mu <= 0.0 {
+            return f64::NAN;
+        }
+        let cs = (kB * self.Tg as f64 / (self.comp.mu as f64 * mH)).sqrt();
+        3.0_f64.sqrt() * self.sigmaNT as f64 / cs
+    }
+
+    /// Effective signal speed of the gas, including magnetic support
+    ///
+    /// Parameters
+    ///    None
+    ///
+    /// Returns
+    ///    sqrt(c_s^2 + sigmaNT^2 + v_A^2), in cm s^-1, where c_s is the
+    ///    isothermal sound speed and v_A = B / sqrt(4 pi rho) the
+    ///    Alfven speed
+    ///
+    /// Remarks
+    ///    If b_field is None the Alfven term is omitted. A
+    ///    ZeroDensity error is returned if nH is not positive, and a
+    ///    MissingField error if comp.mu has not been computed.
+    pub fn magnetosonic_speed(&self) -> Result<f64, DespoticError> {
+        self.check_density()?;
+        if self.comp.mu <= 0.0 {
+            return Err(DespoticError::MissingField("mu".to_string()));
+        }
+        let cs2 = kB * self.Tg as f64 / (self.comp.mu as f64 * mH);
+        let vA2 = self.b_field.map_or(0.0, |B| {
+            (B as f64).powi(2) / (4.0 * std::f64::consts::PI * self.rho())
+        });
+        Ok((cs2 + (self.sigmaNT as f64).powi(2) + vA2).sqrt())
+    }
+
+    /// Width of the lognormal density PDF of supersonic turbulence
+    ///
+    /// Parameters
+    ///    b : float
+    ///       turbulent forcing parameter, from 1/3 for purely
+    ///       solenoidal to 1 for purely compressive driving
+    ///
+    /// Returns
+    ///    dispersion of ln(rho / rho_mean), sqrt(ln(1 + b^2 M^2)),
+    ///    where M is mach_number (Padoan & Nordlund 2011; Federrath
+    ///    et al. 2008)
+    pub fn density_pdf_sigma(&self, b: f64) -> f64 {
+        let M = self.mach_number();
+        (1.0 + b * b * M * M).ln().sqrt()
+    }
+
+    /// Virial parameter of the cloud
+    ///
+    /// Parameters
+    ///    None
+    ///
+    /// Returns
+    ///    alpha_vir = 5 sigma_tot^2 R / (G M)
+    ///
+    /// Remarks
+    ///    The cloud is treated as a uniform sphere of radius R =
+    ///    colDen / nH and mass M = (4/3) pi R^3 muH mH nH. The
+    ///    velocity dispersion sigma_tot includes the isothermal sound
+    ///    speed as well as sigmaNT. Clouds with alpha_vir of order 1
+    ///    to 2 are marginally bound. A ZeroDensity error is returned
+    ///    if nH is not positive, and a MissingField error if comp.mu
+    ///    has not been computed.
+    pub fn virial_parameter(&self) -> Result<f64, DespoticError> {
+        self.check_density()?;
+        if self.comp.mu <= 0.0 {
+            return Err(DespoticError::MissingField("mu".to_string()));
+        }
+        let R = self.colDen as f64 / self.nH as f64;
+        let M = 4.0 / 3.0 * std::f64::consts::PI * R.powi(3) * self.rho();
+        let cs2 = kB * self.Tg as f64 / (self.comp.mu as f64 * mH);
+        let sigma2 = self.sigmaNT as f64 * self.sigmaNT as f64 + cs2;
+        Ok(5.0 * sigma2 * R / (G * M))
+    }
+
+    /// Visual extinction through the cloud
+    ///
+    /// Parameters
+    ///    None
+    ///
+    /// Returns
+    ///    A_V, in magnitudes
+    ///
+    /// Remarks
+    ///    Uses the Milky Way ratio of column density to extinction,
+    ///    scaled by the dust abundance Zd
+    pub fn a_v(&self) -> f64 {
+        self.colDen as f64 * self.dust.Zd as f64 / NH_PER_AV
+    }
+
+    /// Optical depth through the cloud at V band
+    ///
+    /// Parameters
+    ///    None
+    ///
+    /// Returns
+    ///    tau_V = A_V / 1.086
+    pub fn tau_v(&self) -> f64 {
+        self.a_v() / (2.5 * std::f64::consts::LOG10_E)
+    }
+
+    /// Timescale for the H2 ortho-to-para ratio to relax to
+    /// equilibrium by proton exchange
+    ///
+    /// Parameters
+    ///    None
+    ///
+    /// Returns
+    ///    1 / (n(H+) (k_op + k_po)), in s; infinite if there are no
+    ///    free protons
+    ///
+    /// Remarks
+    ///    k_op is the ortho-to-para rate coefficient of Gerlich
+    ///    (1990), and k_po = 9 k_op exp(-170.5 K / Tg) follows from
+    ///    detailed balance between the J = 0 and J = 1 levels. The
+    ///    result is the e-folding time of the departure of the
+    ///    ortho-to-para ratio from its equilibrium value at Tg. A
+    ///    ZeroDensity error is returned if nH is not positive.
+    pub fn opr_conversion_time(&self) -> Result<f64, DespoticError> {
+        self.check_density()?;
+        let nHplus = self.nH as f64 * self.comp.xHplus as f64;
+        let kPO = 9.0 * K_OP_HPLUS * (-E_ORTHO_K / self.Tg as f64).exp();
+        Ok(1.0 / (nHplus * (K_OP_HPLUS + kPO)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::consts::{kB, mH, G};
+    use crate::testutil::{co_cloud, read_cloud};
+    use crate::DespoticError;
+
+    #[test]
+    fn diffuse_cloud_pressure() {
+        let cloud =
+            read_cloud("nH = 30\nxHI = 1\nxHe = 0.1\nxe = 0\nTg = 100\nsigmaNT = 1e5\n").unwrap();
+
+        // P / k = 1.1 nH Tg for atomic gas with 10% He
+        let Pk = cloud.thermal_pressure() / crate::consts::kB;
+        assert!((Pk / 3300.0 - 1.0).abs() < 1.0e-3, "P/k = {Pk}");
+        let Pturb = cloud.turbulent_pressure();
+        assert!((Pturb / (cloud.rho() * 1.0e10) - 1.0).abs() < 1.0e-6);
+        assert_eq!(
+            cloud.pressure_ratio().unwrap(),
+            cloud.thermal_pressure() / Pturb
+        );
+    }
+
+    #[test]
+    fn milky_way_extinction() {
+        let mut cloud = co_cloud();
+        cloud.colDen = 1.87e21;
+        assert!((cloud.a_v() - 1.0).abs() < 1.0e-6);
+        assert!((cloud.tau_v() * 1.0857 - 1.0).abs() < 1.0e-4);
+        cloud.dust.Zd = 0.5;
+        assert!((cloud.a_v() - 0.5).abs() < 1.0e-6);
+    }
+
+    #[test]
+    fn density_pdf_width_at_mach_10() {
+        let mut cloud = co_cloud();
+        let cs = (kB * cloud.Tg as f64 / (cloud.comp.mu as f64 * mH)).sqrt();
+        cloud.sigmaNT = (10.0 * cs / 3.0_f64.sqrt()) as f32;
+        assert!((cloud.mach_number() - 10.0).abs() < 1.0e-5);
+        let expected = 26.0_f64.ln().sqrt();
+        assert!((cloud.density_pdf_sigma(0.5) - expected).abs() < 1.0e-5);
+    }
+
+    #[test]
+    fn marginally_bound_cloud() {
+        // A 3 pc cloud of density 1e3 cm^-3 with a 1.3 km/s velocity
+        // dispersion is marginally bound
+        let mut cloud = co_cloud();
+        cloud.sigmaNT = 1.3e5;
+        let alpha = cloud.virial_parameter().unwrap();
+        assert!(alpha > 1.0 && alpha < 2.0, "alpha_vir = {alpha}");
+
+        // alpha_vir = 15 sigma^2 / (4 pi G rho R^2) for a uniform sphere
+        let R = (cloud.colDen / cloud.nH) as f64;
+        let cs2 = kB * cloud.Tg as f64 / (cloud.comp.mu as f64 * mH);
+        let sigma2 = 1.3e5_f64.powi(2) + cs2;
+        let expected = 15.0 * sigma2 / (4.0 * std::f64::consts::PI * G * cloud.rho() * R * R);
+        assert!((alpha / expected - 1.0).abs() < 1.0e-6);
+    }
+
+    #[test]
+    fn opr_conversion_faster_when_more_ionized() {
+        let mut cloud = co_cloud();
+        cloud.comp.xHplus = 1.0e-8;
+        let slow = cloud.opr_conversion_time().unwrap();
+        cloud.comp.xHplus = 1.0e-6;
+        let fast = cloud.opr_conversion_time().unwrap();
+        assert!(
+            fast < slow,
+            "t_op = {fast} s at xH+ = 1e-6, {slow} s at 1e-8"
+        );
+        assert!((slow / fast / 100.0 - 1.0).abs() < 1.0e-5);
+
+        cloud.comp.xHplus = 0.0;
+        assert!(cloud.opr_conversion_time().unwrap().is_infinite());
+    }
+
+    #[test]
+    fn atomic_ism_density() {
+        // muH = 1.4 for atomic gas with 10% He by number
+        let cloud = read_cloud("nH = 30\nxHI = 1\nxHe = 0.1\nxe = 0\nTg = 100\n").unwrap();
+        let expected = 1.4 * mH * 30.0;
+        assert!(
+            (cloud.rho() / expected - 1.0).abs() < 1.0e-3,
+            "rho = {}",
+            cloud.rho()
+        );
+    }
+
+    #[test]
+    fn zero_density_diagnostics_are_errors() {
+        let mut cloud = co_cloud();
+        cloud.sigmaNT = 1.0e5;
+        cloud.nH = 0.0;
+        let zero = |r: Result<f64, DespoticError>| matches!(r, Err(DespoticError::ZeroDensity));
+        assert!(zero(cloud.pressure_ratio()));
+        assert!(zero(cloud.virial_parameter()));
+        assert!(zero(cloud.opr_conversion_time()));
+    }
+
+    #[test]
+    fn missing_mu_is_an_error() {
+        let mut cloud = co_cloud();
+        cloud.comp.mu = 0.0;
+        let missing = |r: Result<f64, DespoticError>| matches!(r, Err(DespoticError::MissingField(f)) if f == "mu");
+        assert!(missing(cloud.magnetosonic_speed()));
+        assert!(missing(cloud.virial_parameter()));
+    }
+
+    #[test]
+    fn magnetosonic_speed_without_field() {
+        let mut cloud = co_cloud();
+        assert_eq!(cloud.b_field, None);
+        let cs2 = kB * cloud.Tg as f64 / (cloud.comp.mu as f64 * mH);
+        let hydro = (cs2 + (cloud.sigmaNT as f64).powi(2)).sqrt();
+        assert!((cloud.magnetosonic_speed().unwrap() / hydro - 1.0).abs() < 1.0e-12);
+
+        // A 10 microgauss field adds the Alfven speed in quadrature
+        cloud.b_field = Some(1.0e-5);
+        let vA2 = 1.0e-10 / (4.0 * std::f64::consts::PI * cloud.rho());
+        let expected = (hydro * hydro + vA2).sqrt();
+        assert!((cloud.magnetosonic_speed().unwrap() / expected - 1.0).abs() < 1.0e-6);
+
+        cloud.nH = 0.0;
+        assert!(matches!(
+            cloud.magnetosonic_speed(),
+            Err(DespoticError::ZeroDensity)
+        ));
+    }
+}
@@ -0,0 +1,303 @@
+use crate::consts::{h, kB};
+use crate::roots::{bracket_outward, brent};
+use crate::{DespoticError, Radiation};
+
+/// Number of points used in numerical integrals over frequency
+const N_NU: usize = 2000;
+
+/// Dust-to-gas mass ratio of the Milky Way, to which Zd is normalized
+pub const DUST_TO_GAS_MW: f64 = 0.01;
+
+/// Range of log10 Td, in K, searched when fitting a dust temperature
+const LOG_TD_MIN: f64 = 0.0;
+const LOG_TD_MAX: f64 = 4.0;
+
+/// Properties of the dust in a cloud
+#[derive(Debug, Clone)]
+pub struct DustProp {
+    /// gas-dust energy exchange coefficient, in erg cm^3 K^-3/2
+    pub alphaGD: f32,
+    /// dust cross section per H nucleus to thermal radiation at
+    /// 10 K, in cm^2 H^-1
+    pub sigma10: f32,
+    /// dust cross section per H nucleus to the FUV photons that drive
+    /// photoelectric heating, in cm^2 H^-1
+    pub sigmaPE: f32,
+    /// dust cross section per H nucleus to ISRF photons, in cm^2 H^-1
+    pub sigmaISRF: f32,
+    /// dust abundance relative to the Milky Way value
+    pub Zd: f32,
+    /// spectral index of the dust opacity, kappa ~ nu^beta
+    pub beta: f32,
+}
+
+impl Default for DustProp {
+    fn default() -> Self {
+        Self {
+            alphaGD: 3.2e-34,
+            sigma10: 2.0e-25,
+            sigmaPE: 1.0e-21,
+            sigmaISRF: 3.0e-22,
+            Zd: 1.0,
+            beta: 2.0,
+        }
+    }
+}
+
+/// Planck-weighted mean of x^beta, where x = h nu / kT
+fn planck_mean_xpow(beta: f64) -> f64 {
+    // Integrate x^(3+beta) / (e^x - 1) in log x, and normalize by the
+    // same integral for beta = 0, which is pi^4 / 15
+    let (lnx0, lnx1) = (1.0e-4_f64.ln(), 100.0_f64.ln());
+    let dlnx = (lnx1 - lnx0) / (N_NU - 1) as f64;
+    let f = |i: usize| {
+        let x = (lnx0 + i as f64 * dlnx).exp();
+        x.powf(4.0 + beta) / x.exp_m1()
+    };
+    let integral = (0..N_NU).map(f).sum::<f64>() - 0.5 * (f(0) + f(N_NU - 1));
+    integral * dlnx / (std::f64::consts::PI.powi(4) / 15.0)
+}
+
+impl DustProp {
+    /// Dust cross section per H nucleus at a given frequency
+    ///
+    /// Parameters
+    ///    nu : float
+    ///       frequency, in Hz
+    ///
+    /// Returns
+    ///    cross section per H nucleus, in cm^2 H^-1
+    ///
+    /// Remarks
+    ///    The cross section scales as nu^beta, normalized so that its
+    ///    Planck mean at 10 K is Zd * sigma10
+    pub fn sigma_nu(&self, nu: f64) -> f64 {
+        self.sigma_nu_scale() * (h * nu / (kB * 10.0)).powf(self.beta as f64)
+    }
+
+    /// Dust optical depth at a given frequency
+    ///
+    /// Parameters
+    ///    nu : float
+    ///       frequency, in Hz
+    ///    col_den : float
+    ///       column density of H nuclei, in cm^-2
+    ///
+    /// Returns
+    ///    optical depth tau_nu through the column
+    ///
+    /// Remarks
+    ///    tau_nu = kappa_nu Sigma_dust, where kappa_nu is the opacity
+    ///    per unit dust mass and Sigma_dust = dust_to_gas muH m_H
+    ///    col_den. The dust mass per H nucleus cancels between the
+    ///    two, so this is sigma_nu(nu) col_den.
+    pub fn tau(&self, nu: f64, col_den: f64) -> f64 {
+        self.sigma_nu(nu) * col_den
+    }
+
+    /// Coefficient of (h nu / k_B 10 K)^beta in sigma_nu
+    fn sigma_nu_scale(&self) -> f64 {
+        self.Zd as f64 * self.sigma10 as f64 / planck_mean_xpow(self.beta as f64)
+    }
+
+    /// Dust-to-gas mass ratio
+    ///
+    /// Parameters
+    ///    None
+    ///
+    /// Returns
+    ///    mass of dust per unit mass of gas, Zd times the Milky Way
+    ///    value of 0.01
+    pub fn dust_to_gas(&self) -> f64 {
+        self.Zd as f64 * DUST_TO_GAS_MW
+    }
+
+    /// Dust temperature that gives an observed color
+    ///
+    /// Parameters
+    ///    nu1, nu2 : float
+    ///       frequencies of the two bands, in Hz
+    ///    flux_ratio : float
+    ///       observed ratio of the flux at nu1 to that at nu2
+    ///
+    /// Returns
+    ///    dust temperature Td, in K, for which the optically thin
+    ///    modified blackbody nu^beta B_nu(Td) has the given ratio
+    ///
+    /// Remarks
+    ///    Td is searched for between 1 and 10^4 K. The ratio tends to
+    ///    the Rayleigh-Jeans value (nu1 / nu2)^(beta + 2) at high
+    ///    temperature, so colors near that limit constrain Td only
+    ///    weakly. A NoConvergence error is returned if no temperature
+    ///    in the range gives the ratio, or if a frequency or the ratio
+    ///    is not positive.
+    pub fn temp_from_color(
+        &self,
+        nu1: f64,
+        nu2: f64,
+        flux_ratio: f64,
+    ) -> Result<f64, DespoticError> {
+        let fail = || {
+            DespoticError::NoConvergence(format!(
+                "no dust temperature gives a flux ratio of {flux_ratio} between {nu1:e} and {nu2:e} Hz"
+            ))
+        };
+        if !(nu1 > 0.0 && nu2 > 0.0 && flux_ratio > 0.0) {
+            return Err(fail());
+        }
+        // ln of nu^(3 + beta) / (e^(h nu / kT) - 1), safe from
+        // overflow at low temperature
+        let beta = self.beta as f64;
+        let lnFlux = |nu: f64, T: f64| {
+            let x = h * nu / (kB * T);
+            let lnExpm1 = if x > 50.0 { x } else { x.exp_m1().ln() };
+            (3.0 + beta) * nu.ln() - lnExpm1
+        };
+        let target = flux_ratio.ln();
+        let resid = |logT: f64| {
+            let T = 10.0_f64.powf(logT);
+            Ok::<_, DespoticError>(lnFlux(nu1, T) - lnFlux(nu2, T) - target)
+        };
+        let Some((lo, hi)) =
+            bracket_outward(resid, 20.0_f64.log10(), 0.25, (LOG_TD_MIN, LOG_TD_MAX))?
+        else {
+            return Err(fail());
+        };
+        let logT = brent(resid, lo, hi, 1.0e-10, 100)?.ok_or_else(fail)?;
+        Ok(10.0_f64.powf(logT))
+    }
+
+    /// Mean dust cross section weighted by the spectrum of a
+    /// radiation field
+    ///
+    /// Parameters
+    ///    radiation : Radiation
+    ///       the radiation field; its CMB, dust-reprocessed IR, and
+    ///       ISRF components are all included
+    ///
+    /// Returns
+    ///    the intensity-weighted mean cross section per H nucleus, in
+    ///    cm^2 H^-1; zero if the field is empty
+    ///
+    /// Remarks
+    ///    The ISRF has the shape given by Radiation::isrf; see Isrf.
+    ///    For a pure blackbody field at temperature T this is the
+    ///    Planck-mean cross section, Zd sigma10 (T / 10 K)^beta. The
+    ///    nu^beta law of sigma_nu is used at all frequencies, so
+    ///    where starlight dominates the weight the result reflects
+    ///    its extrapolation to optical frequencies.
+    pub fn mean_opacity(&self, radiation: &Radiation) -> f64 {
+        let Some((nu0, nu1)) = radiation.band() else {
+            return 0.0;
+        };
+
+        // Integrate nu J_nu and sigma nu J_nu in log nu
+        let (lnnu0, lnnu1) = (nu0.ln(), nu1.ln());
+        let dlnnu = (lnnu1 - lnnu0) / (N_NU - 1) as f64;
+        let scale = self.sigma_nu_scale();
+        let (mut num, mut den) = (0.0, 0.0);
+        for i in 0..N_NU {
+            let nu = (lnnu0 + i as f64 * dlnnu).exp();
+            let wt = if i == 0 || i == N_NU - 1 { 0.5 } else { 1.0 };
+            let nuJnu = nu * radiation.mean_intensity(nu);
+            let sigma = scale * (h * nu / (kB * 10.0)).powf(self.beta as f64);
+            num += wt * sigma * nuJnu;
+            den += wt * nuJnu;
+        }
+        if den > 0.0 {
+            num / den
+        } else {
+            0.0
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A field that is a pure blackbody at temperature T
+    fn blackbody(T: f32) -> Radiation {
+        Radiation {
+            TCMB: T,
+            chi: 0.0,
+            ..Radiation::default()
+        }
+    }
+
+    #[test]
+    fn planck_mean_of_blackbody() {
+        let dust = DustProp::default();
+        for T in [2.73, 10.0, 30.0] {
+            let expected = dust.Zd as f64 * dust.sigma10 as f64 * (T as f64 / 10.0).powi(2);
+            let mean = dust.mean_opacity(&blackbody(T));
+            assert!((mean / expected - 1.0).abs() < 1.0e-3, "T = {T}: {mean:e}");
+        }
+    }
+
+    #[test]
+    fn isrf_raises_mean_opacity() {
+        let dust = DustProp::default();
+        let cmb = dust.mean_opacity(&blackbody(2.73));
+        let rad = Radiation {
+            chi: 1.0,
+            ..blackbody(2.73)
+        };
+        let withIsrf = dust.mean_opacity(&rad);
+        assert!(withIsrf > 1.0e3 * cmb);
+        let doubled = dust.mean_opacity(&Radiation { chi: 2.0, ..rad });
+        assert!(doubled > withIsrf);
+    }
+
+    #[test]
+    fn custom_isrf_is_weighted() {
+        // A narrow flat spectrum with no other components gives the
+        // cross section near its frequency
+        let dust = DustProp::default();
+        let nu = 1.0e12;
+        let rad = Radiation {
+            TCMB: 0.0,
+            isrf: crate::radiation::Isrf::custom(&[(0.999 * nu, 1.0), (1.001 * nu, 1.0)]).unwrap(),
+            ..Radiation::default()
+        };
+        let mean = dust.mean_opacity(&rad);
+        assert!((mean / dust.sigma_nu(nu) - 1.0).abs() < 1.0e-2, "{mean:e}");
+        assert_eq!(
+            dust.mean_opacity(&Radiation {
+                chi: 0.0,
+                ..blackbody(0.0)
+            }),
+            0.0
+        );
+    }
+
+    #[test]
+    fn far_ir_optical_depth() {
+        let dust = DustProp::default();
+        let nu = crate::consts::c / 100.0e-4;
+        let tau = dust.tau(nu, 1.0e25);
+        assert!(tau > 1.0, "tau(100 um) = {tau}");
+        assert!((dust.tau(nu, 1.0e24) / tau - 0.1).abs() < 1.0e-12);
+    }
+
+    #[test]
+    fn temperature_from_color() {
+        let dust = DustProp::default();
+        let (nu1, nu2) = (crate::consts::c / 160.0e-4, crate::consts::c / 500.0e-4);
+        let flux =
+            |nu: f64, T: f64| nu.powf(dust.beta as f64) * crate::lines::j_nu(T, nu) * nu * nu;
+        for Td in [8.0, 20.0, 45.0] {
+            let ratio = flux(nu1, Td) / flux(nu2, Td);
+            let fit = dust.temp_from_color(nu1, nu2, ratio).unwrap();
+            assert!((fit / Td - 1.0).abs() < 1.0e-6, "Td = {Td}, fit {fit}");
+        }
+
+        // Bluer than any modified blackbody can be
+        let rj = (nu1 / nu2).powf(dust.beta as f64 + 2.0);
+        assert!(matches!(
+            dust.temp_from_color(nu1, nu2, 2.0 * rj),
+            Err(DespoticError::NoConvergence(_))
+        ));
+        assert!(dust.temp_from_color(nu1, nu2, -1.0).is_err());
+    }
+}
@@ -0,0 +1,975 @@
+use crate::cloud::Cloud;
+use crate::consts::{c, h, kB};
+use crate::emitter_data::{EmitterData, Partner};
+use crate::energy::CoolingMode;
+use crate::memo::{MemoEntry, PopMemo};
+use crate::sum::kahan_sum;
+use crate::DespoticError;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+/// Maximum number of iterations between level populations and escape
+/// probabilities
+const MAX_ITER: usize = 200;
+/// Relative tolerance on level populations
+const POP_TOL: f64 = 1.0e-6;
+/// Weight given to the newest iterate when updating populations
+const DAMP: f64 = 0.5;
+/// Magnitude above which a negative level population is taken to
+/// signal a real failure of the solver rather than round-off
+const NEG_POP_TOL: f64 = 1.0e-8;
+
+/// Geometry assumed when computing escape probabilities
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EscapeGeometry {
+    /// uniform slab; optical depths are computed from the column
+    /// density and the line width
+    #[default]
+    Slab,
+    /// large velocity gradient; optical depths are computed from the
+    /// volume density and the velocity gradient
+    Lvg,
+    /// uniform sphere; optical depths are computed as for a slab,
+    /// taking the column density from center to edge
+    Sphere,
+}
+
+impl EscapeGeometry {
+    /// Escape probability for a line-center optical depth tau
+    ///
+    /// Remarks
+    ///    Transitions with negative optical depth (masers) are treated
+    ///    as optically thin
+    pub fn beta(self, tau: f64) -> f64 {
+        if tau <= 0.0 {
+            return 1.0;
+        }
+        let t = match self {
+            Self::Slab => 3.0 * tau,
+            Self::Lvg => tau,
+            Self::Sphere => return sphere_beta(tau),
+        };
+        if t < 1.0e-6 {
+            1.0 - t / 2.0
+        } else {
+            -(-t).exp_m1() / t
+        }
+    }
+}
+
+/// Escape probability from a uniform sphere of line-center optical
+/// depth tau, (3/2 tau) [1 - 2/tau^2 + (2/tau + 2/tau^2) e^-tau]
+fn sphere_beta(tau: f64) -> f64 {
+    // The bracketed terms cancel to O(tau) at small tau, so use the
+    // series expansion there
+    if tau < 1.0e-2 {
+        1.0 - tau * (3.0 / 8.0 - tau * (1.0 / 10.0 - tau / 48.0))
+    } else {
+        let tau2 = tau * tau;
+        1.5 / tau * (1.0 - 2.0 / tau2 + (2.0 / tau + 2.0 / tau2) * (-tau).exp())
+    }
+}
+
+/// Outcome of a level population solve
+#[derive(Debug, Clone, Default)]
+struct LastSolve {
+    /// converged populations; None if the solve did not converge
+    pops: Option<Vec<f64>>,
+    /// number of iterations taken
+    iterations: usize,
+    /// escape probabilities used in the final iteration
+    beta: Option<Vec<f64>>,
+}
+
+/// Result of the most recent level population solve, kept so that the
+/// next solve can start from it
+#[derive(Debug, Default)]
+struct PopCache(Mutex<LastSolve>);
+
+impl PopCache {
+    fn get(&self) -> LastSolve {
+        self.0.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    fn set(&self, last: LastSolve) {
+        *self.0.lock().unwrap_or_else(|e| e.into_inner()) = last;
+    }
+}
+
+impl Clone for PopCache {
+    fn clone(&self) -> Self {
+        Self(Mutex::new(self.get()))
+    }
+}
+
+/// Abundance of an emitting species per H nucleus
+#[derive(Clone)]
+pub enum Abundance {
+    /// the same abundance in every cloud
+    Constant(f32),
+    /// abundance evaluated from the properties of each cloud, e.g. to
+    /// follow depth or density through the zones of a slab model
+    Profile(Arc<dyn Fn(&Cloud) -> f64 + Send + Sync>),
+}
+
+impl Abundance {
+    /// Abundance in a given cloud
+    pub fn value(&self, cloud: &Cloud) -> f64 {
+        match self {
+            Self::Constant(x) => *x as f64,
+            Self::Profile(f) => f(cloud),
+        }
+    }
+
+    /// The abundance divided by factor, wherever it is evaluated
+    pub fn scaled_down(&self, factor: f64) -> Self {
+        match self {
+            Self::Constant(x) => Self::Constant((*x as f64 / factor) as f32),
+            Self::Profile(f) => {
+                let f = f.clone();
+                Self::Profile(Arc::new(move |cloud| f(cloud) / factor))
+            }
+        }
+    }
+}
+
+impl From<f32> for Abundance {
+    fn from(x: f32) -> Self {
+        Self::Constant(x)
+    }
+}
+
+impl std::fmt::Debug for Abundance {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Constant(x) => f.debug_tuple("Constant").field(x).finish(),
+            Self::Profile(_) => f.debug_tuple("Profile").finish_non_exhaustive(),
+        }
+    }
+}
+
+/// A species whose line emission is computed for a cloud
+#[derive(Debug, Clone)]
+pub struct Emitter {
+    /// name of the emitting species
+    pub name: String,
+    /// abundance of the species per H nucleus; read it through
+    /// abundance_in
+    pub abundance: Abundance,
+    /// atomic and molecular data for the species, which may be
+    /// shared with other emitters read from the same file
+    pub data: Arc<EmitterData>,
+    /// if true, this emitter is ignored when computing heating and
+    /// cooling rates
+    pub energySkip: bool,
+    /// if true, collision rates are extrapolated beyond the
+    /// temperature range tabulated in the data file
+    pub extrap: bool,
+    /// geometry used to compute escape probabilities
+    pub escapeProbGeom: EscapeGeometry,
+    /// level populations and iteration count of the last solve
+    popCache: PopCache,
+    /// populations shared with emitters of other clouds, if any
+    popMemo: Option<Arc<PopMemo>>,
+    /// file from which data was read, if it was read from a file
+    dataPath: Option<PathBuf>,
+}
+
+impl Emitter {
+    /// Create an emitter, reading its data from a LAMDA file
+    ///
+    /// Parameters
+    ///    name : string
+    ///       name of the emitting species
+    ///    abundance : Abundance
+    ///       abundance of the species per H nucleus, either a constant
+    ///       or a function of the cloud
+    ///    energySkip : Boolean
+    ///       if true, the emitter is ignored in heating and cooling
+    ///       calculations
+    ///    extrap : Boolean
+    ///       if true, collision rates may be extrapolated beyond the
+    ///       tabulated temperature range
+    ///    emitterFile : string
+    ///       name of the LAMDA file; defaults to the lower-cased
+    ///       species name with extension .dat
+    ///    emitterURL : string
+    ///       URL from which the file can be obtained
+    ///
+    /// Remarks
+    ///    The file is looked for first relative to the working
+    ///    directory and then in the LAMDA subdirectory of the data
+    ///    directory. Files are never downloaded; if the file is
+    ///    missing the URL is reported in the error so that it can be
+    ///    fetched by hand.
+    pub fn new(
+        name: &str,
+        abundance: impl Into<Abundance>,
+        energySkip: bool,
+        extrap: bool,
+        emitterFile: Option<&str>,
+        emitterURL: Option<&str>,
+    ) -> Result<Self, DespoticError> {
+        let path = Self::data_path(name, emitterFile, emitterURL)?;
+        Self::from_path(name, abundance, energySkip, extrap, path, None)
+    }
+
+    /// Create an emitter from data already read
+    ///
+    /// Parameters
+    ///    name : string
+    ///       name of the emitting species
+    ///    abundance : Abundance
+    ///       abundance of the species per H nucleus, either a constant
+    ///       or a function of the cloud
+    ///    energySkip : Boolean
+    ///       if true, the emitter is ignored in heating and cooling
+    ///       calculations
+    ///    extrap : Boolean
+    ///       if true, collision rates may be extrapolated beyond the
+    ///       tabulated temperature range
+    ///    data : EmitterData
+    ///       atomic and molecular data for the species, which may be
+    ///       shared with other emitters
+    ///
+    /// Remarks
+    ///    Emitters sharing data, such as ortho- and para-H2O, are
+    ///    otherwise independent: each has its own abundance, escape
+    ///    probability geometry and cached level populations
+    pub fn with_data(
+        name: &str,
+        abundance: impl Into<Abundance>,
+        energySkip: bool,
+        extrap: bool,
+        data: Arc<EmitterData>,
+    ) -> Self {
+        Self {
+            name: name.to_string(),
+            abundance: abundance.into(),
+            data,
+            energySkip,
+            extrap,
+            escapeProbGeom: EscapeGeometry::default(),
+            popCache: PopCache::default(),
+            popMemo: None,
+            dataPath: None,
+        }
+    }
+
+    /// Locate the LAMDA file of a species, as described for new
+    pub(crate) fn data_path(
+        name: &str,
+        emitterFile: Option<&str>,
+        emitterURL: Option<&str>,
+    ) -> Result<PathBuf, DespoticError> {
+        let file_name = emitterFile
+            .map(str::to_string)
+            .unwrap_or_else(|| format!("{}.dat", name.to_lowercase()));
+        let local = PathBuf::from(&file_name);
+        if local.is_file() {
+            return Ok(local);
+        }
+        let bundled = crate::data_dir().join("LAMDA").join(&file_name);
+        if !bundled.is_file() {
+            return Err(DespoticError::MissingData(match emitterURL {
+                Some(url) => format!("{file_name} (available from {url})"),
+                None => file_name,
+            }));
+        }
+        Ok(bundled)
+    }
+
+    /// Create an emitter whose data comes from the file at path,
+    /// reading it unless the data are supplied
+    pub(crate) fn from_path(
+        name: &str,
+        abundance: impl Into<Abundance>,
+        energySkip: bool,
+        extrap: bool,
+        path: PathBuf,
+        data: Option<Arc<EmitterData>>,
+    ) -> Result<Self, DespoticError> {
+        let data = match data {
+            Some(data) => data,
+            None => Arc::new(EmitterData::from_lamda(&path)?),
+        };
+        let mut em = Self::with_data(name, abundance, energySkip, extrap, data);
+        em.dataPath = Some(path.canonicalize().unwrap_or(path));
+        Ok(em)
+    }
+
+    /// Abundance of the species per H nucleus in a cloud
+    ///
+    /// Parameters
+    ///    cloud : Cloud
+    ///       the cloud containing the emitter
+    ///
+    /// Returns
+    ///    the constant abundance, or the abundance profile evaluated
+    ///    for the cloud
+    pub fn abundance_in(&self, cloud: &Cloud) -> f64 {
+        self.abundance.value(cloud)
+    }
+
+    /// File from which the emitter's data was read, canonicalized
+    /// where possible; None if the data was supplied directly
+    pub(crate) fn data_file(&self) -> Option<&std::path::Path> {
+        self.dataPath.as_deref()
+    }
+
+    /// Line width of the emitting species
+    ///
+    /// Parameters
+    ///    cloud : Cloud
+    ///       the cloud containing the emitter
+    ///
+    /// Returns
+    ///    one-dimensional velocity dispersion, thermal plus
+    ///    nonthermal, in cm s^-1
+    pub fn line_width(&self, cloud: &Cloud) -> f64 {
+        self.sigma_tot(cloud)
+    }
+
+    /// Total velocity dispersion of the emitting species, thermal plus
+    /// nonthermal, in cm s^-1
+    fn sigma_tot(&self, cloud: &Cloud) -> f64 {
+        let sigmaTh = self.data.thermal_width(cloud.Tg);
+        (cloud.sigmaNT as f64 * cloud.sigmaNT as f64 + sigmaTh * sigmaTh).sqrt()
+    }
+
+    /// Line-center optical depths of the radiative transitions
+    ///
+    /// Parameters
+    ///    cloud : Cloud
+    ///       the cloud containing the emitter
+    ///    pops : array
+    ///       fractional level populations
+    ///
+    /// Returns
+    ///    optical depth of each transition, in the order of
+    ///    data.transitions
+    pub fn optical_depths(&self, cloud: &Cloud, pops: &[f64]) -> Vec<f64> {
+        // Column of the species along the ray, in cm^-2, per unit
+        // velocity, in cm s^-1
+        let colPerVel = match self.escapeProbGeom {
+            EscapeGeometry::Slab | EscapeGeometry::Sphere => {
+                self.abundance_in(cloud) * cloud.colDen as f64
+                    / ((2.0 * std::f64::consts::PI).sqrt() * self.sigma_tot(cloud))
+            }
+            EscapeGeometry::Lvg => {
+                self.abundance_in(cloud) * cloud.nH as f64 / (cloud.dVdr as f64).abs()
+            }
+        };
+        let levels = &self.data.levels;
+        self.data
+            .transitions
+            .iter()
+            .map(|t| {
+                let (gu, gl) = (levels[t.upper].weight, levels[t.lower].weight);
+                let lambda = c / t.freq;
+                t.A * lambda.powi(3) / (8.0 * std::f64::consts::PI) * gu / gl
+                    * colPerVel
+                    * (pops[t.lower] - pops[t.upper] * gl / gu)
+            })
+            .collect()
+    }
+
+    /// Escape probabilities of the radiative transitions
+    ///
+    /// Parameters
+    ///    cloud : Cloud
+    ///       the cloud containing the emitter
+    ///    pops : array
+    ///       fractional level populations
+    ///
+    /// Returns
+    ///    escape probability of each transition
+    pub fn escape_probs(&self, cloud: &Cloud, pops: &[f64]) -> Vec<f64> {
+        self.optical_depths(cloud, pops)
+            .into_iter()
+            .map(|tau| self.escapeProbGeom.beta(tau))
+            .collect()
+    }
+
+    /// Number density of a collision partner, in cm^-3
+    fn partner_density(&self, cloud: &Cloud, partner: Partner) -> f64 {
+        let comp = &cloud.comp;
+        let has = |p| self.data.partners.iter().any(|pd| pd.partner == p);
+        let x = match partner {
+            Partner::H2 => comp.xH2(),
+            // If only one spin state of H2 is tabulated, its rates
+            // are used for all H2
+            Partner::ParaH2 if !has(Partner::OrthoH2) && !has(Partner::H2) => comp.xH2(),
+            Partner::OrthoH2 if !has(Partner::ParaH2) && !has(Partner::H2) => comp.xH2(),
+            Partner::ParaH2 => comp.xpH2,
+            Partner::OrthoH2 => comp.xoH2,
+            Partner::Electron => comp.xe,
+            Partner::HI => comp.xHI,
+            Partner::He => comp.xHe,
+            Partner::HPlus => comp.xHplus,
+        };
+        x as f64 * cloud.nH as f64
+    }
+
+    /// Collisional transition rates, in s^-1, with element [i][j]
+    /// giving the rate from level i to level j; rate tables of
+    /// partners absent from the cloud are not read
+    fn collision_rates(&self, cloud: &Cloud) -> Result<Vec<Vec<f64>>, DespoticError> {
+        let T = cloud.Tg as f64;
+        let levels = &self.data.levels;
+        let nlev = levels.len();
+        let mut C = vec![vec![0.0; nlev]; nlev];
+        for pd in &self.data.partners {
+            let n = self.partner_density(cloud, pd.partner);
+            if n == 0.0 {
+                continue;
+            }
+            let cp = pd.table()?;
+            let k = cp.rate_at(T, self.extrap);
+            for ((&u, &l), k) in cp.upper.iter().zip(&cp.lower).zip(k) {
+                let down = k * n;
+                let (lu, ll) = (&levels[u], &levels[l]);
+                C[u][l] += down;
+                C[l][u] +=
+                    down * lu.weight / ll.weight * (-(lu.energy - ll.energy) / (kB * T)).exp();
+            }
+        }
+        Ok(C)
+    }
+
+    /// Assemble the rate matrix from the collisional rates, escape
+    /// probabilities and photon occupation numbers; element [j][i] is
+    /// the rate at which level j is populated from level i
+    fn assemble_rates(&self, C: &[Vec<f64>], beta: &[f64], ngamma: &[f64]) -> Vec<Vec<f64>> {
+        let levels = &self.data.levels;
+        let nlev = levels.len();
+        let mut M = vec![vec![0.0; nlev]; nlev];
+        for (i, row) in C.iter().enumerate() {
+            for (j, &r) in row.iter().enumerate() {
+                M[j][i] += r;
+                M[i][i] -= r;
+            }
+        }
+        for ((t, b), ng) in self.data.transitions.iter().zip(beta).zip(ngamma) {
+            let (u, l) = (t.upper, t.lower);
+            let down = t.A * b * (1.0 + ng);
+            let up = t.A * b * ng * levels[u].weight / levels[l].weight;
+            M[l][u] += down;
+            M[u][u] -= down;
+            M[u][l] += up;
+            M[l][l] -= up;
+        }
+        M
+    }
+
+    /// Photon occupation number of the background radiation field at
+    /// the frequency of each transition
+    fn background_occupation(&self, cloud: &Cloud) -> Vec<f64> {
+        self.data
+            .transitions
+            .iter()
+            .map(|t| cloud.rad.ngamma(t.freq))
+            .collect()
+    }
+
+    /// Rate matrix of the level population equations
+    ///
+    /// Parameters
+    ///    cloud : Cloud
+    ///       the cloud containing the emitter
+    ///
+    /// Returns
+    ///    matrix whose element [j][i] is the rate, in s^-1, at which
+    ///    level j is populated from level i, with diagonal elements
+    ///    the negated total rates out of each level
+    ///
+    /// Remarks
+    ///    The matrix includes collisional transitions and radiative
+    ///    transitions reduced by the escape probabilities, which are
+    ///    evaluated at the populations returned by level_pops. It is
+    ///    the matrix before the normalization condition replaces its
+    ///    last row, so each column sums to zero.
+    pub fn rate_matrix(&self, cloud: &Cloud) -> Result<Vec<Vec<f64>>, DespoticError> {
+        let pops = self.level_pops(cloud)?;
+        let beta = self.escape_probs(cloud, &pops);
+        Ok(self.assemble_rates(
+            &self.collision_rates(cloud)?,
+            &beta,
+            &self.background_occupation(cloud),
+        ))
+    }
+
+    /// Compute the statistical equilibrium level populations
+    ///
+    /// Parameters
+    ///    cloud : Cloud
+    ///       the cloud containing the emitter
+    ///
+    /// Returns
+    ///    fraction of the species in each level
+    ///
+    /// Remarks
+    ///    Populations and escape probabilities are iterated starting
+    ///    from the populations found by the previous call, or from LTE
+    ///    at the gas temperature if there was none; when conditions
+    ///    change only slightly between calls, as when searching for a
+    ///    temperature, this saves iterations. If the iteration does not
+    ///    converge a warning is printed, unless the cloud's noWarn
+    ///    flag is set, and the last iterate is returned.
+    ///
+    ///    Species whose data file provides no collision partners
+    ///    cannot be placed in statistical equilibrium with the gas, so
+    ///    they are assumed to be in LTE at the gas temperature, again
+    ///    with a warning unless noWarn is set.
+    ///
+    ///    If a PopMemo has been attached with set_pop_memo, converged
+    ///    populations are stored in it, and populations found there
+    ///    for the same conditions are returned without solving.
+    ///
+    ///    A ZeroDensity error is returned if the cloud's nH is not
+    ///    positive, and a MissingField error if Tg is not.
+    pub fn level_pops(&self, cloud: &Cloud) -> Result<Vec<f64>, DespoticError> {
+        cloud.check_density()?;
+        if cloud.Tg <= 0.0 {
+            return Err(DespoticError::MissingField("Tg".to_string()));
+        }
+        if self.data.partners.is_empty() {
+            if !cloud.noWarn {
+                eprintln!(
+                    "Warning: no collision data for {}, assuming LTE level populations",
+                    self.name
+                );
+            }
+            return Ok(self.data.lte_pops(cloud.Tg as f64));
+        }
+        if let Some(entry) = self
+            .popMemo
+            .as_ref()
+            .and_then(|memo| memo.get(&self.name, self.abundance_in(cloud) as f32, cloud))
+        {
+            self.popCache.set(LastSolve {
+                pops: Some(entry.pops.clone()),
+                iterations: 0,
+                beta: entry.beta,
+            });
+            return Ok(entry.pops);
+        }
+        let levels = &self.data.levels;
+        let nlev = levels.len();
+        let C = self.collision_rates(cloud)?;
+        let ngamma = self.background_occupation(cloud);
+
+        let mut pops = match self.popCache.get().pops {
+            Some(p) if p.len() == nlev => p,
+            _ => self.data.lte_pops(cloud.Tg as f64),
+        };
+        let mut badPops = false;
+        let mut lastBeta = Vec::new();
+        for iter in 1..=MAX_ITER {
+            let beta = self.escape_probs(cloud, &pops);
+            let mut M = self.assemble_rates(&C, &beta, &ngamma);
+            lastBeta = beta;
+
+            // Replace the last equation with the normalization
+            // condition and solve
+            M[nlev - 1] = vec![1.0; nlev];
+            let mut rhs = vec![0.0; nlev];
+            rhs[nlev - 1] = 1.0;
+            let mut newPops = crate::linalg::solve(M, rhs)
+                .ok_or_else(|| DespoticError::SingularMatrix(self.name.clone()))?;
+            badPops |= clip_negative(&mut newPops);
+
+            let converged = newPops
+                .iter()
+                .zip(&pops)
+                .all(|(n, o)| (n - o).abs() <= POP_TOL * n.abs().max(1.0e-10));
+            if converged {
+                self.warn_negative_pops(cloud, badPops);
+                if let Some(memo) = &self.popMemo {
+                    let entry = MemoEntry {
+                        pops: newPops.clone(),
+                        beta: Some(lastBeta.clone()),
+                    };
+                    memo.insert(&self.name, self.abundance_in(cloud) as f32, cloud, entry);
+                }
+                self.popCache.set(LastSolve {
+                    pops: Some(newPops.clone()),
+                    iterations: iter,
+                    beta: Some(lastBeta),
+                });
+                return Ok(newPops);
+            }
+            for (o, n) in pops.iter_mut().zip(&newPops) {
+                *o = DAMP * n + (1.0 - DAMP) * *o;
+            }
+        }
+        self.warn_negative_pops(cloud, badPops);
+        // Do not warm-start from an unconverged solution
+        self.popCache.set(LastSolve {
+            pops: None,
+            iterations: MAX_ITER,
+            beta: Some(lastBeta),
+        });
+        if !cloud.noWarn {
+            eprintln!(
+                "Warning: level populations of {} did not converge",
+                self.name
+            );
+        }
+        Ok(pops)
+    }
+
+    /// Level populations in the two-level approximation
+    ///
+    /// Returns
+    ///    populations with all but the two lowest levels empty, found
+    ///    from the balance of collisions and the radiative transition
+    ///    between those two levels; None if there is no such
+    ///    transition or no collision data
+    fn two_level_pops(&self, cloud: &Cloud) -> Result<Option<Vec<f64>>, DespoticError> {
+        cloud.check_density()?;
+        if cloud.Tg <= 0.0 {
+            return Err(DespoticError::MissingField("Tg".to_string()));
+        }
+        let levels = &self.data.levels;
+        let Some(idx) = self
+            .data
+            .transitions
+            .iter()
+            .position(|t| t.upper == 1 && t.lower == 0)
+        else {
+            return Ok(None);
+        };
+        if self.data.partners.is_empty() {
+            return Ok(None);
+        }
+        let t = &self.data.transitions[idx];
+        let C = self.collision_rates(cloud)?;
+        let ng = cloud.rad.ngamma(t.freq);
+        let gRatio = levels[1].weight / levels[0].weight;
+
+        // Iterate the upper level population and escape probability,
+        // starting from LTE between the two levels
+        let lte = self.data.lte_pops(cloud.Tg as f64);
+        let mut pops = vec![0.0; levels.len()];
+        let mut x1 = lte[1] / (lte[0] + lte[1]);
+        for _ in 0..MAX_ITER {
+            (pops[0], pops[1]) = (1.0 - x1, x1);
+            let tau = self.optical_depths(cloud, &pops)[idx];
+            let b = self.escapeProbGeom.beta(tau);
+            let up = C[0][1] + t.A * b * ng * gRatio;
+            let down = C[1][0] + t.A * b * (1.0 + ng);
+            let x1New = up / (up + down);
+            let converged = (x1New - x1).abs() <= POP_TOL * x1New.max(1.0e-10);
+            x1 = DAMP * x1New + (1.0 - DAMP) * x1;
+            if converged {
+                break;
+            }
+        }
+        (pops[0], pops[1]) = (1.0 - x1, x1);
+        Ok(Some(pops))
+    }
+
+    /// Number of iterations taken by the most recent level population
+    /// solve; zero if populations have not been solved for
+    pub fn last_iterations(&self) -> usize {
+        self.popCache.get().iterations
+    }
+
+    /// Escape probabilities used in the final iteration of the most
+    /// recent level population solve
+    ///
+    /// Parameters
+    ///    None
+    ///
+    /// Returns
+    ///    beta(tau) for each transition, in the order of
+    ///    data.transitions; None if populations have not been solved
+    ///    for since the cache was last cleared, or were taken to be
+    ///    LTE
+    ///
+    /// Remarks
+    ///    The probabilities are those computed from the populations
+    ///    of the previous iterate, so they match the returned
+    ///    populations to within the convergence tolerance. They are
+    ///    kept even if the solve did not converge.
+    pub fn escape_probabilities(&self) -> Option<Vec<f64>> {
+        self.popCache.get().beta
+    }
+
+    /// Share solved populations with other emitters through a memo
+    ///
+    /// Parameters
+    ///    memo : PopMemo
+    ///       memo in which to look up and store populations; None
+    ///       detaches any memo
+    ///
+    /// Returns
+    ///    Nothing
+    pub fn set_pop_memo(&mut self, memo: Option<Arc<PopMemo>>) {
+        self.popMemo = memo;
+    }
+
+    /// Discard the cached populations, so that the next solve starts
+    /// from LTE
+    pub fn clear_pop_cache(&self) {
+        self.popCache.set(LastSolve::default());
+    }
+
+    /// Warn, unless the cloud's noWarn flag is set, that level
+    /// populations well below zero had to be clipped
+    fn warn_negative_pops(&self, cloud: &Cloud, clipped: bool) {
+        if clipped && !cloud.noWarn {
+            eprintln!(
+                "Warning: clipped significantly negative level populations of {}",
+                self.name
+            );
+        }
+    }
+
+    /// Net cooling rate from line emission
+    ///
+    /// Parameters
+    ///    cloud : Cloud
+    ///       the cloud containing the emitter
+    ///
+    /// Returns
+    ///    net rate of energy loss from line emission, in erg s^-1
+    ///    H^-1, accounting for absorption of background photons
+    ///
+    /// Remarks
+    ///    Level populations are found as selected by the cloud's
+    ///    coolingMode. In TwoLevel mode only the line between the two
+    ///    lowest levels contributes; species with no such line are
+    ///    solved in full. In every mode, as for level_pops, a
+    ///    ZeroDensity error is returned if nH is not positive, and a
+    ///    MissingField error if Tg is not.
+    pub fn line_cool(&self, cloud: &Cloud) -> Result<f64, DespoticError> {
+        let (pops, nlev) = match cloud.coolingMode {
+            CoolingMode::Full => (self.level_pops(cloud)?, self.data.levels.len()),
+            CoolingMode::Lte => {
+                cloud.check_density()?;
+                if cloud.Tg <= 0.0 {
+                    return Err(DespoticError::MissingField("Tg".to_string()));
+                }
+                (self.data.lte_pops(cloud.Tg as f64), self.data.levels.len())
+            }
+            CoolingMode::TwoLevel => match self.two_level_pops(cloud)? {
+                Some(pops) => (pops, 2),
+                None => (self.level_pops(cloud)?, self.data.levels.len()),
+            },
+        };
+        let beta = self.escape_probs(cloud, &pops);
+        let levels = &self.data.levels;
+        let lines = self.data.transitions.iter().zip(&beta);
+        let cool = kahan_sum(lines.filter(|(t, _)| t.upper < nlev).map(|(t, b)| {
+            let (u, l) = (t.upper, t.lower);
+            let ng = cloud.rad.ngamma(t.freq);
+            let gRatio = levels[u].weight / levels[l].weight;
+            h * t.freq * t.A * b * (pops[u] - ng * (gRatio * pops[l] - pops[u]))
+        }));
+        Ok(self.abundance_in(cloud) * cool)
+    }
+}
+
+/// Clip negative level populations to zero and renormalize
+///
+/// Returns true if any clipped population was more negative than
+/// NEG_POP_TOL, i.e. too large to be round-off error
+fn clip_negative(pops: &mut [f64]) -> bool {
+    let mut bad = false;
+    for p in pops.iter_mut() {
+        if *p < 0.0 {
+            bad |= *p < -NEG_POP_TOL;
+            *p = 0.0;
+        }
+    }
+    let total: f64 = pops.iter().sum();
+    if total > 0.0 {
+        pops.iter_mut().for_each(|p| *p /= total);
+    }
+    bad
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::co_cloud;
+
+    #[test]
+    fn no_collision_data_gives_lte() {
+        let cloud = co_cloud();
+        let em = Emitter::new("nocoll", 1.0e-8, false, true, None, None).unwrap();
+        assert!(em.data.partners.is_empty());
+        let pops = em.level_pops(&cloud).unwrap();
+        assert_eq!(pops, em.data.lte_pops(cloud.Tg as f64));
+    }
+
+    #[test]
+    fn clipping_renormalizes_and_flags_large_negatives() {
+        let mut pops = vec![0.5, -1.0e-12, 0.5];
+        assert!(!clip_negative(&mut pops));
+        assert_eq!(pops, vec![0.5, 0.0, 0.5]);
+
+        let mut pops = vec![0.6, -0.2, 0.6];
+        assert!(clip_negative(&mut pops));
+        assert_eq!(pops, vec![0.5, 0.0, 0.5]);
+    }
+
+    #[test]
+    fn nearly_singular_pops_stay_non_negative() {
+        // With almost no collisions and no background radiation the
+        // upper levels are nearly empty and the rate matrix is close
+        // to singular
+        let mut cloud = co_cloud();
+        cloud.set_nh(1.0e-6);
+        cloud.rad.TCMB = 0.0;
+        let em = &cloud.emitters["co"];
+        let pops = em.level_pops(&cloud).unwrap();
+        assert!(pops.iter().all(|&p| p >= 0.0), "{pops:?}");
+        assert!((pops.iter().sum::<f64>() - 1.0).abs() < 1.0e-12);
+    }
+
+    #[test]
+    fn warm_start_takes_fewer_iterations() {
+        let mut cloud = co_cloud();
+        cloud.emitters["co"].level_pops(&cloud).unwrap();
+        cloud.set_tg(21.0);
+        let em = &cloud.emitters["co"];
+        let warm = em.level_pops(&cloud).unwrap();
+        let warmIter = em.last_iterations();
+        em.clear_pop_cache();
+        let cold = em.level_pops(&cloud).unwrap();
+        let coldIter = em.last_iterations();
+        assert!(warmIter < coldIter, "warm {warmIter}, cold {coldIter}");
+        for (w, x) in warm.iter().zip(&cold) {
+            assert!((w - x).abs() < 1.0e-4 * x.max(1.0e-6));
+        }
+    }
+
+    #[test]
+    fn escape_probabilities_fall_with_column() {
+        let mut cloud = co_cloud();
+        assert!(cloud.emitters["co"].escape_probabilities().is_none());
+        let mut betas = Vec::new();
+        for colDen in [1.0e12, 1.0e20, 1.0e22] {
+            cloud.colDen = colDen;
+            let em = &cloud.emitters["co"];
+            em.level_pops(&cloud).unwrap();
+            betas.push(em.escape_probabilities().unwrap());
+        }
+        assert!(betas[0].iter().all(|&b| b > 0.99), "{:?}", betas[0]);
+        for pair in betas.windows(2) {
+            for (thin, thick) in pair[0].iter().zip(&pair[1]) {
+                assert!(thick < thin);
+            }
+        }
+    }
+
+    #[test]
+    fn rate_matrix_conserves_population() {
+        let cloud = co_cloud();
+        let em = &cloud.emitters["co"];
+        let M = em.rate_matrix(&cloud).unwrap();
+        let n = em.data.levels.len();
+        assert_eq!((M.len(), M[0].len()), (n, n));
+        for (i, row) in M.iter().enumerate() {
+            let colSum: f64 = M.iter().map(|r| r[i]).sum();
+            assert!(row[i] < 0.0);
+            assert!(
+                colSum.abs() < 1.0e-12 * row[i].abs(),
+                "column {i} sums to {colSum}"
+            );
+        }
+
+        // The solved populations are a null vector of the matrix, to
+        // within the convergence tolerance
+        let pops = em.level_pops(&cloud).unwrap();
+        for row in &M {
+            let rate: f64 = row.iter().zip(&pops).map(|(m, p)| m * p).sum();
+            let scale: f64 = row.iter().zip(&pops).map(|(m, p)| (m * p).abs()).sum();
+            assert!(rate.abs() < 1.0e-3 * scale, "net rate {rate}");
+        }
+    }
+
+    #[test]
+    fn sphere_escape_probability() {
+        let sphere = |tau| EscapeGeometry::Sphere.beta(tau);
+        assert_eq!(sphere(0.0), 1.0);
+        assert!((sphere(1.0e-8) - 1.0).abs() < 1.0e-8);
+
+        // The series and the closed form agree where they meet
+        let closed = |tau: f64| {
+            1.5 / tau * (1.0 - 2.0 / (tau * tau) + (2.0 / tau + 2.0 / (tau * tau)) * (-tau).exp())
+        };
+        assert!((sphere(0.999e-2) - closed(0.999e-2)).abs() < 1.0e-8);
+        assert!((sphere(1.0) - closed(1.0)).abs() < 1.0e-15);
+
+        // At large optical depth beta -> 3 / (2 tau), 1.5 times the
+        // LVG value
+        for tau in [1.0e3, 1.0e5] {
+            let ratio = sphere(tau) / EscapeGeometry::Lvg.beta(tau);
+            assert!(
+                (ratio - 1.5).abs() < 3.0 / tau,
+                "ratio {ratio} at tau = {tau}"
+            );
+        }
+    }
+
+    #[test]
+    fn lte_cooling_agrees_only_at_high_density() {
+        let cooling = |nH: f32, mode| {
+            let mut cloud = co_cloud();
+            cloud.set_nh(nH);
+            cloud.coolingMode = mode;
+            cloud.emitters["co"].line_cool(&cloud).unwrap()
+        };
+        let ratio = |nH| cooling(nH, CoolingMode::Lte) / cooling(nH, CoolingMode::Full);
+        let (dense, diffuse) = (ratio(1.0e8), ratio(10.0));
+        assert!(
+            (dense - 1.0).abs() < 0.05,
+            "LTE / full = {dense} at 1e8 cm^-3"
+        );
+        assert!(diffuse > 2.0, "LTE / full = {diffuse} at 10 cm^-3");
+
+        let mut cloud = co_cloud();
+        cloud.coolingMode = CoolingMode::Lte;
+        cloud.Tg = 0.0;
+        assert!(matches!(
+            cloud.emitters["co"].line_cool(&cloud),
+            Err(DespoticError::MissingField(f)) if f == "Tg"
+        ));
+    }
+
+    #[test]
+    fn abundance_profile_varies_between_zones() {
+        // CO freezes out by a factor of 10 above 1e3 cm^-3
+        let profile = Abundance::Profile(Arc::new(
+            |cloud: &Cloud| {
+                if cloud.nH > 1.0e3 {
+                    1.0e-5
+                } else {
+                    1.0e-4
+                }
+            },
+        ));
+        let mut zones = [co_cloud(), co_cloud()];
+        zones[1].set_nh(1.0e4);
+        for (cloud, expected) in zones.iter_mut().zip([1.0e-4, 1.0e-5]) {
+            let em = cloud.emitters.get_mut("co").unwrap();
+            em.abundance = profile.clone();
+            let em = &cloud.emitters["co"];
+            assert_eq!(em.abundance_in(cloud), expected);
+
+            // The solver sees the same abundance as a constant would
+            // give
+            let mut constant = em.clone();
+            constant.abundance = Abundance::Constant(expected as f32);
+            let (a, b) = (
+                em.line_cool(cloud).unwrap(),
+                constant.line_cool(cloud).unwrap(),
+            );
+            assert!((a / b - 1.0).abs() < 1.0e-6, "{a:e} vs {b:e}");
+        }
+    }
+}
@@ -0,0 +1,1161 @@
+use crate::consts::{c, h, kB, mH};
+use crate::interp::{interp1d, Interp};
+use crate::parse::parse_float;
+use crate::DespoticError;
+use std::io::{BufRead, Seek, SeekFrom};
+use std::path::PathBuf;
+use std::sync::{Arc, OnceLock};
+
+/// An energy level of an emitting species
+#[derive(Debug, Clone)]
+pub struct Level {
+    /// energy above the ground state, in erg
+    pub energy: f64,
+    /// statistical weight
+    pub weight: f64,
+}
+
+/// A radiative transition between two levels
+#[derive(Debug, Clone)]
+pub struct Transition {
+    /// index of the upper level
+    pub upper: usize,
+    /// index of the lower level
+    pub lower: usize,
+    /// Einstein A coefficient, in s^-1
+    pub A: f64,
+    /// frequency, in Hz
+    pub freq: f64,
+    /// energy of the upper level, in K
+    pub Tupper: f64,
+    /// hyperfine components into which the line is split, in order
+    /// of frequency; empty if it is not split
+    pub hyperfine: Vec<HyperfineComponent>,
+}
+
+/// One hyperfine component of a line
+#[derive(Debug, Clone, PartialEq)]
+pub struct HyperfineComponent {
+    /// frequency offset from the transition frequency, in Hz
+    pub offset: f64,
+    /// fraction of the line intensity in this component, proportional
+    /// to g_u A as for optically thin emission in LTE
+    pub relInt: f64,
+}
+
+/// Number of hyperfine quantum numbers at the end of each level
+/// label, read from the header line above the levels
+///
+/// Remarks
+///    LAMDA files that resolve hyperfine structure, such as hcn@hfs
+///    and n2h+@hfs, name the label column e.g. "J_F" or "J_F1_F";
+///    the trailing numbers whose names start with F are hyperfine
+///    quantum numbers. Other files, e.g. "J_KaKc" for H2O, have none.
+fn hyperfine_depth(header: &str) -> usize {
+    let names: Vec<&str> = match header.split_whitespace().last() {
+        Some(col) => col.split('_').collect(),
+        None => return 0,
+    };
+    names
+        .iter()
+        .skip(1)
+        .rev()
+        .take_while(|name| name.starts_with('F'))
+        .count()
+}
+
+/// Map from the hyperfine-split levels of a data file to the levels
+/// they are merged into
+#[derive(Debug)]
+struct LevelMerge {
+    /// merged level of each level in the file
+    parent: Vec<usize>,
+    /// fraction of the statistical weight of its merged level carried
+    /// by each level in the file
+    frac: Vec<f64>,
+}
+
+impl LevelMerge {
+    /// Merge levels whose labels agree but for their hyperfine
+    /// quantum numbers
+    ///
+    /// Parameters
+    ///    levels : list of Level
+    ///       levels as listed in the data file
+    ///    keys : list of string
+    ///       label of each level without its hyperfine quantum numbers
+    ///
+    /// Returns
+    ///    the merged levels, in order of their first split level, and
+    ///    the map to them; None if no level is split
+    ///
+    /// Remarks
+    ///    A merged level has the summed weight of its split levels
+    ///    and their weight-averaged energy.
+    fn new(levels: &[Level], keys: &[String]) -> Option<(Vec<Level>, Self)> {
+        let mut index = std::collections::HashMap::new();
+        let mut merged: Vec<Level> = Vec::new();
+        let mut parent = Vec::with_capacity(levels.len());
+        for (level, key) in levels.iter().zip(keys) {
+            let i = *index.entry(key.as_str()).or_insert_with(|| {
+                merged.push(Level {
+                    energy: 0.0,
+                    weight: 0.0,
+                });
+                merged.len() - 1
+            });
+            merged[i].energy += level.weight * level.energy;
+            merged[i].weight += level.weight;
+            parent.push(i);
+        }
+        if merged.len() == levels.len() {
+            return None;
+        }
+        for level in &mut merged {
+            level.energy /= level.weight;
+        }
+        let frac = levels
+            .iter()
+            .zip(&parent)
+            .map(|(level, &i)| level.weight / merged[i].weight)
+            .collect();
+        Some((merged, Self { parent, frac }))
+    }
+
+    /// Merge the radiative transitions between split levels into
+    /// lines with hyperfine components
+    ///
+    /// Remarks
+    ///    The components of a line are the transitions between the
+    ///    split levels of its upper and lower merged levels. The line
+    ///    takes the frequency and upper level energy of its strongest
+    ///    component, and the Einstein A that gives it the summed g_u A
+    ///    of its components. Transitions between levels of the same
+    ///    merged level are dropped.
+    fn merge_transitions(&self, levels: &[Level], transitions: &[Transition]) -> Vec<Transition> {
+        let mut index = std::collections::HashMap::new();
+        let mut groups: Vec<Vec<&Transition>> = Vec::new();
+        for t in transitions {
+            let key = (self.parent[t.upper], self.parent[t.lower]);
+            if key.0 == key.1 {
+                continue;
+            }
+            let i = *index.entry(key).or_insert_with(|| {
+                groups.push(Vec::new());
+                groups.len() - 1
+            });
+            groups[i].push(t);
+        }
+        let strength = |t: &Transition| levels[t.upper].weight * t.A;
+        groups
+            .into_iter()
+            .map(|mut group| {
+                group.sort_by(|a, b| a.freq.total_cmp(&b.freq));
+                let total: f64 = group.iter().map(|t| strength(t)).sum();
+                let main = group
+                    .iter()
+                    .max_by(|a, b| strength(a).total_cmp(&strength(b)))
+                    .unwrap();
+                let gU = levels[main.upper].weight / self.frac[main.upper];
+                let hyperfine = if group.len() > 1 {
+                    group
+                        .iter()
+                        .map(|t| HyperfineComponent {
+                            offset: t.freq - main.freq,
+                            relInt: strength(t) / total,
+                        })
+                        .collect()
+                } else {
+                    Vec::new()
+                };
+                Transition {
+                    upper: self.parent[main.upper],
+                    lower: self.parent[main.lower],
+                    A: total / gU,
+                    freq: main.freq,
+                    Tupper: main.Tupper,
+                    hyperfine,
+                }
+            })
+            .collect()
+    }
+
+    /// Merge the rows of a rate table between split levels
+    ///
+    /// Remarks
+    ///    The rate from merged level U to merged level L is
+    ///    sum_{u in U} (g_u / g_U) sum_{l in L} k(u -> l), which holds
+    ///    if the split levels of U are populated in proportion to
+    ///    their weights. Rows between levels of the same merged level
+    ///    are dropped.
+    fn merge_rates(&self, table: CollisionPartner) -> CollisionPartner {
+        let mut index = std::collections::HashMap::new();
+        let mut upper = Vec::new();
+        let mut lower = Vec::new();
+        let mut rates: Vec<Vec<f64>> = Vec::new();
+        for ((u, l), k) in table.upper.iter().zip(&table.lower).zip(&table.rates) {
+            let (pu, pl) = (self.parent[*u], self.parent[*l]);
+            if pu == pl {
+                continue;
+            }
+            let row = *index.entry((pu, pl)).or_insert_with(|| {
+                upper.push(pu);
+                lower.push(pl);
+                rates.push(vec![0.0; k.len()]);
+                rates.len() - 1
+            });
+            for (r, k) in rates[row].iter_mut().zip(k) {
+                *r += self.frac[*u] * k;
+            }
+        }
+        CollisionPartner {
+            upper,
+            lower,
+            rates,
+            ..table
+        }
+    }
+}
+
+/// Unit of the transition frequencies in a LAMDA file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FreqUnit {
+    /// GHz, as in the standard LAMDA format
+    #[default]
+    GHz,
+    /// MHz
+    MHz,
+    /// Hz
+    Hz,
+    /// wavenumber, in cm^-1
+    InvCm,
+}
+
+impl FreqUnit {
+    /// Convert a frequency in this unit to Hz
+    pub fn to_hz(self, value: f64) -> f64 {
+        match self {
+            Self::GHz => value * 1.0e9,
+            Self::MHz => value * 1.0e6,
+            Self::Hz => value,
+            Self::InvCm => value * c,
+        }
+    }
+}
+
+/// Species that collisionally excite an emitter, numbered as in the
+/// LAMDA file format
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Partner {
+    H2,
+    ParaH2,
+    OrthoH2,
+    Electron,
+    HI,
+    He,
+    HPlus,
+}
+
+impl Partner {
+    fn from_lamda_id(id: usize) -> Option<Self> {
+        match id {
+            1 => Some(Self::H2),
+            2 => Some(Self::ParaH2),
+            3 => Some(Self::OrthoH2),
+            4 => Some(Self::Electron),
+            5 => Some(Self::HI),
+            6 => Some(Self::He),
+            7 => Some(Self::HPlus),
+            _ => None,
+        }
+    }
+
+    /// Conventional name of the species, e.g. "p-H2" or "e"
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::H2 => "H2",
+            Self::ParaH2 => "p-H2",
+            Self::OrthoH2 => "o-H2",
+            Self::Electron => "e",
+            Self::HI => "H",
+            Self::He => "He",
+            Self::HPlus => "H+",
+        }
+    }
+}
+
+/// Summary of the collision data for one partner of an emitter
+#[derive(Debug, Clone, PartialEq)]
+pub struct PartnerInfo {
+    /// the colliding species
+    pub partner: Partner,
+    /// conventional name of the colliding species
+    pub name: &'static str,
+    /// number of collisional transitions tabulated in the data file
+    pub n_transitions: usize,
+    /// lowest and highest tabulated temperatures, in K
+    pub t_range: (f64, f64),
+}
+
+/// Tabulated collision rates between an emitter and one partner
+#[derive(Debug, Clone)]
+pub struct CollisionPartner {
+    /// the colliding species
+    pub partner: Partner,
+    /// temperatures at which rates are tabulated, in K
+    pub temps: Vec<f64>,
+    /// index of the upper level of each collisional transition
+    pub upper: Vec<usize>,
+    /// index of the lower level of each collisional transition
+    pub lower: Vec<usize>,
+    /// downward rate coefficients, in cm^3 s^-1, indexed by
+    /// collisional transition and then by temperature
+    pub rates: Vec<Vec<f64>>,
+}
+
+impl CollisionPartner {
+    /// Downward collision rate coefficients at a given temperature
+    ///
+    /// Parameters
+    ///    T : float
+    ///       gas kinetic temperature, in K
+    ///    extrap : Boolean
+    ///       if true, rates outside the tabulated temperature range are
+    ///       extrapolated as a power law from the two nearest table
+    ///       entries; otherwise they are held at the edge values
+    ///
+    /// Returns
+    ///    rate coefficient of each collisional transition, in
+    ///    cm^3 s^-1
+    ///
+    /// Remarks
+    ///    Within the table, rates are interpolated linearly in
+    ///    temperature
+    pub fn rate_at(&self, T: f64, extrap: bool) -> Vec<f64> {
+        let (T, mode) = if extrap {
+            (T, Interp::PowerLaw)
+        } else {
+            let Tmax = self.temps[self.temps.len() - 1];
+            (T.clamp(self.temps[0], Tmax), Interp::LinLin)
+        };
+        self.rates
+            .iter()
+            .map(|k| interp1d(T, &self.temps, k, mode))
+            .collect()
+    }
+}
+
+/// Place in a LAMDA file where the rate table of a partner starts
+#[derive(Debug, Clone)]
+struct TableSource {
+    path: Arc<PathBuf>,
+    /// byte offset of the line following the temperatures
+    offset: u64,
+    /// number of the last line read before offset
+    line_number: usize,
+    nlev: usize,
+    merge: Option<Arc<LevelMerge>>,
+}
+
+/// Collision data for one partner of an emitter, whose rate table
+/// is read from the data file on first access if the data were
+/// opened with from_lamda_lazy
+#[derive(Debug, Clone)]
+pub struct PartnerData {
+    /// the colliding species
+    pub partner: Partner,
+    /// number of collisional transitions tabulated in the data file
+    pub n_transitions: usize,
+    /// temperatures at which rates are tabulated, in K
+    pub temps: Vec<f64>,
+    table: OnceLock<CollisionPartner>,
+    source: Option<TableSource>,
+}
+
+impl PartnerData {
+    /// Rate table of the partner
+    ///
+    /// Returns
+    ///    the tabulated collision rates
+    ///
+    /// Remarks
+    ///    If the table has not been read yet, it is read from the data
+    ///    file now and kept for later calls. An error is returned if
+    ///    the file can no longer be read or has changed so that the
+    ///    table no longer parses.
+    pub fn table(&self) -> Result<&CollisionPartner, DespoticError> {
+        if let Some(table) = self.table.get() {
+            return Ok(table);
+        }
+        let src = self
+            .source
+            .as_ref()
+            .expect("partner data without a table has a source");
+        let mut fp = std::fs::File::open(src.path.as_ref())?;
+        fp.seek(SeekFrom::Start(src.offset))?;
+        let mut lines = DataLines {
+            reader: std::io::BufReader::new(fp),
+            line_number: src.line_number,
+            offset: src.offset,
+            header: String::new(),
+        };
+        let table = lines.read_rates(
+            self.partner,
+            self.temps.clone(),
+            self.n_transitions,
+            src.nlev,
+            src.merge.as_deref(),
+        )?;
+        // Another thread may have read the table meanwhile; either
+        // copy will do
+        let _ = self.table.set(table);
+        Ok(self.table.get().unwrap())
+    }
+
+    /// Whether the rate table has been read
+    pub fn is_loaded(&self) -> bool {
+        self.table.get().is_some()
+    }
+}
+
+/// Atomic and molecular data for an emitting species
+#[derive(Debug, Clone)]
+pub struct EmitterData {
+    /// name of the species, as given in the data file
+    pub name: String,
+    /// molecular weight, in units of H masses
+    pub molWgt: f64,
+    /// energy levels, in order of increasing energy
+    pub levels: Vec<Level>,
+    /// radiative transitions
+    pub transitions: Vec<Transition>,
+    /// collision partners
+    pub partners: Vec<PartnerData>,
+}
+
+/// Largest number of entries reserved ahead of reading them; the
+/// counts in a data file are not trusted to size allocations, since a
+/// corrupt count would otherwise abort the program before the short
+/// file could be reported
+const MAX_PREALLOC: usize = 1024;
+
+/// Parse the first n whitespace-separated fields of a line; None if
+/// there are fewer or any fails to parse
+fn leading_fields<T>(line: &str, n: usize, parse: impl Fn(&str) -> Option<T>) -> Option<Vec<T>> {
+    let fields: Option<Vec<T>> = line.split_whitespace().take(n).map(parse).collect();
+    fields.filter(|f| f.len() == n)
+}
+
+/// Iterator over the data lines of a LAMDA file, skipping the "!"
+/// header lines and keeping track of physical line numbers and byte
+/// offsets
+struct DataLines<R> {
+    reader: R,
+    line_number: usize,
+    offset: u64,
+    /// the last header line read
+    header: String,
+}
+
+impl<R: BufRead> DataLines<R> {
+    fn new(reader: R) -> Self {
+        Self {
+            reader,
+            line_number: 0,
+            offset: 0,
+            header: String::new(),
+        }
+    }
+
+    fn next_line(&mut self) -> Result<(usize, String), DespoticError> {
+        let mut line = String::new();
+        loop {
+            line.clear();
+            let n = self.reader.read_line(&mut line)?;
+            if n == 0 {
+                return Err(DespoticError::ParseData {
+                    line_number: self.line_number + 1,
+                    line: "unexpected end of file".to_string(),
+                });
+            }
+            self.line_number += 1;
+            self.offset += n as u64;
+            let trimmed = line.trim();
+            if trimmed.starts_with('!') {
+                self.header = trimmed.to_string();
+            }
+            if trimmed.is_empty() || trimmed.starts_with('!') {
+                continue;
+            }
+            let len = line.trim_end_matches(['\n', '\r']).len();
+            line.truncate(len);
+            return Ok((self.line_number, line));
+        }
+    }
+
+    /// Read a line and parse its leading whitespace-separated fields
+    fn next_fields<T: std::str::FromStr>(&mut self, n: usize) -> Result<Vec<T>, DespoticError> {
+        self.next_parsed(n, |s| s.parse().ok())
+    }
+
+    /// Read a line and parse its leading whitespace-separated fields
+    /// as floating point numbers
+    fn next_floats(&mut self, n: usize) -> Result<Vec<f64>, DespoticError> {
+        self.next_parsed(n, |s| parse_float(s).ok())
+    }
+
+    fn next_parsed<T>(
+        &mut self,
+        n: usize,
+        parse: impl Fn(&str) -> Option<T>,
+    ) -> Result<Vec<T>, DespoticError> {
+        let (line_number, line) = self.next_line()?;
+        leading_fields(&line, n, parse).ok_or(DespoticError::ParseData { line_number, line })
+    }
+
+    /// Read the rows of a partner's rate table, which follow its
+    /// temperatures, merging them if the levels were merged
+    fn read_rates(
+        &mut self,
+        partner: Partner,
+        temps: Vec<f64>,
+        ntrans: usize,
+        nlev: usize,
+        merge: Option<&LevelMerge>,
+    ) -> Result<CollisionPartner, DespoticError> {
+        let mut upper = Vec::with_capacity(ntrans.min(MAX_PREALLOC));
+        let mut lower = Vec::with_capacity(ntrans.min(MAX_PREALLOC));
+        let mut rates = Vec::with_capacity(ntrans.min(MAX_PREALLOC));
+        for _ in 0..ntrans {
+            let f = self.next_floats(3 + temps.len())?;
+            let line_number = self.line_number;
+            let (u, l) = (f[1] as usize, f[2] as usize);
+            if u == 0 || l == 0 || u > nlev || l > nlev {
+                return Err(DespoticError::ParseData {
+                    line_number,
+                    line: format!("collision between nonexistent levels {u} -> {l}"),
+                });
+            }
+            upper.push(u - 1);
+            lower.push(l - 1);
+            rates.push(f[3..].to_vec());
+        }
+        let table = CollisionPartner {
+            partner,
+            temps,
+            upper,
+            lower,
+            rates,
+        };
+        Ok(match merge {
+            Some(merge) => merge.merge_rates(table),
+            None => table,
+        })
+    }
+}
+
+impl EmitterData {
+    /// Summary of the collision data for each partner
+    ///
+    /// Parameters
+    ///    None
+    ///
+    /// Returns
+    ///    one PartnerInfo per collision partner, in the order of the
+    ///    data file
+    ///
+    /// Remarks
+    ///    The full tables are in the partners field; this gives only
+    ///    their extent, and does not read tables that have not been
+    ///    read yet
+    pub fn partners(&self) -> Vec<PartnerInfo> {
+        self.partners
+            .iter()
+            .map(|pd| PartnerInfo {
+                partner: pd.partner,
+                name: pd.partner.name(),
+                n_transitions: pd.n_transitions,
+                t_range: (pd.temps[0], pd.temps[pd.temps.len() - 1]),
+            })
+            .collect()
+    }
+
+    /// Number of collision partners whose rate tables have been read
+    pub fn tables_loaded(&self) -> usize {
+        self.partners.iter().filter(|pd| pd.is_loaded()).count()
+    }
+
+    /// Partition function at a given temperature
+    ///
+    /// Parameters
+    ///    T : float
+    ///       temperature, in K
+    ///
+    /// Returns
+    ///    the partition function, sum_i g_i exp(-E_i / kT)
+    ///
+    /// Remarks
+    ///    The sum is taken relative to the lowest level, so no term
+    ///    can overflow or underflow to a zero total however many
+    ///    levels there are and whatever the temperature; only the
+    ///    final factor exp(-E_min / kT) can, and LAMDA energies are
+    ///    measured from the ground state, making it unity. If the
+    ///    lowest level lies so far below zero that the result would
+    ///    overflow, a warning is printed and the result is clamped to
+    ///    the largest finite float.
+    pub fn partition_function(&self, T: f64) -> f64 {
+        let (weights, Emin) = self.relative_boltzmann_weights(T);
+        let Z: f64 = weights.iter().sum();
+        let maxArg = (f64::MAX / Z).ln();
+        let mut arg = -Emin / (kB * T);
+        if arg > maxArg {
+            eprintln!(
+                "Warning: partition function overflows at T = {T} K, \
+                 clamping exp({arg:e}) to exp({maxArg:e})"
+            );
+            arg = maxArg;
+        }
+        // exp may round the clamped factor up past the largest float
+        (Z * arg.exp()).min(f64::MAX)
+    }
+
+    /// Boltzmann factors g_i exp(-(E_i - E_min) / kT) of the levels,
+    /// and the lowest level energy E_min, in erg
+    fn relative_boltzmann_weights(&self, T: f64) -> (Vec<f64>, f64) {
+        let Emin = self
+            .levels
+            .iter()
+            .map(|l| l.energy)
+            .fold(f64::INFINITY, f64::min);
+        let weights = self
+            .levels
+            .iter()
+            .map(|l| l.weight * (-(l.energy - Emin) / (kB * T)).exp())
+            .collect();
+        (weights, Emin)
+    }
+
+    /// Level populations in local thermodynamic equilibrium
+    ///
+    /// Parameters
+    ///    T : float
+    ///       temperature, in K
+    ///
+    /// Returns
+    ///    fraction of the species in each level
+    pub fn lte_pops(&self, T: f64) -> Vec<f64> {
+        let (weights, _) = self.relative_boltzmann_weights(T);
+        let Z: f64 = weights.iter().sum();
+        weights.iter().map(|w| w / Z).collect()
+    }
+
+    /// Thermal velocity dispersion of the species
+    ///
+    /// Parameters
+    ///    Tg : float
+    ///       gas temperature, in K
+    ///
+    /// Returns
+    ///    one-dimensional thermal velocity dispersion sqrt(kT / m),
+    ///    in cm s^-1, with m taken from the molecular weight
+    pub fn thermal_width(&self, Tg: f32) -> f64 {
+        (kB * Tg as f64 / (self.molWgt * mH)).sqrt()
+    }
+
+    /// Frequencies of the radiative transitions, for plotting
+    ///
+    /// Parameters
+    ///    None
+    ///
+    /// Returns
+    ///    frequency of every radiative transition, in Hz, sorted in
+    ///    ascending order
+    pub fn line_frequencies(&self) -> Vec<f64> {
+        let mut freqs: Vec<f64> = self.transitions.iter().map(|t| t.freq).collect();
+        freqs.sort_by(f64::total_cmp);
+        freqs
+    }
+
+    /// Read emitter data from a file in LAMDA format
+    ///
+    /// Parameters
+    ///    path : string
+    ///       name of the LAMDA file
+    ///
+    /// Returns
+    ///    the emitter data
+    ///
+    /// Remarks
+    ///    Files that resolve hyperfine structure, such as LAMDA's
+    ///    hcn@hfs, list each hyperfine level and component as its own
+    ///    level and transition. Their split levels are merged, so the
+    ///    level populations are those of the unsplit levels, and the
+    ///    transitions between two merged levels become the hyperfine
+    ///    components of one line.
+    pub fn from_lamda(path: impl AsRef<std::path::Path>) -> Result<Self, DespoticError> {
+        Self::from_lamda_with(path, FreqUnit::default())
+    }
+
+    /// Read emitter data from a file in LAMDA format whose transition
+    /// frequencies may be in units other than GHz
+    ///
+    /// Parameters
+    ///    path : string
+    ///       name of the LAMDA file
+    ///    unit : FreqUnit
+    ///       unit of the transition frequencies in the file
+    ///
+    /// Returns
+    ///    the emitter data, with frequencies converted to Hz
+    pub fn from_lamda_with(
+        path: impl AsRef<std::path::Path>,
+        unit: FreqUnit,
+    ) -> Result<Self, DespoticError> {
+        let fp = std::fs::File::open(path)?;
+        Self::read_lamda_with(std::io::BufReader::new(fp), unit)
+    }
+
+    /// Open emitter data in a LAMDA file, deferring the collision rate
+    /// tables until they are needed
+    ///
+    /// Parameters
+    ///    path : string
+    ///       name of the LAMDA file
+    ///
+    /// Returns
+    ///    the emitter data
+    ///
+    /// Remarks
+    ///    The levels, radiative transitions, and each partner's
+    ///    temperatures are read now; each partner's rate table is
+    ///    read from the file the first time PartnerData::table is
+    ///    called for it, so the file must stay in place until then.
+    ///    This saves memory for species with large collision tables
+    ///    of which only some partners are present.
+    pub fn from_lamda_lazy(path: impl AsRef<std::path::Path>) -> Result<Self, DespoticError> {
+        Self::from_lamda_lazy_with(path, FreqUnit::default())
+    }
+
+    /// Open emitter data in a LAMDA file whose transition frequencies
+    /// may be in units other than GHz, deferring the collision rate
+    /// tables until they are needed
+    pub fn from_lamda_lazy_with(
+        path: impl AsRef<std::path::Path>,
+        unit: FreqUnit,
+    ) -> Result<Self, DespoticError> {
+        let path = Arc::new(path.as_ref().to_path_buf());
+        let fp = std::fs::File::open(path.as_ref())?;
+        Self::parse_lamda(std::io::BufReader::new(fp), unit, Some(path))
+    }
+
+    /// Read emitter data in LAMDA format from any buffered reader
+    pub fn read_lamda(reader: impl BufRead) -> Result<Self, DespoticError> {
+        Self::read_lamda_with(reader, FreqUnit::default())
+    }
+
+    /// Read emitter data in LAMDA format from any buffered reader,
+    /// with transition frequencies in the given unit
+    pub fn read_lamda_with(reader: impl BufRead, unit: FreqUnit) -> Result<Self, DespoticError> {
+        Self::parse_lamda(reader, unit, None)
+    }
+
+    /// Parse LAMDA data; if lazy gives the path of the file being
+    /// read, the rate tables are skipped and read from it on demand
+    fn parse_lamda(
+        reader: impl BufRead,
+        unit: FreqUnit,
+        lazy: Option<Arc<PathBuf>>,
+    ) -> Result<Self, DespoticError> {
+        let mut lines = DataLines::new(reader);
+
+        let name = lines.next_line()?.1.trim().to_string();
+        let molWgt = lines.next_floats(1)?[0];
+
+        // Energy levels; energies are given in cm^-1, and the labels
+        // matter only if they end in hyperfine quantum numbers
+        let nlev = lines.next_fields::<usize>(1)?[0];
+        let mut levels = Vec::with_capacity(nlev.min(MAX_PREALLOC));
+        let mut keys = Vec::with_capacity(nlev.min(MAX_PREALLOC));
+        let mut depth = 0;
+        for i in 0..nlev {
+            let (line_number, line) = lines.next_line()?;
+            if i == 0 {
+                depth = hyperfine_depth(&lines.header);
+            }
+            let f = leading_fields(&line, 3, |s| parse_float(s).ok());
+            let label = line.split_whitespace().nth(3).unwrap_or("");
+            let key: Vec<&str> = label.rsplitn(depth + 1, '_').collect();
+            let f = match f {
+                Some(f) if key.len() == depth + 1 => f,
+                _ => return Err(DespoticError::ParseData { line_number, line }),
+            };
+            levels.push(Level {
+                energy: f[1] * h * c,
+                weight: f[2],
+            });
+            keys.push(key[depth].to_string());
+        }
+
+        // Radiative transitions; frequencies are normally given in GHz
+        let nrad = lines.next_fields::<usize>(1)?[0];
+        let mut transitions = Vec::with_capacity(nrad.min(MAX_PREALLOC));
+        for _ in 0..nrad {
+            let f = lines.next_floats(6)?;
+            let line_number = lines.line_number;
+            let (upper, lower) = (f[1] as usize, f[2] as usize);
+            if upper == 0 || lower == 0 || upper > nlev || lower > nlev {
+                return Err(DespoticError::ParseData {
+                    line_number,
+                    line: format!("transition between nonexistent levels {upper} -> {lower}"),
+                });
+            }
+            transitions.push(Transition {
+                upper: upper - 1,
+                lower: lower - 1,
+                A: f[3],
+                freq: unit.to_hz(f[4]),
+                Tupper: f[5],
+                hyperfine: Vec::new(),
+            });
+        }
+
+        // Levels split by hyperfine structure are merged, and their
+        // transitions become the hyperfine components of lines
+        let split = if depth > 0 {
+            LevelMerge::new(&levels, &keys)
+        } else {
+            None
+        };
+        let merge = split.map(|(merged, merge)| {
+            transitions = merge.merge_transitions(&levels, &transitions);
+            levels = merged;
+            Arc::new(merge)
+        });
+
+        // Collision partners
+        let npart = lines.next_fields::<usize>(1)?[0];
+        let mut partners = Vec::with_capacity(npart.min(MAX_PREALLOC));
+        for _ in 0..npart {
+            let (line_number, line) = lines.next_line()?;
+            let partner = line
+                .split_whitespace()
+                .next()
+                .and_then(|s| s.parse().ok())
+                .and_then(Partner::from_lamda_id)
+                .ok_or(DespoticError::ParseData { line_number, line })?;
+            let ntrans = lines.next_fields::<usize>(1)?[0];
+            let (line_number, line) = lines.next_line()?;
+            let ntemp = match line.split_whitespace().next().map(str::parse::<usize>) {
+                Some(Ok(n)) if n > 0 => n,
+                _ => return Err(DespoticError::ParseData { line_number, line }),
+            };
+            let temps = lines.next_floats(ntemp)?;
+            let table = OnceLock::new();
+            let source = match &lazy {
+                Some(path) => {
+                    let source = TableSource {
+                        path: path.clone(),
+                        offset: lines.offset,
+                        line_number: lines.line_number,
+                        nlev,
+                        merge: merge.clone(),
+                    };
+                    // Step over the rows without parsing them
+                    for _ in 0..ntrans {
+                        lines.next_line()?;
+                    }
+                    Some(source)
+                }
+                None => {
+                    let _ = table.set(lines.read_rates(
+                        partner,
+                        temps.clone(),
+                        ntrans,
+                        nlev,
+                        merge.as_deref(),
+                    )?);
+                    None
+                }
+            };
+            partners.push(PartnerData {
+                partner,
+                n_transitions: ntrans,
+                temps,
+                table,
+                source,
+            });
+        }
+
+        Ok(Self {
+            name,
+            molWgt,
+            levels,
+            transitions,
+            partners,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::lamda;
+
+    /// A two-level stand-in for ortho-H2O, with the 557 GHz line
+    const H2O: &str = "\
+!MOLECULE
+o-H2O
+!MOLECULAR WEIGHT
+18.0
+!NUMBER OF ENERGY LEVELS
+2
+!LEVEL + ENERGIES(cm^-1) + WEIGHT + J_KaKc
+    1     23.794363   3.0   1_01
+    2     42.371733   9.0   1_10
+!NUMBER OF RADIATIVE TRANSITIONS
+1
+!TRANS + UP + LOW + EINSTEINA(s^-1) + FREQ(GHz) + E_u(K)
+    1    2    1  3.458e-03    556.9359877    61.0
+!NUMBER OF COLL PARTNERS
+0
+";
+
+    #[test]
+    fn thermal_width_scales_with_molecular_weight() {
+        let h2o = EmitterData::read_lamda(H2O.as_bytes()).unwrap();
+        let co = lamda("co");
+        assert_eq!(h2o.molWgt, 18.0);
+        let ratio = h2o.thermal_width(30.0) / co.thermal_width(30.0);
+        assert!((ratio - (28.0_f64 / 18.0).sqrt()).abs() < 1.0e-12);
+
+        // sqrt(kT / m) for CO at 30 K is close to 0.095 km/s
+        assert!((co.thermal_width(30.0) / 0.0945e5 - 1.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn hyperfine_components_of_hcn() {
+        // The file lists J = 1 as three levels split by F, and the
+        // J = 1 - 0 line as three transitions
+        let hcn = lamda("hcn");
+        let weights: Vec<f64> = hcn.levels.iter().map(|l| l.weight).collect();
+        assert_eq!(weights, [3.0, 9.0]);
+        assert_eq!(hcn.transitions.len(), 1);
+        let t = &hcn.transitions[0];
+        assert_eq!((t.upper, t.lower), (1, 0));
+        assert_eq!(t.freq, 88.6318473e9);
+        assert!((t.A / 2.407e-05 - 1.0).abs() < 1.0e-12);
+        let expected = [
+            (-1.4316e6, 3.0 / 9.0),
+            (0.0, 5.0 / 9.0),
+            (2.0893e6, 1.0 / 9.0),
+        ];
+        assert_eq!(t.hyperfine.len(), expected.len());
+        for (hf, (offset, relInt)) in t.hyperfine.iter().zip(expected) {
+            assert!((hf.offset - offset).abs() < 1.0);
+            assert!((hf.relInt - relInt).abs() < 1.0e-12);
+        }
+
+        // Labels that do not end in hyperfine quantum numbers are not
+        // grouped, however alike they look
+        let h2o = EmitterData::read_lamda(H2O.as_bytes()).unwrap();
+        assert_eq!(h2o.levels.len(), 2);
+        assert!(h2o.transitions[0].hyperfine.is_empty());
+        assert!(lamda("co")
+            .transitions
+            .iter()
+            .all(|t| t.hyperfine.is_empty()));
+
+        // A split level needs its hyperfine quantum number
+        let path = crate::testutil::testdata().join("LAMDA").join("hcn.dat");
+        let text = std::fs::read_to_string(path).unwrap();
+        let bad = text.replace("    1_2\n", "\n");
+        assert!(matches!(
+            EmitterData::read_lamda(bad.as_bytes()),
+            Err(DespoticError::ParseData {
+                line_number: 10,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn hyperfine_rates_are_merged() {
+        let path = crate::testutil::testdata().join("LAMDA").join("hcn.dat");
+        let text = std::fs::read_to_string(path).unwrap().replace(
+            "!NUMBER OF COLL PARTNERS\n0\n",
+            "\
+!NUMBER OF COLL PARTNERS
+1
+!COLLISIONS BETWEEN
+1 HCN-H2
+!NUMBER OF COLL TRANS
+5
+!NUMBER OF COLL TEMPS
+2
+!COLL TEMPS
+   10.0   20.0
+!TRANS + UP + LOW + COLLRATES(cm^3 s^-1)
+    1    2    1  1.0e-11  2.0e-11
+    2    3    1  2.0e-11  4.0e-11
+    3    4    1  4.0e-11  8.0e-11
+    4    3    2  5.0e-11  1.0e-10
+    5    4    3  5.0e-11  1.0e-10
+",
+        );
+        let hcn = EmitterData::read_lamda(text.as_bytes()).unwrap();
+        let table = hcn.partners[0].table().unwrap();
+        assert_eq!(hcn.partners[0].n_transitions, 5);
+
+        // Rates within J = 1 drop out, and those to J = 0 are
+        // averaged over F weighted by 3, 5, 1
+        assert_eq!((&table.upper[..], &table.lower[..]), (&[1][..], &[0][..]));
+        let k = 17.0 / 9.0 * 1.0e-11;
+        assert!((table.rates[0][0] / k - 1.0).abs() < 1.0e-12);
+        assert!((table.rates[0][1] / (2.0 * k) - 1.0).abs() < 1.0e-12);
+
+        // Tables read on demand are merged the same way
+        let path = std::env::temp_dir().join(format!("despoticir-hcn-{}.dat", std::process::id()));
+        std::fs::write(&path, &text).unwrap();
+        let lazy = EmitterData::from_lamda_lazy(&path).unwrap();
+        let lazy_table = lazy.partners[0].table().unwrap();
+        assert_eq!(lazy_table.upper, table.upper);
+        assert_eq!(lazy_table.rates, table.rates);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn huge_counts_are_parse_errors() {
+        // Each count runs past the end of the data it introduces
+        let huge = "1000000000000000000";
+        for count in ["LEVELS\n2\n", "TRANSITIONS\n1\n", "PARTNERS\n0\n"] {
+            let (header, _) = count.split_once('\n').unwrap();
+            let text = H2O.replace(count, &format!("{header}\n{huge}\n"));
+            assert!(matches!(
+                EmitterData::read_lamda(text.as_bytes()),
+                Err(DespoticError::ParseData { .. })
+            ));
+        }
+        let co = std::fs::read_to_string(crate::testutil::testdata().join("LAMDA").join("co.dat"))
+            .unwrap()
+            .replacen("COLL TRANS\n15\n", &format!("COLL TRANS\n{huge}\n"), 1);
+        assert!(matches!(
+            EmitterData::read_lamda(co.as_bytes()),
+            Err(DespoticError::ParseData { .. })
+        ));
+    }
+
+    #[test]
+    fn trailing_fields_of_a_transition_are_ignored() {
+        // Anything after E_u is not read, however it looks
+        for extra in [
+            "HFS 9223372036854775808",
+            "HFS 18446744073709551615 0.0 -1.0",
+        ] {
+            let text = H2O.replace("61.0\n", &format!("61.0  {extra}\n"));
+            let h2o = EmitterData::read_lamda(text.as_bytes()).unwrap();
+            assert_eq!(h2o.transitions[0].Tupper, 61.0);
+            assert!(h2o.transitions[0].hyperfine.is_empty());
+        }
+    }
+
+    #[test]
+    fn frequency_units() {
+        let ghz = EmitterData::read_lamda(H2O.as_bytes()).unwrap();
+        let mhz = H2O.replace("556.9359877", "556935.9877");
+        let mhz = EmitterData::read_lamda_with(mhz.as_bytes(), FreqUnit::MHz).unwrap();
+        let (a, b) = (ghz.transitions[0].freq, mhz.transitions[0].freq);
+        assert!((a - b).abs() <= 1.0e-15 * a, "{a} vs {b}");
+        assert!((a - 5.569359877e11).abs() <= 1.0e-15 * a);
+
+        // The line spans the 18.577 cm^-1 between the two levels
+        let wavenumber = H2O.replace("556.9359877", "18.57737");
+        let wavenumber =
+            EmitterData::read_lamda_with(wavenumber.as_bytes(), FreqUnit::InvCm).unwrap();
+        assert!((wavenumber.transitions[0].freq / a - 1.0).abs() < 1.0e-6);
+    }
+
+    #[test]
+    fn partition_function_of_a_large_rotor() {
+        // A linear rotor with rotational constant 1 cm^-1 and 400
+        // levels, the highest at 160400 cm^-1
+        let nlev = 400;
+        let mut text = format!(
+            "!MOLECULE\nrotor\n!MOLECULAR WEIGHT\n50.0\n!NUMBER OF ENERGY LEVELS\n{nlev}\n!LEVEL + ENERGIES(cm^-1) + WEIGHT + J\n"
+        );
+        for J in 0..nlev {
+            text += &format!("{} {} {} {J}\n", J + 1, J * (J + 1), 2 * J + 1);
+        }
+        text += "!NUMBER OF RADIATIVE TRANSITIONS\n0\n!TRANS + UP + LOW + EINSTEINA(s^-1) + FREQ(GHz) + E_u(K)\n!NUMBER OF COLL PARTNERS\n0\n";
+        let mut rotor = EmitterData::read_lamda(text.as_bytes()).unwrap();
+
+        // Z = kT / (h c B) + 1/3 in the high-temperature limit
+        let T = 5000.0;
+        let Z = rotor.partition_function(T);
+        let expected = kB * T / (h * c) + 1.0 / 3.0;
+        assert!(
+            (Z / expected - 1.0).abs() < 1.0e-3,
+            "Z = {Z}, expected {expected}"
+        );
+        let pops = rotor.lte_pops(T);
+        assert!(pops.iter().all(|p| p.is_finite()));
+        assert!((pops.iter().sum::<f64>() - 1.0).abs() < 1.0e-12);
+
+        // Energies far below zero would overflow the final factor
+        for level in &mut rotor.levels {
+            level.energy -= 1.0e-9;
+        }
+        let Z = rotor.partition_function(T);
+        assert!(Z.is_finite() && Z > 1.0e300);
+    }
+
+    #[test]
+    fn co_partners() {
+        let info = lamda("co").partners();
+        let expected = [(Partner::ParaH2, "p-H2"), (Partner::OrthoH2, "o-H2")];
+        assert_eq!(info.len(), expected.len());
+        for (p, (partner, name)) in info.iter().zip(expected) {
+            assert_eq!((p.partner, p.name), (partner, name));
+            assert_eq!(p.n_transitions, 15);
+            assert_eq!(p.t_range, (10.0, 2000.0));
+        }
+        assert!(EmitterData::read_lamda(H2O.as_bytes())
+            .unwrap()
+            .partners()
+            .is_empty());
+    }
+
+    #[test]
+    fn lazy_tables_are_read_on_first_access() {
+        let path = crate::testutil::testdata().join("LAMDA").join("co.dat");
+        let co = EmitterData::from_lamda_lazy(&path).unwrap();
+        let eager = lamda("co");
+        assert_eq!(co.levels.len(), eager.levels.len());
+        assert_eq!(co.transitions.len(), eager.transitions.len());
+        assert_eq!(co.partners(), eager.partners());
+        assert_eq!(co.tables_loaded(), 0);
+        assert_eq!(eager.tables_loaded(), 2);
+
+        let table = co.partners[1].table().unwrap();
+        assert_eq!(co.tables_loaded(), 1);
+        assert!(!co.partners[0].is_loaded());
+        let expected = eager.partners[1].table().unwrap();
+        assert_eq!(table.partner, Partner::OrthoH2);
+        assert_eq!(
+            (&table.upper, &table.lower),
+            (&expected.upper, &expected.lower)
+        );
+        assert_eq!(table.rates, expected.rates);
+
+        // A second access reuses the table
+        assert!(std::ptr::eq(co.partners[1].table().unwrap(), table));
+        assert_eq!(co.tables_loaded(), 1);
+    }
+
+    #[test]
+    fn co_line_frequencies_ascend() {
+        let freqs = lamda("co").line_frequencies();
+        assert!(freqs.len() > 1);
+        assert!(freqs.windows(2).all(|w| w[0] < w[1]));
+        assert!((freqs[0] / 115.2712018e9 - 1.0).abs() < 1.0e-6);
+    }
+}
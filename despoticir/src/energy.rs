@@ -0,0 +1,659 @@
+use crate::cloud::Cloud;
+use crate::consts::{a, c, kB, mH};
+use crate::sum::kahan_sum;
+use crate::DespoticError;
+use std::collections::{BTreeMap, HashMap};
+
+/// Energy density of the solar-neighborhood starlight field, in
+/// erg cm^-3 (Draine 2011)
+const U_ISRF: f64 = 5.29e-13;
+
+/// Photoelectric heating rate per H nucleus for the solar-neighborhood
+/// ISRF and Milky Way dust, in erg s^-1 H^-1
+const GAMMA_PE_MW: f64 = 4.0e-26;
+
+/// Coefficient of T^1/2 in the thermal conductivity of neutral gas,
+/// in erg s^-1 cm^-1 K^-3/2 (Parker 1953)
+const KAPPA_NEUTRAL: f64 = 2.5e3;
+/// Coefficient of T^5/2 in the Spitzer conductivity of fully ionized
+/// gas, in erg s^-1 cm^-1 K^-7/2, and the Coulomb logarithm assumed
+const KAPPA_SPITZER: f64 = 1.84e-5;
+const LN_COULOMB: f64 = 30.0;
+
+/// Case B recombination rate coefficient of hydrogen at 10^4 K, in
+/// cm^3 s^-1 (Draine 2011, eq. 14.6)
+const ALPHA_B_1E4: f64 = 2.54e-13;
+
+/// Whether a user-supplied rate heats or cools the gas
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RateKind {
+    Heating,
+    Cooling,
+}
+
+/// How the level populations used for line cooling are found
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CoolingMode {
+    /// full statistical equilibrium with escape probabilities
+    #[default]
+    Full,
+    /// statistical equilibrium between the two lowest levels of each
+    /// emitter, cooling only through the line connecting them
+    TwoLevel,
+    /// LTE populations at the gas temperature
+    Lte,
+}
+
+/// A user-supplied heating or cooling term for the gas
+///
+/// The function returns a rate in erg s^-1 H^-1 that is non-negative
+/// for both kinds; kind says whether it adds or removes energy.
+pub struct CustomRate {
+    pub f: Box<dyn Fn(&Cloud) -> f64 + Send + Sync>,
+    pub kind: RateKind,
+}
+
+impl std::fmt::Debug for CustomRate {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CustomRate")
+            .field("kind", &self.kind)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Heating and cooling rates of the gas and dust in a cloud
+///
+/// All rates are in erg s^-1 H^-1. Heating terms (Gamma) are positive
+/// when they add energy, cooling terms (Lambda) are positive when they
+/// remove it, and PsiGD is positive when energy flows from dust to gas.
+#[derive(Debug, Clone)]
+pub struct EnergyRates {
+    /// photoelectric heating of the gas
+    pub GammaPE: f64,
+    /// cosmic ray heating of the gas
+    pub GammaCR: f64,
+    /// heating of dust by the ISRF
+    pub GammaDustISRF: f64,
+    /// heating of dust by the CMB
+    pub GammaDustCMB: f64,
+    /// heating of dust by the dust-reprocessed IR field
+    pub GammaDustIR: f64,
+    /// cooling of dust by thermal emission
+    pub LambdaDust: f64,
+    /// energy transfer from dust to gas by collisions
+    pub PsiGD: f64,
+    /// net cooling of the gas by line emission, keyed by emitter name
+    pub LambdaLine: HashMap<String, f64>,
+    /// heating of the gas by user-supplied terms
+    pub GammaCustom: f64,
+    /// cooling of the gas by user-supplied terms
+    pub LambdaCustom: f64,
+    /// cooling of the gas by radiative recombination of H+
+    pub LambdaRec: f64,
+    /// net rate of change of gas energy
+    pub dEdtGas: f64,
+    /// net rate of change of dust energy
+    pub dEdtDust: f64,
+}
+
+impl EnergyRates {
+    /// Total heating of the gas
+    ///
+    /// Returns
+    ///    GammaPE + GammaCR + GammaCustom, plus PsiGD if the dust is
+    ///    warmer than the gas, in erg s^-1 H^-1
+    pub fn total_heating(&self) -> f64 {
+        self.GammaPE + self.GammaCR + self.GammaCustom + self.PsiGD.max(0.0)
+    }
+
+    /// Total cooling of the gas
+    ///
+    /// Returns
+    ///    the line cooling summed over emitters plus LambdaCustom and
+    ///    LambdaRec, plus -PsiGD if the gas is warmer than the dust,
+    ///    in erg s^-1 H^-1
+    ///
+    /// Remarks
+    ///    Line cooling is net of absorption of background photons, so
+    ///    a line seen in absorption reduces the total
+    pub fn total_cooling(&self) -> f64 {
+        kahan_sum(self.LambdaLine.values().copied())
+            + self.LambdaCustom
+            + self.LambdaRec
+            + (-self.PsiGD).max(0.0)
+    }
+
+    /// Net rate of change of gas energy
+    ///
+    /// Returns
+    ///    total_heating - total_cooling, in erg s^-1 H^-1; this
+    ///    equals dEdtGas up to round-off
+    pub fn net(&self) -> f64 {
+        self.total_heating() - self.total_cooling()
+    }
+}
+
+impl Cloud {
+    /// Planck-mean dust cross section per H nucleus at temperature T,
+    /// in cm^2 H^-1
+    fn sigma_dust(&self, T: f64) -> f64 {
+        self.dust.Zd as f64 * self.dust.sigma10 as f64 * (T / 10.0).powf(self.dust.beta as f64)
+    }
+
+    /// Heating rates of the dust by the attenuated ISRF, the CMB, and
+    /// the dust-reprocessed IR field, in erg s^-1 H^-1
+    fn dust_heating(&self) -> [f64; 3] {
+        let (TCMB, TradDust) = (self.rad.TCMB as f64, self.rad.TradDust as f64);
+        let sigmaISRF = self.dust.sigmaISRF as f64;
+        let GammaDustISRF = self.dust.Zd as f64
+            * sigmaISRF
+            * self.rad.chi as f64
+            * c
+            * U_ISRF
+            * (-self.colDen as f64 * sigmaISRF / 2.0).exp();
+        let GammaDustCMB = self.sigma_dust(TCMB) * c * a * TCMB.powi(4);
+        let GammaDustIR =
+            self.rad.fdDilute as f64 * self.sigma_dust(TradDust) * c * a * TradDust.powi(4);
+        [GammaDustISRF, GammaDustCMB, GammaDustIR]
+    }
+
+    /// Rate of energy transfer from dust to gas by collisions, in
+    /// erg s^-1 H^-1
+    fn psi_gd(&self) -> f64 {
+        let (Tg, Td) = (self.Tg as f64, self.Td as f64);
+        self.dust.alphaGD as f64 * self.dust.Zd as f64 * self.nH as f64 * Tg.sqrt() * (Td - Tg)
+    }
+
+    /// Net rate of change of dust energy, in erg s^-1 H^-1
+    ///
+    /// Remarks
+    ///    This is dedt().dEdtDust, but is much cheaper to compute
+    ///    because it does not require the gas line cooling
+    pub(crate) fn dedt_dust(&self) -> f64 {
+        let Td = self.Td as f64;
+        self.dust_heating().iter().sum::<f64>()
+            - self.sigma_dust(Td) * c * a * Td.powi(4)
+            - self.psi_gd()
+    }
+
+    /// Compute the rates of heating and cooling of the gas and dust
+    ///
+    /// Parameters
+    ///    None
+    ///
+    /// Returns
+    ///    the individual heating and cooling terms and the net rates
+    ///    of change of gas and dust energy
+    ///
+    /// Remarks
+    ///    Line cooling requires solving for the level populations of
+    ///    every emitter not marked energySkip, so this can be
+    ///    expensive for clouds with many emitters. An error is
+    ///    returned if the composition fails Composition::validate,
+    ///    and a ZeroDensity error if nH is not positive. If the cloud
+    ///    is dirty, cached level populations are discarded first, so
+    ///    the solve starts afresh.
+    pub fn dedt(&self) -> Result<EnergyRates, DespoticError> {
+        self.check_density()?;
+        self.comp.validate()?;
+        self.refresh_if_dirty();
+        let Td = self.Td as f64;
+        let colDen = self.colDen as f64;
+        let Zd = self.dust.Zd as f64;
+        let chi = self.rad.chi as f64;
+
+        // Gas heating by the photoelectric effect and by cosmic rays
+        let GammaPE = GAMMA_PE_MW * chi * Zd * (-colDen * self.dust.sigmaPE as f64 / 2.0).exp();
+        let GammaCR = self.rad.ionRate as f64 * self.comp.qIon as f64;
+
+        // Dust heating by the attenuated ISRF, the CMB, and the IR
+        // field, and cooling by thermal emission
+        let [GammaDustISRF, GammaDustCMB, GammaDustIR] = self.dust_heating();
+        let LambdaDust = self.sigma_dust(Td) * c * a * Td.powi(4);
+
+        // Gas-dust energy exchange
+        let PsiGD = self.psi_gd();
+
+        // Line cooling
+        let mut LambdaLine = HashMap::new();
+        for (name, em) in &self.emitters {
+            if !em.energySkip {
+                LambdaLine.insert(name.clone(), em.line_cool(self)?);
+            }
+        }
+
+        // User-supplied terms
+        let custom = |kind| {
+            self.customRates
+                .iter()
+                .filter(|r| r.kind == kind)
+                .map(|r| (r.f)(self))
+                .sum::<f64>()
+        };
+        let GammaCustom = custom(RateKind::Heating);
+        let LambdaCustom = custom(RateKind::Cooling);
+
+        // Kinetic energy carried off by recombining electrons
+        let LambdaRec = self.recombination_cooling();
+
+        let dEdtGas = GammaPE + GammaCR + GammaCustom
+            - kahan_sum(LambdaLine.values().copied())
+            - LambdaCustom
+            - LambdaRec
+            + PsiGD;
+        let dEdtDust = GammaDustISRF + GammaDustCMB + GammaDustIR - LambdaDust - PsiGD;
+        Ok(EnergyRates {
+            GammaPE,
+            GammaCR,
+            GammaDustISRF,
+            GammaDustCMB,
+            GammaDustIR,
+            LambdaDust,
+            PsiGD,
+            LambdaLine,
+            GammaCustom,
+            LambdaCustom,
+            LambdaRec,
+            dEdtGas,
+            dEdtDust,
+        })
+    }
+
+    /// Add a user-supplied heating or cooling term for the gas
+    ///
+    /// Parameters
+    ///    f : function
+    ///       function of the cloud returning the rate, in erg s^-1
+    ///       H^-1
+    ///    kind : RateKind
+    ///       whether the rate heats or cools the gas
+    ///
+    /// Returns
+    ///    Nothing
+    ///
+    /// Remarks
+    ///    Custom terms are included in dedt, and hence in all
+    ///    calculations of thermal balance
+    pub fn add_custom_rate(&mut self, f: Box<dyn Fn(&Cloud) -> f64 + Send + Sync>, kind: RateKind) {
+        self.customRates.push(CustomRate { f, kind });
+    }
+
+    /// Net cooling of the gas by line emission
+    ///
+    /// Parameters
+    ///    None
+    ///
+    /// Returns
+    ///    net line cooling rate summed over all emitters not marked
+    ///    energySkip, in erg s^-1 H^-1
+    ///
+    /// Remarks
+    ///    Absorption of CMB and IR photons is subtracted, so a cloud
+    ///    in equilibrium with a CMB-only background at Tg = TCMB has
+    ///    zero net line cooling. Level populations are found as
+    ///    selected by the cloud's coolingMode.
+    pub fn gas_line_cool(&self) -> Result<f64, DespoticError> {
+        Ok(kahan_sum(self.cooling_by_species()?.into_values()))
+    }
+
+    /// Net cooling of the gas by the [CII] 158 micron line
+    ///
+    /// Parameters
+    ///    None
+    ///
+    /// Returns
+    ///    net cooling rate in the line, in erg s^-1 H^-1
+    ///
+    /// Remarks
+    ///    This is the lumPerH of cii_line, so it is net of absorption
+    ///    of background photons and counts the line even if the C+
+    ///    emitter is marked energySkip. Errors are as for cii_line.
+    pub fn cii_cooling(&self) -> Result<f64, DespoticError> {
+        Ok(self.cii_line()?.lumPerH)
+    }
+
+    /// Net cooling of the gas by line emission per unit mass
+    ///
+    /// Parameters
+    ///    None
+    ///
+    /// Returns
+    ///    gas_line_cool divided by the mass per H nucleus, muH mH, in
+    ///    erg s^-1 g^-1
+    ///
+    /// Remarks
+    ///    An error is returned if comp.muH has not been computed
+    pub fn cooling_per_gram(&self) -> Result<f64, DespoticError> {
+        if self.comp.muH <= 0.0 {
+            return Err(DespoticError::MissingField("muH".to_string()));
+        }
+        Ok(self.gas_line_cool()? / (self.comp.muH as f64 * mH))
+    }
+
+    /// Net cooling of the gas by line emission, broken down by emitter
+    ///
+    /// Parameters
+    ///    None
+    ///
+    /// Returns
+    ///    net line cooling rate of each emitter not marked energySkip,
+    ///    in erg s^-1 H^-1, keyed by emitter name
+    pub fn cooling_by_species(&self) -> Result<BTreeMap<String, f64>, DespoticError> {
+        let mut cool = BTreeMap::new();
+        for (name, em) in &self.emitters {
+            if !em.energySkip {
+                cool.insert(name.clone(), em.line_cool(self)?);
+            }
+        }
+        Ok(cool)
+    }
+
+    /// Cooling of the gas by radiative recombination of H+
+    ///
+    /// Parameters
+    ///    None
+    ///
+    /// Returns
+    ///    rate at which recombining electrons remove kinetic energy
+    ///    from the gas, in erg s^-1 H^-1
+    ///
+    /// Remarks
+    ///    The rate is xe xH+ nH alpha_B(Tg) <E_rec>, using the case B
+    ///    recombination coefficient alpha_B = 2.54e-13 T4^(-0.8163 -
+    ///    0.0208 ln T4) cm^3 s^-1 and mean kinetic energy lost per
+    ///    recombination <E_rec> = (0.684 - 0.0416 ln T4) k Tg, where
+    ///    T4 = Tg / 10^4 K (Draine 2011, eqs. 14.6 and 27.23). The
+    ///    ionization energy released is not counted, since case B
+    ///    assumes the ionizing photons are reabsorbed locally. The
+    ///    rate is zero if Tg is not positive.
+    pub fn recombination_cooling(&self) -> f64 {
+        let Tg = self.Tg as f64;
+        if Tg <= 0.0 {
+            return 0.0;
+        }
+        let lnT4 = (Tg / 1.0e4).ln();
+        let alphaB = ALPHA_B_1E4 * (lnT4 * (-0.8163 - 0.0208 * lnT4)).exp();
+        let Erec = (0.684 - 0.0416 * lnT4) * kB * Tg;
+        self.comp.xe as f64 * self.comp.xHplus as f64 * self.nH as f64 * alphaB * Erec
+    }
+
+    /// Net cooling of the dust by thermal emission
+    ///
+    /// Parameters
+    ///    None
+    ///
+    /// Returns
+    ///    dust thermal emission minus absorption of CMB photons, in
+    ///    erg s^-1 H^-1; zero when Td = TCMB
+    pub fn dust_cool(&self) -> f64 {
+        let (Td, TCMB) = (self.Td as f64, self.rad.TCMB as f64);
+        self.sigma_dust(Td) * c * a * Td.powi(4) - self.sigma_dust(TCMB) * c * a * TCMB.powi(4)
+    }
+
+    /// Compute the cooling time of the gas
+    ///
+    /// Parameters
+    ///    None
+    ///
+    /// Returns
+    ///    the ratio of the thermal energy of the gas to the magnitude
+    ///    of its net rate of energy change, in s
+    ///
+    /// Remarks
+    ///    The specific heat comp.cv must have been computed, which
+    ///    happens automatically when a cloud with a nonzero Tg is
+    ///    read. An error is returned if heating and cooling balance to
+    ///    within round-off, since the cooling time is then infinite.
+    pub fn cooling_time(&self) -> Result<f64, DespoticError> {
+        let cv = self
+            .comp
+            .cv
+            .ok_or_else(|| DespoticError::MissingField("cv".to_string()))?;
+        Ok(cv as f64 * kB * self.Tg as f64 / self.net_gas_rate()?.abs())
+    }
+
+    /// Strength of gas-dust coupling relative to line cooling
+    ///
+    /// Parameters
+    ///    None
+    ///
+    /// Returns
+    ///    |PsiGD| / |gas_line_cool|; values much greater than 1 mean
+    ///    the gas temperature tracks the dust temperature
+    ///
+    /// Remarks
+    ///    The ratio is zero if Tg = Td, since there is then no
+    ///    exchange, and infinite if there is exchange but no net line
+    ///    cooling. Errors are as for gas_line_cool.
+    pub fn gas_dust_coupling_ratio(&self) -> Result<f64, DespoticError> {
+        self.check_density()?;
+        let psi = self.psi_gd().abs();
+        if psi == 0.0 {
+            return Ok(0.0);
+        }
+        Ok(psi / self.gas_line_cool()?.abs())
+    }
+
+    /// Field length, the scale below which thermal conduction damps
+    /// thermal instability
+    ///
+    /// Parameters
+    ///    None
+    ///
+    /// Returns
+    ///    lambda_F = (kappa Tg / (nH |dEdtGas|))^1/2, in cm
+    ///
+    /// Remarks
+    ///    The conductivity kappa interpolates in xH+ between that of
+    ///    neutral gas, 2.5e3 Tg^1/2 erg s^-1 cm^-1 K^-1, and the
+    ///    Spitzer value for ionized gas with a Coulomb logarithm of
+    ///    30. As for cooling_time, an error is returned if heating
+    ///    and cooling balance to within round-off, since the Field
+    ///    length is then infinite.
+    pub fn field_length(&self) -> Result<f64, DespoticError> {
+        let dEdt = self.net_gas_rate()?;
+        let Tg = self.Tg as f64;
+        let fIon = (self.comp.xHplus as f64).clamp(0.0, 1.0);
+        let kappa = (1.0 - fIon) * KAPPA_NEUTRAL * Tg.sqrt()
+            + fIon * KAPPA_SPITZER * Tg.powf(2.5) / LN_COULOMB;
+        Ok((kappa * Tg / (self.nH as f64 * dEdt.abs())).sqrt())
+    }
+
+    /// Net rate of change of gas energy, dEdtGas, failing with
+    /// NoNetCooling if heating and cooling balance to within
+    /// round-off
+    fn net_gas_rate(&self) -> Result<f64, DespoticError> {
+        let rates = self.dedt()?;
+        let scale = rates.GammaPE
+            + rates.GammaCR
+            + rates.GammaCustom
+            + rates.LambdaCustom
+            + rates.LambdaRec
+            + rates.PsiGD.abs()
+            + rates.LambdaLine.values().map(|l| l.abs()).sum::<f64>();
+        if rates.dEdtGas.abs() <= 1.0e-10 * scale {
+            return Err(DespoticError::NoNetCooling);
+        }
+        Ok(rates.dEdtGas)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RateKind;
+    use crate::cloud::Cloud;
+    use crate::consts::{kB, mH, G};
+    use crate::testutil::{co_cloud, init, read_cloud};
+    use crate::DespoticError;
+
+    #[test]
+    fn warm_cloud_cooling_time() {
+        let mut cloud = co_cloud();
+        cloud.set_tg(100.0);
+        let t_cool = cloud.cooling_time().unwrap();
+        assert!(t_cool.is_finite() && t_cool > 0.0);
+
+        // Warm molecular gas cools quickly compared to its collapse
+        let t_ff = (3.0 * std::f64::consts::PI / (32.0 * G * cloud.rho())).sqrt();
+        assert!(t_cool < t_ff, "t_cool = {t_cool:e}, t_ff = {t_ff:e}");
+        assert!(
+            t_cool > 1.0e-4 * t_ff,
+            "t_cool = {t_cool:e}, t_ff = {t_ff:e}"
+        );
+    }
+
+    #[test]
+    fn no_net_cooling_at_cmb_temperature() {
+        let mut cloud = co_cloud();
+        let warm = cloud.gas_line_cool().unwrap();
+        let TCMB = cloud.rad.TCMB;
+        cloud.set_tg(TCMB);
+        cloud.set_td(TCMB);
+        assert!(cloud.gas_line_cool().unwrap().abs() < 1.0e-6 * warm);
+        assert_eq!(cloud.dust_cool(), 0.0);
+    }
+
+    #[test]
+    fn cooling_breakdown_sums_to_total() {
+        init();
+        let cloud = Cloud::simple(1.0e3, 10.0, &[("co", 1.0e-4), ("c+", 1.0e-5)]).unwrap();
+        let bySpecies = cloud.cooling_by_species().unwrap();
+        let total = cloud.gas_line_cool().unwrap();
+        assert_eq!(bySpecies.len(), 2);
+        // The second solve starts from the first, so they agree only
+        // to the convergence tolerance
+        assert!((bySpecies.values().sum::<f64>() / total - 1.0).abs() < 1.0e-5);
+        assert!(bySpecies["co"] > 10.0 * bySpecies["c+"]);
+    }
+
+    #[test]
+    fn custom_heating_raises_equilibrium_temperature() {
+        let mut cloud = co_cloud();
+        cloud.set_gas_temp_eq().unwrap();
+        let Tg0 = cloud.Tg;
+
+        cloud.add_custom_rate(Box::new(|_| 1.0e-26), RateKind::Heating);
+        assert_eq!(cloud.dedt().unwrap().GammaCustom, 1.0e-26);
+        cloud.set_gas_temp_eq().unwrap();
+        assert!(cloud.Tg > 1.5 * Tg0, "Tg = {} -> {}", Tg0, cloud.Tg);
+    }
+
+    #[test]
+    fn cooling_per_gram_converts_from_per_h() {
+        let mut cloud = co_cloud();
+        let perH = cloud.gas_line_cool().unwrap();
+        let perGram = cloud.cooling_per_gram().unwrap();
+        // The second solve starts from the first, so they agree only
+        // to the convergence tolerance
+        let expected = perH / (cloud.comp.muH as f64 * mH);
+        assert!((perGram / expected - 1.0).abs() < 1.0e-5);
+
+        cloud.comp.muH = 0.0;
+        assert!(matches!(
+            cloud.cooling_per_gram(),
+            Err(crate::DespoticError::MissingField(f)) if f == "muH"
+        ));
+    }
+
+    #[test]
+    fn zero_density_is_an_error() {
+        let mut cloud = co_cloud();
+        cloud.nH = 0.0;
+        let zero = |r: Result<_, DespoticError>| matches!(r, Err(DespoticError::ZeroDensity));
+        assert!(zero(cloud.dedt().map(|_| ())));
+        assert!(zero(cloud.cooling_time().map(|_| ())));
+        assert!(zero(cloud.emitters["co"].level_pops(&cloud).map(|_| ())));
+    }
+
+    #[test]
+    fn cnm_cii_cooling() {
+        let cloud = crate::testutil::read_cloud(
+            "nH = 30\ncolDen = 1e20\nxHI = 1\nxHe = 0.1\nxe = 1e-3\nTg = 80\nTd = 20\n\
+             emitter = c+ 1.4e-4\n",
+        )
+        .unwrap();
+        // The cold neutral medium cools at about 1e-26 erg s^-1 per H
+        // in [CII] (Wolfire et al. 2003)
+        let cool = cloud.cii_cooling().unwrap();
+        assert!(cool > 3.0e-27 && cool < 3.0e-26, "Lambda_CII = {cool:e}");
+        let line = cloud.cii_line().unwrap();
+        assert!((line.freq / 1.9005e12 - 1.0).abs() < 1.0e-4);
+        assert!(line.intIntensity > 0.0);
+        let total = cloud.dedt().unwrap().LambdaLine["c+"];
+        assert!((total / cool - 1.0).abs() < 1.0e-5);
+    }
+
+    #[test]
+    fn field_length_when_cooling() {
+        let mut cloud = co_cloud();
+        cloud.set_tg(100.0);
+        let rates = cloud.dedt().unwrap();
+        assert!(rates.dEdtGas < 0.0);
+        let lambda = cloud.field_length().unwrap();
+        assert!(lambda.is_finite() && lambda > 0.0, "lambda_F = {lambda:e}");
+
+        // For neutral gas, lambda_F^2 = 2.5e3 Tg^1.5 / (nH |dEdtGas|)
+        let expected = (2.5e3 * 100.0_f64.powf(1.5) / (1.0e3 * rates.dEdtGas.abs())).sqrt();
+        assert!(
+            (lambda / expected - 1.0).abs() < 1.0e-3,
+            "{lambda:e} vs {expected:e}"
+        );
+    }
+
+    #[test]
+    fn gas_dust_coupling_grows_with_density() {
+        let ratio = |nH: f32| {
+            let mut cloud = co_cloud();
+            cloud.set_nh(nH);
+            cloud.set_td(10.0);
+            cloud.gas_dust_coupling_ratio().unwrap()
+        };
+        // Gas-dust exchange grows as nH^2 per H, but thick, thermalized
+        // line cooling only as nH or slower
+        let (diffuse, dense) = (ratio(1.0e2), ratio(1.0e7));
+        assert!(diffuse < 0.1, "ratio {diffuse} at 1e2 cm^-3");
+        assert!(dense > 10.0, "ratio {dense} at 1e7 cm^-3");
+
+        let mut cloud = co_cloud();
+        cloud.set_td(cloud.Tg);
+        assert_eq!(cloud.gas_dust_coupling_ratio().unwrap(), 0.0);
+    }
+
+    #[test]
+    fn recombination_cooling_needs_ionized_gas() {
+        // Molecular gas has no free protons to recombine
+        let cloud = co_cloud();
+        let rates = cloud.dedt().unwrap();
+        assert_eq!(rates.LambdaRec, 0.0);
+        assert!(rates.LambdaRec < 1.0e-6 * rates.LambdaLine["co"]);
+
+        // Fully ionized gas at 10^4 K: xe xH+ nH alpha_B 0.684 k T
+        let cloud = read_cloud("nH = 100\ncolDen = 1e20\nxH+ = 1\nTg = 1e4\nTd = 10\n").unwrap();
+        let rec = cloud.recombination_cooling();
+        let expected = 100.0 * 2.54e-13 * 0.684 * kB * 1.0e4;
+        assert!((rec / expected - 1.0).abs() < 1.0e-6, "{rec:e}");
+        let rates = cloud.dedt().unwrap();
+        assert_eq!(rates.LambdaRec, rec);
+        assert!(rec > 100.0 * (rates.GammaPE + rates.GammaCR));
+        assert!(rates.dEdtGas < -0.99 * rec);
+    }
+
+    #[test]
+    fn totals_split_net_rate() {
+        let mut cloud = co_cloud();
+        for Tg in [10.0, 20.0, 100.0] {
+            cloud.set_tg(Tg);
+            let rates = cloud.dedt().unwrap();
+            let (heat, cool) = (rates.total_heating(), rates.total_cooling());
+            assert!(heat > 0.0 && cool > 0.0);
+            assert_eq!(heat - cool, rates.net());
+            let scale = heat + cool;
+            assert!(
+                (rates.net() - rates.dEdtGas).abs() < 1.0e-12 * scale,
+                "{} vs {}",
+                rates.net(),
+                rates.dEdtGas
+            );
+        }
+    }
+}
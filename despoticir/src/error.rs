@@ -0,0 +1,123 @@
+/// Errors raised while reading or operating on a cloud
+///
+/// Line numbers are 1-based and count every physical line of the
+/// input, including blank and comment lines, so they can be used to
+/// jump straight to the offending line in an editor.
+///
+/// Errors that wrap a lower-level error also expose it through
+/// source().
+#[derive(Debug, thiserror::Error)]
+pub enum DespoticError {
+    /// an underlying I/O operation failed
+    #[error("{0}")]
+    Io(#[from] std::io::Error),
+    /// an HDF5 file could not be written
+    #[cfg(feature = "hdf5")]
+    #[error("HDF5 error: {0}")]
+    Hdf5(#[from] hdf5::Error),
+    /// a number could not be parsed
+    #[error("invalid number: {0}")]
+    ParseFloat(#[from] std::num::ParseFloatError),
+    /// a line of a cloud file could not be parsed
+    #[error("error parsing input line {line_number}: {}", line.trim_end())]
+    ParseLine { line_number: usize, line: String },
+    /// a line of a cloud file began with a keyword we do not know
+    #[error("unrecognized token {keyword} on line {line_number}")]
+    UnknownKeyword { line_number: usize, keyword: String },
+    /// a keyword was repeated in a cloud file read with
+    /// DuplicatePolicy::Error
+    #[error("keyword {keyword} on line {line_number} was already set on line {first_line}")]
+    DuplicateKeyword {
+        line_number: usize,
+        keyword: String,
+        first_line: usize,
+    },
+    /// a cloud file includes itself, directly or through other
+    /// included files
+    #[error("cloud file {} includes itself", .0.display())]
+    IncludeCycle(std::path::PathBuf),
+    /// a line of a LAMDA molecular data file could not be parsed
+    #[error("error parsing data file line {line_number}: {}", line.trim_end())]
+    ParseData { line_number: usize, line: String },
+    /// a data file could not be found locally or in the data directory
+    #[error("cannot find data file {0}")]
+    MissingData(String),
+    /// the hydrogen abundances xHI + xH+ + 2 xH2 do not sum to unity
+    #[error("total hydrogen abundance xHI + xH+ + 2 xH2 = {0} != 1")]
+    HydrogenBudget(f32),
+    /// an abundance or the H2 ortho-to-para ratio is negative or not
+    /// finite
+    #[error("invalid composition: {0}")]
+    InvalidComposition(String),
+    /// the cloud's density nH is zero or negative, so quantities
+    /// that scale with it cannot be computed
+    #[error("cloud density nH must be positive")]
+    ZeroDensity,
+    /// a tabulated spectrum is too short, or its frequencies are not
+    /// increasing or its intensities not finite and non-negative
+    #[error("invalid spectrum: {0}")]
+    InvalidSpectrum(String),
+    /// a quantity needed for a calculation has not been set
+    #[error("{0} has not been set")]
+    MissingField(String),
+    /// the rate matrix for the named emitter's level populations is
+    /// singular
+    #[error("singular rate matrix for level populations of {0}")]
+    SingularMatrix(String),
+    /// heating and cooling balance, so the gas has no net cooling
+    #[error("net cooling rate of the gas is zero")]
+    NoNetCooling,
+    /// an iterative calculation failed to converge
+    #[error("failed to converge: {0}")]
+    NoConvergence(String),
+    /// a species name is not known to the chemical network
+    #[error("unknown species {0}")]
+    UnknownSpecies(String),
+    /// the cloud has no emitter of the given name
+    #[error("cloud has no emitter {0}")]
+    UnknownEmitter(String),
+    /// a distance to the cloud is zero, negative or not finite
+    #[error("distance must be positive and finite, got {0}")]
+    InvalidDistance(f64),
+    /// a relative tolerance is not finite or is finer than a float
+    /// can resolve
+    #[error("relative tolerance must be finite and at least 2.2e-16, got {0}")]
+    InvalidTolerance(f64),
+    /// an emitter has no radiative transition between the given levels
+    #[error("{species} has no transition {upper} -> {lower}")]
+    UnknownTransition {
+        species: String,
+        upper: usize,
+        lower: usize,
+    },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::error::Error;
+
+    #[test]
+    fn io_error_exposes_source() {
+        crate::testutil::init();
+        let mut cloud = crate::cloud::Cloud::new(None::<&str>, true, false).unwrap();
+        let err = cloud.read("no/such/cloud/file.desp", false).unwrap_err();
+        assert!(matches!(err, DespoticError::Io(_)));
+        let source = err.source().unwrap();
+        let io = source.downcast_ref::<std::io::Error>().unwrap();
+        assert_eq!(io.kind(), std::io::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn messages() {
+        let err = DespoticError::ParseLine {
+            line_number: 3,
+            line: "nH 100  \n".to_string(),
+        };
+        assert_eq!(err.to_string(), "error parsing input line 3: nH 100");
+        assert!(err.source().is_none());
+        let err = DespoticError::from("x".parse::<f64>().unwrap_err());
+        assert_eq!(err.to_string(), "invalid number: invalid float literal");
+        assert!(err.source().is_some());
+    }
+}
@@ -0,0 +1,208 @@
+//! Grids of clouds
+//!
+//! Writing grids to HDF5 files is only available with the hdf5
+//! feature, which needs the HDF5 C library.
+
+use crate::cloud::Cloud;
+use crate::DespoticError;
+#[cfg(feature = "hdf5")]
+use std::collections::BTreeMap;
+#[cfg(feature = "hdf5")]
+use std::path::Path;
+
+/// One axis of a grid of clouds
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize))]
+pub struct GridAxis {
+    /// cloud file keyword that the axis varies, e.g. "nH" or "Tg"
+    pub name: String,
+    /// values the keyword takes along the axis
+    pub values: Vec<f64>,
+}
+
+/// Compact description of a grid of clouds, as the values taken by
+/// each of several cloud file keywords
+#[derive(Debug, Clone, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize))]
+pub struct GridSpec {
+    /// axes of the grid, the last varying fastest
+    pub axes: Vec<GridAxis>,
+}
+
+impl GridSpec {
+    /// Number of clouds in the grid, the product of the axis lengths
+    pub fn len(&self) -> usize {
+        self.axes.iter().map(|axis| axis.values.len()).product()
+    }
+
+    /// Whether the grid has no clouds, because some axis has no
+    /// values
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Expand the grid into clouds
+    ///
+    /// Parameters
+    ///    base : Cloud
+    ///       cloud whose properties are used for everything the axes
+    ///       do not vary
+    ///
+    /// Returns
+    ///    one cloud for each combination of axis values, in
+    ///    row-major order, with the last axis varying fastest
+    ///
+    /// Remarks
+    ///    Each cloud is a copy of base with the keywords of the axes
+    ///    overridden as by Cloud::apply_overrides, so derived
+    ///    quantities are recomputed. The copies have no chemical
+    ///    network or custom rates, which cannot be copied. An error
+    ///    is returned if an override fails, e.g. because a keyword is
+    ///    unknown. A grid with no axes expands to a single copy of
+    ///    base.
+    pub fn expand(&self, base: &Cloud) -> Result<Vec<Cloud>, DespoticError> {
+        let n = self.len();
+        let mut clouds = Vec::with_capacity(n);
+        for i in 0..n {
+            // Decompose the index into one index per axis
+            let mut rest = i;
+            let mut values = vec![String::new(); self.axes.len()];
+            for (k, axis) in self.axes.iter().enumerate().rev() {
+                let len = axis.values.len();
+                values[k] = axis.values[rest % len].to_string();
+                rest /= len;
+            }
+            let overrides: Vec<(&str, &str)> = self
+                .axes
+                .iter()
+                .zip(&values)
+                .map(|(axis, value)| (axis.name.as_str(), value.as_str()))
+                .collect();
+            let mut cloud = base.duplicate();
+            cloud.apply_overrides(&overrides)?;
+            clouds.push(cloud);
+        }
+        Ok(clouds)
+    }
+}
+
+/// Write the properties of a grid of clouds to an HDF5 file
+///
+/// Parameters
+///    results : array of Cloud
+///       the clouds of the grid, after they have been solved
+///    path : string
+///       name of the file to create; an existing file is overwritten
+///
+/// Returns
+///    Nothing
+///
+/// Remarks
+///    The file holds one dataset for each scalar property that
+///    Cloud::diff compares, named as there (e.g. "nH", "comp.muH",
+///    "emitters.co.abundance"), plus the visual extinction as "AV".
+///    Each dataset has one entry per cloud, in the order given.
+///    Properties a cloud does not have, such as an unset b_field or
+///    an emitter it lacks, are stored as NaN.
+#[cfg(feature = "hdf5")]
+pub fn write_hdf5(results: &[Cloud], path: &Path) -> Result<(), DespoticError> {
+    let mut columns: BTreeMap<String, Vec<f64>> = BTreeMap::new();
+    for (i, cloud) in results.iter().enumerate() {
+        let mut fields = cloud.scalars();
+        fields.push(("AV".to_string(), Some(cloud.a_v())));
+        for (name, value) in fields {
+            let column = columns
+                .entry(name)
+                .or_insert_with(|| vec![f64::NAN; results.len()]);
+            column[i] = value.unwrap_or(f64::NAN);
+        }
+    }
+    let file = hdf5::File::create(path)?;
+    for (name, values) in &columns {
+        file.new_dataset_builder()
+            .with_data(values.as_slice())
+            .create(name.as_str())?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::co_cloud;
+
+    #[cfg(feature = "hdf5")]
+    fn nh_grid() -> Vec<Cloud> {
+        [10.0, 100.0, 1000.0]
+            .into_iter()
+            .map(|nH| {
+                let mut cloud = co_cloud();
+                cloud.set_nh(nH);
+                cloud
+            })
+            .collect()
+    }
+
+    #[test]
+    fn expand_three_by_two() {
+        let spec = GridSpec {
+            axes: vec![
+                GridAxis {
+                    name: "nH".to_string(),
+                    values: vec![10.0, 100.0, 1000.0],
+                },
+                GridAxis {
+                    name: "Tg".to_string(),
+                    values: vec![15.0, 30.0],
+                },
+            ],
+        };
+        assert_eq!(spec.len(), 6);
+        let base = co_cloud();
+        let clouds = spec.expand(&base).unwrap();
+        let combos: Vec<(f32, f32)> = clouds.iter().map(|c| (c.nH, c.Tg)).collect();
+        assert_eq!(
+            combos,
+            [
+                (10.0, 15.0),
+                (10.0, 30.0),
+                (100.0, 15.0),
+                (100.0, 30.0),
+                (1000.0, 15.0),
+                (1000.0, 30.0)
+            ]
+        );
+        // Properties not on an axis come from the base cloud
+        assert!(clouds.iter().all(|c| c.colDen == base.colDen));
+        assert!(clouds
+            .iter()
+            .all(|c| c.emitters["co"].abundance_in(c) == base.emitters["co"].abundance_in(&base)));
+
+        let bad = GridSpec {
+            axes: vec![GridAxis {
+                name: "density".to_string(),
+                values: vec![1.0],
+            }],
+        };
+        assert!(bad.expand(&base).is_err());
+    }
+
+    #[cfg(feature = "hdf5")]
+    #[test]
+    fn hdf5_round_trip() {
+        let clouds = nh_grid();
+        let path = std::env::temp_dir().join("despoticir_grid_test.h5");
+        write_hdf5(&clouds, &path).unwrap();
+
+        let file = hdf5::File::open(&path).unwrap();
+        let nH: Vec<f64> = file.dataset("nH").unwrap().read_raw().unwrap();
+        assert_eq!(nH, [10.0, 100.0, 1000.0]);
+        let xCO: Vec<f64> = file
+            .dataset("emitters.co.abundance")
+            .unwrap()
+            .read_raw()
+            .unwrap();
+        assert!(xCO.iter().all(|&x| (x / 1.0e-4 - 1.0).abs() < 1.0e-6));
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,106 @@
+//! One-dimensional interpolation in tabulated data
+
+/// Interpolation scheme used between (and beyond) table entries
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Interp {
+    /// linear in both x and y, extrapolated linearly from the end
+    /// segments
+    LinLin,
+    /// linear in log x and log y, i.e. a power law between each pair
+    /// of entries, extrapolated as a power law from the end segments;
+    /// segments that touch non-positive values are treated linearly
+    LogLog,
+    /// linear within the table and extrapolated as a power law from
+    /// the end segments; if the end segment touches non-positive
+    /// values, the edge value is held constant instead
+    PowerLaw,
+}
+
+/// Interpolate in a table
+///
+/// Parameters
+///    x : float
+///       point at which to evaluate the interpolant
+///    xs : array
+///       abscissae of the table, in increasing order
+///    ys : array
+///       values of the table; must have the same length as xs
+///    mode : Interp
+///       interpolation scheme
+///
+/// Returns
+///    the interpolated value
+///
+/// Remarks
+///    A table with a single entry is treated as constant. Panics if
+///    the table is empty.
+pub fn interp1d(x: f64, xs: &[f64], ys: &[f64], mode: Interp) -> f64 {
+    let n = xs.len();
+    if n == 1 {
+        return ys[0];
+    }
+
+    // Bracketing entries; outside the table use the end segments
+    let i = xs[1..n - 1].partition_point(|&t| t < x);
+    let (x0, x1, y0, y1) = (xs[i], xs[i + 1], ys[i], ys[i + 1]);
+    let inside = x >= xs[0] && x <= xs[n - 1];
+    let positive = x > 0.0 && x0 > 0.0 && y0 > 0.0 && y1 > 0.0;
+    let lin = || y0 + (y1 - y0) * (x - x0) / (x1 - x0);
+    let pow = || y0 * (y1 / y0).powf((x / x0).ln() / (x1 / x0).ln());
+
+    match mode {
+        Interp::LinLin => lin(),
+        Interp::LogLog if positive => pow(),
+        Interp::LogLog => lin(),
+        Interp::PowerLaw if inside => lin(),
+        Interp::PowerLaw if positive => pow(),
+        Interp::PowerLaw if x < x0 => y0,
+        Interp::PowerLaw => y1,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const XS: [f64; 3] = [1.0, 10.0, 100.0];
+    const YS: [f64; 3] = [2.0, 20.0, 2000.0];
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() <= 1.0e-12 * b.abs()
+    }
+
+    #[test]
+    fn lin_lin() {
+        assert!(close(interp1d(5.5, &XS, &YS, Interp::LinLin), 11.0));
+        assert!(close(interp1d(10.0, &XS, &YS, Interp::LinLin), 20.0));
+        assert!(close(interp1d(0.0, &XS, &YS, Interp::LinLin), 0.0));
+        assert!(close(interp1d(110.0, &XS, &YS, Interp::LinLin), 2220.0));
+    }
+
+    #[test]
+    fn log_log() {
+        // y = 2 x on the first segment and 0.2 x^2 on the second
+        assert!(close(interp1d(3.0, &XS, &YS, Interp::LogLog), 6.0));
+        assert!(close(interp1d(30.0, &XS, &YS, Interp::LogLog), 180.0));
+        assert!(close(interp1d(0.1, &XS, &YS, Interp::LogLog), 0.2));
+        assert!(close(interp1d(1000.0, &XS, &YS, Interp::LogLog), 2.0e5));
+    }
+
+    #[test]
+    fn power_law() {
+        // Linear inside the table, power law beyond it
+        assert!(close(interp1d(30.0, &XS, &YS, Interp::PowerLaw), 460.0));
+        assert!(close(interp1d(0.1, &XS, &YS, Interp::PowerLaw), 0.2));
+        assert!(close(interp1d(1000.0, &XS, &YS, Interp::PowerLaw), 2.0e5));
+
+        // Edge values are held where a power law cannot be fit
+        let ys = [0.0, 1.0, 2.0];
+        assert_eq!(interp1d(0.5, &XS, &ys, Interp::PowerLaw), 0.0);
+    }
+
+    #[test]
+    fn single_entry_is_constant() {
+        assert_eq!(interp1d(7.0, &[1.0], &[3.0], Interp::LogLog), 3.0);
+    }
+}
@@ -1,23 +1,84 @@
 #![allow(non_snake_case)]
 
+pub mod chemistry;
 pub mod cloud;
+pub mod compare;
+pub mod composition;
+pub mod consts;
+pub mod diagnostics;
+pub mod dust;
+pub mod emitter;
+pub mod emitter_data;
+pub mod energy;
+pub mod error;
+pub mod grid;
+pub mod interp;
+mod linalg;
+pub mod lines;
+pub mod memo;
+pub mod parse;
+pub mod radiation;
+mod roots;
+mod sum;
+pub mod temperature;
+#[cfg(test)]
+mod testutil;
+pub mod units;
 
-/// Placeholder struct
-#[derive(Debug)]
-pub struct Composition;
+pub use chemistry::ChemNetwork;
+pub use composition::Composition;
+pub use dust::DustProp;
+pub use emitter::Emitter;
+pub use error::DespoticError;
+pub use radiation::Radiation;
 
-/// Placeholder struct
-#[derive(Debug)]
-pub struct DustProp;
+use std::path::PathBuf;
 
-/// Placeholder struct
-#[derive(Debug)]
-pub struct Radiation;
+/// Directory searched for data files (cloud files and the LAMDA
+/// database) that are not found relative to the working directory
+///
+/// This is the directory named by the DESPOTIC_DATA environment
+/// variable if it is set, and the crate's own directory otherwise.
+pub fn data_dir() -> PathBuf {
+    std::env::var_os("DESPOTIC_DATA")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from(env!("CARGO_MANIFEST_DIR")))
+}
 
-/// Placeholder struct
-#[derive(Debug)]
-pub struct Emitter;
+/// Emitting species whose LAMDA files are in the data directory
+///
+/// Returns
+///    the name of each species, taken from the stem of its .dat file
+///    in the LAMDA subdirectory of data_dir, sorted; empty if that
+///    directory does not exist
+///
+/// Remarks
+///    Any of these names can be passed to Emitter::new, or given on
+///    an EMITTER line of a cloud file, without naming the file
+pub fn available_emitters() -> Vec<String> {
+    let Ok(entries) = std::fs::read_dir(data_dir().join("LAMDA")) else {
+        return Vec::new();
+    };
+    let mut names: Vec<String> = entries
+        .filter_map(|entry| {
+            let path = entry.ok()?.path();
+            if path.extension()? != "dat" || !path.is_file() {
+                return None;
+            }
+            Some(path.file_stem()?.to_str()?.to_string())
+        })
+        .collect();
+    names.sort();
+    names
+}
 
-/// Placeholder struct
-#[derive(Debug)]
-pub struct ChemNetwork;
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fixture_emitters_are_available() {
+        testutil::init();
+        assert_eq!(available_emitters(), ["c+", "co", "hcn", "nocoll"]);
+    }
+}
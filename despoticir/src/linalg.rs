@@ -0,0 +1,35 @@
+//! Small dense linear algebra helpers
+
+/// Solve the linear system a x = b by Gaussian elimination with
+/// partial pivoting
+///
+/// Returns None if the matrix is singular.
+pub(crate) fn solve(mut a: Vec<Vec<f64>>, mut b: Vec<f64>) -> Option<Vec<f64>> {
+    let n = b.len();
+    for col in 0..n {
+        let pivot = (col..n).max_by(|&i, &j| a[i][col].abs().total_cmp(&a[j][col].abs()))?;
+        if a[pivot][col] == 0.0 || !a[pivot][col].is_finite() {
+            return None;
+        }
+        a.swap(col, pivot);
+        b.swap(col, pivot);
+        let (top, bottom) = a.split_at_mut(col + 1);
+        let pivotRow = &top[col];
+        for (row, ar) in bottom.iter_mut().enumerate() {
+            let f = ar[col] / pivotRow[col];
+            if f == 0.0 {
+                continue;
+            }
+            for (x, p) in ar[col..].iter_mut().zip(&pivotRow[col..]) {
+                *x -= f * p;
+            }
+            b[col + 1 + row] -= f * b[col];
+        }
+    }
+    let mut x = vec![0.0; n];
+    for row in (0..n).rev() {
+        let s: f64 = (row + 1..n).map(|k| a[row][k] * x[k]).sum();
+        x[row] = (b[row] - s) / a[row][row];
+    }
+    Some(x)
+}
@@ -0,0 +1,1220 @@
+//! Predicted line and continuum emission
+
+use crate::cloud::Cloud;
+use crate::consts::{c, h, kB, mH};
+use crate::emitter_data::{HyperfineComponent, Level, Transition};
+use crate::roots::{bracket_outward, brent};
+use crate::{DespoticError, Emitter};
+use std::io::Write;
+
+/// Range of log10 nH, in cm^-3, searched when solving for density
+const LOG_N_MIN: f64 = -2.0;
+const LOG_N_MAX: f64 = 12.0;
+
+/// Number of velocity points, and the half-width of the velocity
+/// range in units of the larger line width, used to integrate merged
+/// line emission
+const MERGE_POINTS: usize = 401;
+const MERGE_WIDTHS: f64 = 8.0;
+
+/// Brightness temperature of a specific intensity
+///
+/// Parameters
+///    i_nu : float
+///       specific intensity, in erg cm^-2 s^-1 Hz^-1 sr^-1
+///    nu : float
+///       frequency, in Hz
+///
+/// Returns
+///    temperature of the blackbody with specific intensity i_nu at
+///    frequency nu, in K
+///
+/// Remarks
+///    This inverts the full Planck function; in the Rayleigh-Jeans
+///    limit it reduces to c^2 i_nu / (2 k nu^2)
+pub fn intensity_to_tb(i_nu: f64, nu: f64) -> f64 {
+    h * nu / kB / (2.0 * h * nu.powi(3) / (c * c * i_nu)).ln_1p()
+}
+
+/// Specific intensity of a blackbody
+///
+/// Parameters
+///    tb : float
+///       brightness temperature, in K
+///    nu : float
+///       frequency, in Hz
+///
+/// Returns
+///    Planck function B_nu(tb), in erg cm^-2 s^-1 Hz^-1 sr^-1
+pub fn tb_to_intensity(tb: f64, nu: f64) -> f64 {
+    2.0 * h * nu.powi(3) / (c * c) / (h * nu / (kB * tb)).exp_m1()
+}
+
+/// Radiation temperature of a blackbody
+///
+/// Parameters
+///    T : float
+///       temperature of the blackbody, in K
+///    nu : float
+///       frequency, in Hz
+///
+/// Returns
+///    J_nu(T) = (h nu / k) / (exp(h nu / k T) - 1), in K; this is the
+///    Rayleigh-Jeans brightness temperature of a blackbody at T, and
+///    approaches T at low frequency
+pub fn j_nu(T: f64, nu: f64) -> f64 {
+    let T0 = h * nu / kB;
+    T0 / (T0 / T).exp_m1()
+}
+
+/// Ratio of the full width at half maximum of a Gaussian to its
+/// integral divided by its peak, 2 (ln 2 / pi)^1/2
+fn gaussian_fwhm_factor() -> f64 {
+    2.0 * (std::f64::consts::LN_2 / std::f64::consts::PI).sqrt()
+}
+
+/// Peak brightness temperature of a Gaussian line
+///
+/// Parameters
+///    int_tb : float
+///       velocity-integrated brightness temperature, in K km s^-1
+///    line_width_kms : float
+///       full width at half maximum of the line, in km s^-1
+///
+/// Returns
+///    brightness temperature at line center, in K
+pub fn integrated_to_peak_tb(int_tb: f64, line_width_kms: f64) -> f64 {
+    gaussian_fwhm_factor() * int_tb / line_width_kms
+}
+
+/// Velocity-integrated brightness temperature of a Gaussian line
+///
+/// Parameters
+///    peak_tb : float
+///       brightness temperature at line center, in K
+///    line_width_kms : float
+///       full width at half maximum of the line, in km s^-1
+///
+/// Returns
+///    velocity-integrated brightness temperature, in K km s^-1; the
+///    inverse of integrated_to_peak_tb
+pub fn peak_to_integrated_tb(peak_tb: f64, line_width_kms: f64) -> f64 {
+    peak_tb * line_width_kms / gaussian_fwhm_factor()
+}
+
+/// Predicted emission in a single line
+#[derive(Debug, Clone)]
+pub struct LineLum {
+    /// index of the upper level
+    pub upper: usize,
+    /// index of the lower level
+    pub lower: usize,
+    /// frequency, in Hz
+    pub freq: f64,
+    /// energy of the upper level, in K
+    pub Tupper: f64,
+    /// excitation temperature, in K
+    pub Tex: f64,
+    /// line-center optical depth
+    pub tau: f64,
+    /// luminosity per H nucleus, in erg s^-1 H^-1, net of absorption
+    /// of background photons; CMB photons are excluded if
+    /// rad.subtract_cmb is false
+    pub lumPerH: f64,
+    /// frequency-integrated intensity, in erg cm^-2 s^-1 sr^-1
+    pub intIntensity: f64,
+    /// velocity-integrated brightness temperature, in K km s^-1
+    pub intTB: f64,
+    /// true if the transition is inverted (tau < 0), in which case
+    /// it is treated as optically thin and the luminosity is not
+    /// reliable
+    pub masing: bool,
+}
+
+impl LineLum {
+    /// Integrated intensity averaged over a telescope beam
+    ///
+    /// Parameters
+    ///    source_size : float
+    ///       angular diameter of the source
+    ///    beam_size : float
+    ///       angular diameter of the beam, in the same units
+    ///
+    /// Returns
+    ///    intIntensity times the beam filling factor, in erg cm^-2
+    ///    s^-1 sr^-1
+    ///
+    /// Remarks
+    ///    The source is treated as a uniform disk and the beam as a
+    ///    top hat, so the filling factor is (source_size /
+    ///    beam_size)^2, capped at unity for sources that fill the
+    ///    beam. beam_diluted_tb gives the diluted intTB.
+    pub fn beam_diluted(&self, source_size: f64, beam_size: f64) -> f64 {
+        self.intIntensity * beam_filling(source_size, beam_size)
+    }
+
+    /// Velocity-integrated brightness temperature averaged over a
+    /// telescope beam
+    ///
+    /// Parameters
+    ///    source_size : float
+    ///       angular diameter of the source
+    ///    beam_size : float
+    ///       angular diameter of the beam, in the same units
+    ///
+    /// Returns
+    ///    intTB times the beam filling factor, in K km s^-1
+    ///
+    /// Remarks
+    ///    The filling factor is that of beam_diluted
+    pub fn beam_diluted_tb(&self, source_size: f64, beam_size: f64) -> f64 {
+        self.intTB * beam_filling(source_size, beam_size)
+    }
+
+    /// Peak brightness temperature of the line
+    ///
+    /// Parameters
+    ///    line_width_kms : float
+    ///       full width at half maximum of the line, in km s^-1
+    ///
+    /// Returns
+    ///    brightness temperature at line center, in K, for a Gaussian
+    ///    profile whose integral is intTB
+    pub fn peak_tb(&self, line_width_kms: f64) -> f64 {
+        integrated_to_peak_tb(self.intTB, line_width_kms)
+    }
+}
+
+/// Fraction of a top-hat beam filled by a uniform disk source
+fn beam_filling(source_size: f64, beam_size: f64) -> f64 {
+    (source_size / beam_size).powi(2).min(1.0)
+}
+
+/// Excitation temperature of a transition, in K; negative if the
+/// populations are inverted
+fn excitation_temp(t: &Transition, levels: &[Level], pops: &[f64]) -> f64 {
+    let gRatio = levels[t.upper].weight / levels[t.lower].weight;
+    h * t.freq / kB / (gRatio * pops[t.lower] / pops[t.upper]).ln()
+}
+
+impl Emitter {
+    /// Predicted emission in each radiative transition
+    ///
+    /// Parameters
+    ///    cloud : Cloud
+    ///       the cloud containing the emitter
+    ///
+    /// Returns
+    ///    one LineLum per transition, in the order of
+    ///    data.transitions
+    pub fn line_lum(&self, cloud: &Cloud) -> Result<Vec<LineLum>, DespoticError> {
+        Ok(self.lines_iter(cloud)?.collect())
+    }
+
+    /// Predicted emission in each radiative transition, computed
+    /// lazily
+    ///
+    /// Parameters
+    ///    cloud : Cloud
+    ///       the cloud containing the emitter
+    ///
+    /// Returns
+    ///    an iterator giving one LineLum per transition, in the order
+    ///    of data.transitions
+    ///
+    /// Remarks
+    ///    The level populations are solved for when this is called,
+    ///    which is where any error arises; the emission in each line
+    ///    is then computed only as the iterator is advanced
+    pub fn lines_iter<'a>(
+        &'a self,
+        cloud: &'a Cloud,
+    ) -> Result<impl Iterator<Item = LineLum> + 'a, DespoticError> {
+        self.lines_iter_with(cloud, false)
+    }
+
+    /// As lines_iter, optionally measuring each line against the
+    /// cloud's own dust continuum as well as the background
+    fn lines_iter_with<'a>(
+        &'a self,
+        cloud: &'a Cloud,
+        continuum_subtract: bool,
+    ) -> Result<impl Iterator<Item = LineLum> + 'a, DespoticError> {
+        cloud.refresh_if_dirty();
+        let pops = self.level_pops(cloud)?;
+        let tau = self.optical_depths(cloud, &pops);
+        let levels = &self.data.levels;
+        Ok(self.data.transitions.iter().zip(tau).map(move |(t, tau)| {
+            let (u, l) = (t.upper, t.lower);
+            let gRatio = levels[u].weight / levels[l].weight;
+            let mut ng = cloud.rad.ngamma(t.freq);
+            if !cloud.rad.subtract_cmb {
+                ng -= cloud.rad.cmb_occupation(t.freq);
+            }
+            if continuum_subtract {
+                let Idust = cloud.dust_sed(&[t.freq])[0];
+                ng += c * c * Idust / (2.0 * h * t.freq.powi(3));
+            }
+            let beta = self.escapeProbGeom.beta(tau);
+            let lumPerH = self.abundance_in(cloud)
+                * h
+                * t.freq
+                * t.A
+                * beta
+                * (pops[u] - ng * (gRatio * pops[l] - pops[u]));
+            let intIntensity = lumPerH * cloud.colDen as f64 / (4.0 * std::f64::consts::PI);
+            // I_nu dnu = I_nu (nu / c) dv, and T_B = c^2 I_nu / (2 k nu^2)
+            let intTB = c.powi(3) / (2.0 * kB * t.freq.powi(3)) * intIntensity / 1.0e5;
+            LineLum {
+                upper: u,
+                lower: l,
+                freq: t.freq,
+                Tupper: t.Tupper,
+                Tex: excitation_temp(t, levels, &pops),
+                tau,
+                lumPerH,
+                intIntensity,
+                intTB,
+                masing: tau < 0.0,
+            }
+        }))
+    }
+
+    /// Excitation temperature of each radiative transition
+    ///
+    /// Parameters
+    ///    cloud : Cloud
+    ///       the cloud containing the emitter
+    ///
+    /// Returns
+    ///    excitation temperature of each transition, in K, in the
+    ///    order of data.transitions
+    ///
+    /// Remarks
+    ///    The excitation temperature is found from the ratio of the
+    ///    solved populations of the upper and lower levels. It is
+    ///    negative for transitions whose populations are inverted.
+    pub fn excitation_temps(&self, cloud: &Cloud) -> Result<Vec<f32>, DespoticError> {
+        let pops = self.level_pops(cloud)?;
+        Ok(self
+            .data
+            .transitions
+            .iter()
+            .map(|t| excitation_temp(t, &self.data.levels, &pops) as f32)
+            .collect())
+    }
+
+    /// Transitions whose populations are inverted
+    ///
+    /// Parameters
+    ///    cloud : Cloud
+    ///       the cloud containing the emitter
+    ///
+    /// Returns
+    ///    indices into data.transitions of the transitions with
+    ///    negative optical depth
+    pub fn masing_transitions(&self, cloud: &Cloud) -> Result<Vec<usize>, DespoticError> {
+        let pops = self.level_pops(cloud)?;
+        Ok(self
+            .optical_depths(cloud, &pops)
+            .iter()
+            .enumerate()
+            .filter(|(_, &tau)| tau < 0.0)
+            .map(|(i, _)| i)
+            .collect())
+    }
+
+    /// Mean optical depth of the emitter's lines, weighted by their
+    /// emission
+    ///
+    /// Parameters
+    ///    cloud : Cloud
+    ///       the cloud containing the emitter
+    ///
+    /// Returns
+    ///    sum(tau L) / sum(L) over the radiative transitions, where L
+    ///    is each line's lumPerH; values much less than 1 mean the
+    ///    emission is optically thin
+    ///
+    /// Remarks
+    ///    Lines with no net emission, such as those seen in
+    ///    absorption against the background, are given zero weight.
+    ///    The mean is zero if no line emits.
+    pub fn mean_tau(&self, cloud: &Cloud) -> Result<f64, DespoticError> {
+        let (mut weighted, mut total) = (0.0, 0.0);
+        for line in self.lines_iter(cloud)? {
+            let L = line.lumPerH.max(0.0);
+            weighted += line.tau * L;
+            total += L;
+        }
+        Ok(if total > 0.0 { weighted / total } else { 0.0 })
+    }
+}
+
+impl Cloud {
+    /// Predicted line emission of an emitter
+    ///
+    /// Parameters
+    ///    emitName : string
+    ///       name of the emitter
+    ///
+    /// Returns
+    ///    one LineLum per radiative transition of the emitter
+    pub fn line_lum(&self, emitName: &str) -> Result<Vec<LineLum>, DespoticError> {
+        self.emitters
+            .get(emitName)
+            .ok_or_else(|| DespoticError::UnknownEmitter(emitName.to_string()))?
+            .line_lum(self)
+    }
+
+    /// Frequency-integrated intensity of each line of an emitter
+    ///
+    /// Parameters
+    ///    emitName : string
+    ///       name of the emitter
+    ///    continuum_subtract : Boolean
+    ///       if true, subtract the dust continuum at each line's
+    ///       frequency, as is done for observed lines
+    ///
+    /// Returns
+    ///    integrated intensity of each radiative transition, in erg
+    ///    cm^-2 s^-1 sr^-1, in the order of data.transitions
+    ///
+    /// Remarks
+    ///    Without subtraction these are the intIntensity values of
+    ///    line_lum. With it, the continuum emerging from the cloud,
+    ///    as given by dust_sed, is added to the background against
+    ///    which each line is measured, so the intensity is reduced by
+    ///    the continuum the line absorbs; a line whose excitation
+    ///    temperature is below the dust temperature can appear in
+    ///    absorption.
+    pub fn integrated_intensity(
+        &self,
+        emitName: &str,
+        continuum_subtract: bool,
+    ) -> Result<Vec<f64>, DespoticError> {
+        Ok(self
+            .emitters
+            .get(emitName)
+            .ok_or_else(|| DespoticError::UnknownEmitter(emitName.to_string()))?
+            .lines_iter_with(self, continuum_subtract)?
+            .map(|line| line.intIntensity)
+            .collect())
+    }
+
+    /// Predicted emission in the CO rotational ladder
+    ///
+    /// Parameters
+    ///    jmax : int
+    ///       upper rotational level of the highest line returned
+    ///
+    /// Returns
+    ///    frequency-integrated intensity intIntensity, in erg cm^-2
+    ///    s^-1 sr^-1, of each line J -> J-1 for J = 1 ... jmax
+    ///
+    /// Remarks
+    ///    The emitter is the one named CO, in any case; an
+    ///    UnknownEmitter error is returned if there is none. Levels
+    ///    are assumed to be numbered by J, as in the LAMDA CO files,
+    ///    and an UnknownTransition error is returned if the data do
+    ///    not extend to jmax. The level populations are solved once.
+    pub fn co_sled(&self, jmax: usize) -> Result<Vec<f64>, DespoticError> {
+        let em = self
+            .emitters
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case("co"))
+            .map(|(_, em)| em)
+            .ok_or_else(|| DespoticError::UnknownEmitter("CO".to_string()))?;
+        let lines = em.line_lum(self)?;
+        (1..=jmax)
+            .map(|J| {
+                lines
+                    .iter()
+                    .find(|l| l.upper == J && l.lower == J - 1)
+                    .map(|l| l.intIntensity)
+                    .ok_or_else(|| DespoticError::UnknownTransition {
+                        species: em.name.clone(),
+                        upper: J,
+                        lower: J - 1,
+                    })
+            })
+            .collect()
+    }
+
+    /// Predicted emission in one line, and the width of the line
+    fn line_and_width(
+        &self,
+        species: &str,
+        upper: usize,
+        lower: usize,
+    ) -> Result<(LineLum, f64), DespoticError> {
+        let em = self
+            .emitters
+            .get(species)
+            .ok_or_else(|| DespoticError::UnknownEmitter(species.to_string()))?;
+        let line = em
+            .lines_iter(self)?
+            .find(|l| l.upper == upper && l.lower == lower)
+            .ok_or_else(|| DespoticError::UnknownTransition {
+                species: species.to_string(),
+                upper,
+                lower,
+            })?;
+        Ok((line, em.line_width(self)))
+    }
+
+    /// Emission in a line from this cloud seen through another
+    ///
+    /// Parameters
+    ///    other : Cloud
+    ///       the foreground cloud, lying between this one and the
+    ///       observer
+    ///    species : string
+    ///       name of the emitter, which must be present in both
+    ///       clouds
+    ///    upper, lower : int
+    ///       upper and lower level indices of the line
+    ///
+    /// Returns
+    ///    frequency-integrated intensity of the line emerging from
+    ///    the foreground cloud, in erg cm^-2 s^-1 sr^-1
+    ///
+    /// Remarks
+    ///    Both lines are taken to be Gaussian in velocity, with the
+    ///    widths of their emitters, and centered at the same
+    ///    velocity. At each velocity the background emission is
+    ///    attenuated by exp(-tau), where tau is the foreground
+    ///    line-center optical depth scaled by the foreground line
+    ///    profile, and the foreground emission is added. The
+    ///    foreground's own emission is as computed by line_lum, so
+    ///    already accounts for its self-absorption. Inverted
+    ///    foreground lines are treated as optically thin.
+    pub fn merge_emission(
+        &self,
+        other: &Cloud,
+        species: &str,
+        upper: usize,
+        lower: usize,
+    ) -> Result<f64, DespoticError> {
+        let (bg, sigmaBg) = self.line_and_width(species, upper, lower)?;
+        let (fg, sigmaFg) = other.line_and_width(species, upper, lower)?;
+        let tauFg = fg.tau.max(0.0);
+        let gauss = |v: f64, sigma: f64| {
+            (-0.5 * (v / sigma).powi(2)).exp() / ((2.0 * std::f64::consts::PI).sqrt() * sigma)
+        };
+
+        // Integrate the attenuated background over velocity by the
+        // trapezoidal rule, out to many widths of either line
+        let vmax = MERGE_WIDTHS * sigmaBg.max(sigmaFg);
+        let dv = 2.0 * vmax / (MERGE_POINTS - 1) as f64;
+        let attenuated: f64 = (0..MERGE_POINTS)
+            .map(|i| {
+                let v = -vmax + i as f64 * dv;
+                let w = if i == 0 || i == MERGE_POINTS - 1 {
+                    0.5
+                } else {
+                    1.0
+                };
+                let tau = tauFg * (-0.5 * (v / sigmaFg).powi(2)).exp();
+                w * gauss(v, sigmaBg) * (-tau).exp()
+            })
+            .sum::<f64>()
+            * dv;
+        Ok(bg.intIntensity * attenuated + fg.intIntensity)
+    }
+
+    /// Predicted emission in the [CII] 158 micron line
+    ///
+    /// Parameters
+    ///    None
+    ///
+    /// Returns
+    ///    the LineLum of the 2P3/2 -> 2P1/2 fine-structure transition
+    ///
+    /// Remarks
+    ///    The emitter is the one named C+, CII or Cplus, in any case;
+    ///    an UnknownEmitter error is returned if there is none. Its
+    ///    data must number the fine-structure levels 0 and 1, as in
+    ///    the LAMDA C+ file; any higher levels are included in the
+    ///    population solve.
+    pub fn cii_line(&self) -> Result<LineLum, DespoticError> {
+        let em = self
+            .emitters
+            .iter()
+            .find(|(name, _)| {
+                ["c+", "cii", "cplus"]
+                    .iter()
+                    .any(|n| name.eq_ignore_ascii_case(n))
+            })
+            .map(|(_, em)| em)
+            .ok_or_else(|| DespoticError::UnknownEmitter("C+".to_string()))?;
+        em.lines_iter(self)?
+            .find(|l| l.upper == 1 && l.lower == 0)
+            .ok_or_else(|| DespoticError::UnknownTransition {
+                species: em.name.clone(),
+                upper: 1,
+                lower: 0,
+            })
+    }
+
+    /// Gas mass inferred from a line assuming it is optically thin
+    ///
+    /// Parameters
+    ///    species : string
+    ///       name of the emitter
+    ///    upper, lower : int
+    ///       upper and lower level indices of the line
+    ///    distance : float
+    ///       distance to the cloud, in cm
+    ///
+    /// Returns
+    ///    the gas mass, in g, that an observer at the given distance
+    ///    would infer from the line's predicted flux
+    ///
+    /// Remarks
+    ///    The cloud is a uniform sphere of radius colDen / nH, whose
+    ///    line luminosity is lumPerH times its number of H nuclei.
+    ///    The observed flux F = L / (4 pi d^2) is inverted with the
+    ///    standard optically thin formula: the number of emitters in
+    ///    the upper level is 4 pi d^2 F / (h nu A), the total number
+    ///    follows from LTE level populations at Tg, and the gas mass
+    ///    from the emitter's abundance and the mass per H nucleus. The
+    ///    distance therefore cancels, and the result matches the
+    ///    cloud's true mass when the line is thin, in LTE, and bright
+    ///    compared to the background. An InvalidDistance error is
+    ///    returned if distance is not positive and finite, and a
+    ///    ZeroDensity error if nH is not positive.
+    pub fn thin_mass_from_line(
+        &self,
+        species: &str,
+        upper: usize,
+        lower: usize,
+        distance: f64,
+    ) -> Result<f64, DespoticError> {
+        self.check_density()?;
+        if !(distance > 0.0 && distance.is_finite()) {
+            return Err(DespoticError::InvalidDistance(distance));
+        }
+        let em = self
+            .emitters
+            .get(species)
+            .ok_or_else(|| DespoticError::UnknownEmitter(species.to_string()))?;
+        let (line, _) = self.line_and_width(species, upper, lower)?;
+        let t = em
+            .data
+            .transitions
+            .iter()
+            .find(|t| t.upper == upper && t.lower == lower)
+            .ok_or_else(|| DespoticError::UnknownTransition {
+                species: species.to_string(),
+                upper,
+                lower,
+            })?;
+
+        // Luminosity and flux of the model cloud
+        let R = self.colDen as f64 / self.nH as f64;
+        let NH = 4.0 / 3.0 * std::f64::consts::PI * R.powi(3) * self.nH as f64;
+        let area = 4.0 * std::f64::consts::PI * distance * distance;
+        let flux = line.lumPerH * NH / area;
+
+        // Invert assuming optically thin emission from LTE populations
+        let Tg = self.Tg as f64;
+        let level = &em.data.levels[upper];
+        let fUpper =
+            level.weight * (-level.energy / (kB * Tg)).exp() / em.data.partition_function(Tg);
+        let Nupper = area * flux / (h * t.freq * t.A);
+        let Nem = Nupper / fUpper;
+        Ok(Nem / em.abundance_in(self) * self.comp.muH as f64 * mH)
+    }
+
+    /// Ratio of the emission in two lines of one species
+    ///
+    /// Parameters
+    ///    species : string
+    ///       name of the emitter
+    ///    (u1, l1) : (int, int)
+    ///       upper and lower level indices of the numerator line
+    ///    (u2, l2) : (int, int)
+    ///       upper and lower level indices of the denominator line
+    ///
+    /// Returns
+    ///    ratio of velocity-integrated brightness temperatures,
+    ///    intTB1 / intTB2, the form used by solve_density_from_ratio
+    ///
+    /// Remarks
+    ///    The level populations are solved once for both lines
+    pub fn line_ratio(
+        &self,
+        species: &str,
+        (u1, l1): (usize, usize),
+        (u2, l2): (usize, usize),
+    ) -> Result<f64, DespoticError> {
+        let lines = self.line_lum(species)?;
+        let intTB = |u: usize, l: usize| {
+            lines
+                .iter()
+                .find(|line| line.upper == u && line.lower == l)
+                .map(|line| line.intTB)
+                .ok_or_else(|| DespoticError::UnknownTransition {
+                    species: species.to_string(),
+                    upper: u,
+                    lower: l,
+                })
+        };
+        Ok(intTB(u1, l1)? / intTB(u2, l2)?)
+    }
+
+    /// Write the predicted emission in every line as a CSV table
+    ///
+    /// Parameters
+    ///    writer : Write
+    ///       destination of the table
+    ///
+    /// Returns
+    ///    Nothing
+    ///
+    /// Remarks
+    ///    The table has a header row followed by one row per
+    ///    transition, with columns species, upper and lower level
+    ///    index, frequency in Hz, excitation temperature in K,
+    ///    line-center optical depth, and velocity-integrated
+    ///    brightness temperature in K km s^-1; the units are given in
+    ///    the header. Emitters are written in alphabetical order.
+    pub fn write_lines(&self, mut writer: impl Write) -> Result<(), DespoticError> {
+        writeln!(
+            writer,
+            "species,upper,lower,freq_Hz,T_ex_K,tau,int_TB_K_kms"
+        )?;
+        let mut names: Vec<&String> = self.emitters.keys().collect();
+        names.sort();
+        for name in names {
+            for line in self.line_lum(name)? {
+                writeln!(
+                    writer,
+                    "{},{},{},{:e},{:e},{:e},{:e}",
+                    name, line.upper, line.lower, line.freq, line.Tex, line.tau, line.intTB
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Specific intensity of the dust continuum emerging from the
+    /// cloud
+    ///
+    /// Parameters
+    ///    nu : array
+    ///       frequencies, in Hz
+    ///
+    /// Returns
+    ///    specific intensity at each frequency, in erg cm^-2 s^-1
+    ///    Hz^-1 sr^-1
+    ///
+    /// Remarks
+    ///    The dust is treated as an isothermal slab at Td, with the
+    ///    cross section per H nucleus given by DustProp::sigma_nu.
+    pub fn dust_sed(&self, nu: &[f64]) -> Vec<f64> {
+        let Td = self.Td as f64;
+        nu.iter()
+            .map(|&nu| {
+                if Td <= 0.0 {
+                    return 0.0;
+                }
+                let tau = self.dust.sigma_nu(nu) * self.colDen as f64;
+                let Bnu = 2.0 * h * nu.powi(3) / (c * c) / (h * nu / (kB * Td)).exp_m1();
+                -Bnu * (-tau).exp_m1()
+            })
+            .collect()
+    }
+
+    /// Emergent spectrum of dust continuum plus line emission
+    ///
+    /// Parameters
+    ///    nu : array
+    ///       frequencies, in Hz
+    ///
+    /// Returns
+    ///    specific intensity at each frequency, in erg cm^-2 s^-1
+    ///    Hz^-1 sr^-1
+    ///
+    /// Remarks
+    ///    Each line is given a Gaussian profile whose width is the
+    ///    line width of its emitter and whose integral is the line's
+    ///    integrated intensity. A line with hyperfine components is
+    ///    drawn as one such Gaussian per component, each centered at
+    ///    the component's offset and carrying its relative intensity
+    ///    times the line's integrated intensity; the optical depth of
+    ///    the line as a whole is used for all of them. Overlapping
+    ///    lines and the continuum are simply added, neglecting
+    ///    absorption of one by another.
+    pub fn spectrum(&self, nu: &[f64]) -> Result<Vec<f64>, DespoticError> {
+        let mut spec = self.dust_sed(nu);
+        let unsplit = [HyperfineComponent {
+            offset: 0.0,
+            relInt: 1.0,
+        }];
+        for em in self.emitters.values() {
+            let sigmaV = em.line_width(self);
+            for (line, t) in em.lines_iter(self)?.zip(&em.data.transitions) {
+                let sigmaNu = line.freq * sigmaV / c;
+                let norm = line.intIntensity / ((2.0 * std::f64::consts::PI).sqrt() * sigmaNu);
+                let components = if t.hyperfine.is_empty() {
+                    &unsplit[..]
+                } else {
+                    &t.hyperfine[..]
+                };
+                for hf in components {
+                    let center = line.freq + hf.offset;
+                    for (s, &f) in spec.iter_mut().zip(nu) {
+                        let x = (f - center) / sigmaNu;
+                        *s += hf.relInt * norm * (-0.5 * x * x).exp();
+                    }
+                }
+            }
+        }
+        Ok(spec)
+    }
+
+    /// Find the density at which a line ratio takes a given value
+    ///
+    /// Parameters
+    ///    species : string
+    ///       name of the emitter
+    ///    upper1, lower1 : int
+    ///       upper and lower level indices of the numerator line
+    ///    upper2, lower2 : int
+    ///       upper and lower level indices of the denominator line
+    ///    target : float
+    ///       desired ratio of velocity-integrated brightness
+    ///       temperatures, intTB1 / intTB2
+    ///
+    /// Returns
+    ///    the density nH that reproduces the ratio, in cm^-3
+    ///
+    /// Remarks
+    ///    On success the cloud's nH is set to the solution; on failure
+    ///    it is left unchanged. All other properties, including
+    ///    colDen, are held fixed. The search starts from the current
+    ///    nH and expands outward until it brackets the target, so if
+    ///    the ratio is not monotonic in density the root found is the
+    ///    one nearest the starting density.
+    #[allow(clippy::too_many_arguments)]
+    pub fn solve_density_from_ratio(
+        &mut self,
+        species: &str,
+        upper1: usize,
+        lower1: usize,
+        upper2: usize,
+        lower2: usize,
+        target: f64,
+    ) -> Result<f64, DespoticError> {
+        let em = self
+            .emitters
+            .get(species)
+            .ok_or_else(|| DespoticError::UnknownEmitter(species.to_string()))?;
+        let find = |u: usize, l: usize| {
+            em.data
+                .transitions
+                .iter()
+                .position(|t| t.upper == u && t.lower == l)
+                .ok_or_else(|| DespoticError::UnknownTransition {
+                    species: species.to_string(),
+                    upper: u,
+                    lower: l,
+                })
+        };
+        let (i1, i2) = (find(upper1, lower1)?, find(upper2, lower2)?);
+
+        let nH0 = self.nH;
+        let result = self.bracket_log_density(species, i1, i2, target);
+        let logn = match result {
+            Ok(Some(logn)) => logn,
+            Ok(None) | Err(_) => {
+                self.nH = nH0;
+                self.comp.compute_derived(nH0);
+                return result.and(Err(DespoticError::NoConvergence(format!(
+                    "no density between 10^{LOG_N_MIN} and 10^{LOG_N_MAX} cm^-3 gives \
+                     line ratio {target}"
+                ))));
+            }
+        };
+        self.nH = 10.0_f64.powf(logn) as f32;
+        self.comp.compute_derived(self.nH);
+        Ok(self.nH as f64)
+    }
+
+    /// Search outward in log density from the current nH for an
+    /// interval over which the ratio of lines i1 and i2 crosses the
+    /// target, then find the crossing
+    fn bracket_log_density(
+        &mut self,
+        species: &str,
+        i1: usize,
+        i2: usize,
+        target: f64,
+    ) -> Result<Option<f64>, DespoticError> {
+        let x0 = if self.nH > 0.0 {
+            (self.nH as f64).log10()
+        } else {
+            2.0
+        };
+        let mut resid = |logn: f64| -> Result<f64, DespoticError> {
+            self.nH = 10.0_f64.powf(logn) as f32;
+            self.comp.compute_derived(self.nH);
+            let lines = self.line_lum(species)?;
+            Ok(lines[i1].intTB / lines[i2].intTB - target)
+        };
+        match bracket_outward(&mut resid, x0, 1.0, (LOG_N_MIN, LOG_N_MAX))? {
+            Some((lo, hi)) => brent(resid, lo, hi, 1.0e-6, 100),
+            None => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::consts::pc;
+    use crate::emitter_data::EmitterData;
+    use crate::testutil::{co_cloud, init};
+    use crate::Radiation;
+    use std::sync::Arc;
+
+    #[test]
+    fn write_lines_table() {
+        let cloud = co_cloud();
+        let mut out = Vec::new();
+        cloud.write_lines(&mut out).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        let mut rows = text.lines();
+        assert_eq!(
+            rows.next(),
+            Some("species,upper,lower,freq_Hz,T_ex_K,tau,int_TB_K_kms")
+        );
+        let co10 = rows.find(|r| r.starts_with("co,1,0,")).unwrap();
+        let fields: Vec<f64> = co10
+            .split(',')
+            .skip(3)
+            .map(|f| f.parse().unwrap())
+            .collect();
+        assert!((fields[0] / 115.2712018e9 - 1.0).abs() < 1.0e-6);
+        assert!(fields[3] > 0.0);
+    }
+
+    #[test]
+    fn spectrum_has_line_atop_continuum() {
+        let cloud = co_cloud();
+        let nu0 = 115.2712018e9;
+        let nu: Vec<f64> = (-100..=100)
+            .map(|i| nu0 * (1.0 + i as f64 * 1.0e-6))
+            .collect();
+        let spec = cloud.spectrum(&nu).unwrap();
+        let dust = cloud.dust_sed(&nu);
+
+        // Far from the line only the continuum remains; at its center
+        // the line stands well above it
+        assert!(dust[0] > 0.0);
+        assert!((spec[0] / dust[0] - 1.0).abs() < 1.0e-6);
+        assert!(spec[100] > 10.0 * dust[100]);
+        let peak = (0..nu.len())
+            .max_by(|&i, &j| (spec[i] - dust[i]).total_cmp(&(spec[j] - dust[j])))
+            .unwrap();
+        assert_eq!(peak, 100);
+    }
+
+    #[test]
+    fn mean_tau_of_thin_and_thick_co() {
+        init();
+        let cloud = Cloud::simple(1.0e3, 20.0, &[("co", 1.0e-10)]).unwrap();
+        let tau = cloud.emitters["co"].mean_tau(&cloud).unwrap();
+        assert!(tau > 0.0 && tau < 0.01, "tau = {tau}");
+
+        // At the usual abundance the low-J lines are optically thick
+        let cloud = co_cloud();
+        assert!(cloud.emitters["co"].mean_tau(&cloud).unwrap() > 1.0);
+    }
+
+    #[test]
+    fn density_recovered_from_line_ratio() {
+        init();
+        let mut cloud = Cloud::simple(1.0e3, 20.0, &[("co", 1.0e-6)]).unwrap();
+        let target = cloud.line_ratio("co", (3, 2), (1, 0)).unwrap();
+        cloud.set_nh(1.0e2);
+        let nH = cloud
+            .solve_density_from_ratio("co", 3, 2, 1, 0, target)
+            .unwrap();
+        assert!((nH / 1.0e3 - 1.0).abs() < 0.01, "nH = {nH}");
+        assert_eq!(cloud.nH as f64, nH);
+    }
+
+    /// A three-level species in which collisions pump the top level
+    /// and it decays quickly to the middle one, which has a slow
+    /// radiative decay and no collisional coupling to the ground
+    const PUMPED: &str = "\
+!MOLECULE
+PUMPED
+!MOLECULAR WEIGHT
+30.0
+!NUMBER OF ENERGY LEVELS
+3
+!LEVEL + ENERGIES(cm^-1) + WEIGHT
+    1     0.0   1.0
+    2     1.0   1.0
+    3    20.0   1.0
+!NUMBER OF RADIATIVE TRANSITIONS
+2
+!TRANS + UP + LOW + EINSTEINA(s^-1) + FREQ(GHz) + E_u(K)
+    1    2    1  1.000e-09     29.9792458     1.44
+    2    3    2  1.000e-03    569.6056702    28.78
+!NUMBER OF COLL PARTNERS
+1
+!COLLISIONS BETWEEN
+2 pumped-pH2
+!NUMBER OF COLL TRANS
+1
+!NUMBER OF COLL TEMPS
+2
+!COLL TEMPS
+    10.0  1000.0
+!TRANS + UP + LOW + COLLRATES(cm^3 s^-1)
+    1    3    1  1.0e-10  1.0e-10
+";
+
+    #[test]
+    fn inverted_transition_is_reported() {
+        let mut cloud = co_cloud();
+        cloud.set_tg(100.0);
+        let data = Arc::new(EmitterData::read_lamda(PUMPED.as_bytes()).unwrap());
+        let em = Emitter::with_data("pumped", 1.0e-8, false, true, data);
+        assert_eq!(em.masing_transitions(&cloud).unwrap(), vec![0]);
+        let lines = em.line_lum(&cloud).unwrap();
+        assert!(lines[0].masing && lines[0].tau < 0.0);
+        assert!(!lines[1].masing);
+    }
+
+    #[test]
+    fn hyperfine_components_in_spectrum() {
+        init();
+        let cloud = Cloud::simple(1.0e3, 20.0, &[("hcn", 1.0e-12)]).unwrap();
+        let t = &cloud.emitters["hcn"].data.transitions[0];
+        let freqs: Vec<f64> = t.hyperfine.iter().map(|hf| t.freq + hf.offset).collect();
+        assert_eq!(freqs.len(), 3);
+
+        // Each component peaks above the gaps on either side of it,
+        // and the lines above the continuum are in the ratio of g_u A
+        let mid = [0.5 * (freqs[0] + freqs[1]), 0.5 * (freqs[1] + freqs[2])];
+        let peaks = cloud.spectrum(&freqs).unwrap();
+        let gaps = cloud.spectrum(&mid).unwrap();
+        assert!(peaks[0] > gaps[0] && peaks[1] > gaps[0]);
+        assert!(peaks[1] > gaps[1] && peaks[2] > gaps[1]);
+        let cont = cloud.dust_sed(&freqs);
+        let line = |i: usize| peaks[i] - cont[i];
+        assert!((line(1) / line(0) - 5.0 / 3.0).abs() < 0.02);
+        assert!((line(2) / line(0) - 1.0 / 3.0).abs() < 0.02);
+    }
+
+    #[test]
+    fn lines_iter_matches_line_lum() {
+        let cloud = co_cloud();
+        let em = &cloud.emitters["co"];
+        let lines = em.line_lum(&cloud).unwrap();
+        assert_eq!(em.lines_iter(&cloud).unwrap().count(), lines.len());
+
+        // The second solve starts from the first, so they agree only
+        // to the convergence tolerance
+        let first = em.lines_iter(&cloud).unwrap().next().unwrap();
+        assert_eq!((first.upper, first.lower), (lines[0].upper, lines[0].lower));
+        assert_eq!(first.freq, lines[0].freq);
+        assert!((first.intTB / lines[0].intTB - 1.0).abs() < 1.0e-5);
+    }
+
+    #[test]
+    fn excitation_temps_thermalize_at_high_density() {
+        let mut cloud = co_cloud();
+        cloud.set_nh(1.0e9);
+        let Tex = cloud.emitters["co"].excitation_temps(&cloud).unwrap();
+        assert_eq!(Tex.len(), 5);
+        for T in Tex {
+            assert!((T / cloud.Tg - 1.0).abs() < 1.0e-2, "Tex = {T}");
+        }
+
+        // Inverted populations give a negative excitation temperature
+        let mut cloud = co_cloud();
+        cloud.set_tg(100.0);
+        let data = Arc::new(EmitterData::read_lamda(PUMPED.as_bytes()).unwrap());
+        let em = Emitter::with_data("pumped", 1.0e-8, false, true, data);
+        let Tex = em.excitation_temps(&cloud).unwrap();
+        assert!(Tex[0] < 0.0 && Tex[1] > 0.0);
+    }
+
+    #[test]
+    fn beam_dilution() {
+        let cloud = co_cloud();
+        let line = cloud.emitters["co"].line_lum(&cloud).unwrap()[0].clone();
+        assert_eq!(line.beam_diluted(10.0, 10.0), line.intIntensity);
+        assert_eq!(line.beam_diluted(20.0, 10.0), line.intIntensity);
+        let point = line.beam_diluted(0.1, 10.0);
+        assert!((point / line.intIntensity - 1.0e-4).abs() < 1.0e-12);
+
+        // A source larger than the beam fills it, so neither is diluted
+        assert_eq!(line.beam_diluted_tb(10.0, 10.0), line.intTB);
+        assert_eq!(line.beam_diluted_tb(20.0, 10.0), line.intTB);
+        let point = line.beam_diluted_tb(0.1, 10.0);
+        assert!((point / line.intTB - 1.0e-4).abs() < 1.0e-12);
+    }
+
+    #[test]
+    fn brightness_temperature_conversions() {
+        // Round trip at 345 GHz, where Rayleigh-Jeans is poor at 10 K
+        let nu = 345.8e9;
+        let i_nu = tb_to_intensity(10.0, nu);
+        assert!((intensity_to_tb(i_nu, nu) / 10.0 - 1.0).abs() < 1.0e-12);
+        let rj = c * c * i_nu / (2.0 * kB * nu * nu);
+        assert!(rj < 0.9 * 10.0);
+        assert!((j_nu(10.0, nu) / rj - 1.0).abs() < 1.0e-12);
+
+        // At 1 GHz the full and Rayleigh-Jeans relations agree
+        let nu = 1.0e9;
+        let i_nu = tb_to_intensity(10.0, nu);
+        let rj = c * c * i_nu / (2.0 * kB * nu * nu);
+        assert!((rj / 10.0 - 1.0).abs() < 1.0e-2);
+        assert!((j_nu(10.0, nu) / 10.0 - 1.0).abs() < 1.0e-2);
+    }
+
+    #[test]
+    fn co_sled_peak() {
+        // Cold, moderately dense gas is subthermally excited above
+        // J = 3, so its ladder turns over at J = 4
+        let cloud = co_cloud();
+        let sled = cloud.co_sled(5).unwrap();
+        let peak = (1..=5).max_by(|&a, &b| sled[a - 1].total_cmp(&sled[b - 1]));
+        assert_eq!(peak, Some(4), "SLED {sled:?}");
+
+        // Warm, dense gas populates higher levels, so the ladder
+        // still rises at J = 5
+        let warm = Cloud::simple(1.0e5, 100.0, &[("co", 1.0e-4)]).unwrap();
+        let sled = warm.co_sled(5).unwrap();
+        assert!(sled.windows(2).all(|w| w[1] > w[0]), "SLED {sled:?}");
+
+        assert!(matches!(
+            cloud.co_sled(6),
+            Err(DespoticError::UnknownTransition { upper: 6, .. })
+        ));
+    }
+
+    #[test]
+    fn merge_with_empty_cloud() {
+        let cloud = co_cloud();
+        let empty = Cloud::simple(1.0e3, 20.0, &[("co", 0.0)]).unwrap();
+        let single = cloud.emitters["co"].line_lum(&cloud).unwrap()[0].intIntensity;
+        for merged in [
+            cloud.merge_emission(&empty, "co", 1, 0).unwrap(),
+            empty.merge_emission(&cloud, "co", 1, 0).unwrap(),
+        ] {
+            assert!(
+                (merged / single - 1.0).abs() < 1.0e-6,
+                "{merged} vs {single}"
+            );
+        }
+
+        // An identical, optically thick foreground hides most of the
+        // background emission
+        let stacked = cloud.merge_emission(&cloud, "co", 1, 0).unwrap();
+        assert!(stacked > single && stacked < 1.5 * single);
+    }
+
+    #[test]
+    fn co_2_1_to_1_0_ratio() {
+        let cloud = co_cloud();
+        let ratio = cloud.line_ratio("co", (2, 1), (1, 0)).unwrap();
+        assert!(ratio > 0.0 && ratio < 4.0, "CO(2-1)/(1-0) = {ratio}");
+        let lines = cloud.line_lum("co").unwrap();
+        assert!((ratio / (lines[1].intTB / lines[0].intTB) - 1.0).abs() < 1.0e-5);
+        assert!(matches!(
+            cloud.line_ratio("co", (9, 8), (1, 0)),
+            Err(DespoticError::UnknownTransition { upper: 9, .. })
+        ));
+    }
+
+    #[test]
+    fn redshifted_cmb_reduces_contrast() {
+        let rad = Radiation::with_redshift(6.0);
+        assert!((rad.TCMB - 19.11).abs() < 0.01, "TCMB = {}", rad.TCMB);
+
+        let local = co_cloud();
+        let mut highz = co_cloud();
+        highz.rad = rad;
+        let intTB = |cloud: &Cloud| cloud.line_lum("co").unwrap()[0].intTB;
+        let (z0, z6) = (intTB(&local), intTB(&highz));
+        // A 20 K cloud seen against a 19 K background barely shows
+        assert!(z6 < 0.3 * z0, "intTB {z6} at z = 6, {z0} at z = 0");
+
+        highz.rad.subtract_cmb = false;
+        assert!(intTB(&highz) > 3.0 * z6);
+    }
+
+    #[test]
+    fn thin_mass_recovers_cloud_mass() {
+        // Trace CO in dense gas is thin and nearly thermalized; the
+        // upper levels, which radiate fastest, fall a little below
+        // LTE, leaving slightly more of the species in J = 1
+        init();
+        let mut cloud = Cloud::simple(1.0e6, 20.0, &[("co", 1.0e-10)]).unwrap();
+        cloud.colDen = 1.0e23;
+        cloud.rad.subtract_cmb = false;
+        let R = (cloud.colDen / cloud.nH) as f64;
+        let mass = 4.0 / 3.0 * std::f64::consts::PI * R.powi(3) * cloud.rho();
+        let thin = cloud.thin_mass_from_line("co", 1, 0, 100.0 * pc).unwrap();
+        assert!((thin / mass - 1.0).abs() < 0.05, "{thin:e} g vs {mass:e} g");
+        let far = cloud.thin_mass_from_line("co", 1, 0, 1.0e4 * pc).unwrap();
+        assert!((far / thin - 1.0).abs() < 1.0e-10);
+
+        for d in [0.0, -pc, f64::INFINITY, f64::NAN] {
+            assert!(matches!(
+                cloud.thin_mass_from_line("co", 1, 0, d),
+                Err(DespoticError::InvalidDistance(_))
+            ));
+        }
+        cloud.nH = 0.0;
+        assert!(matches!(
+            cloud.thin_mass_from_line("co", 1, 0, pc),
+            Err(DespoticError::ZeroDensity)
+        ));
+    }
+
+    #[test]
+    fn continuum_subtraction_lowers_line_on_bright_dust() {
+        // A high column of 30 K dust gives a bright submillimeter
+        // continuum
+        let mut cloud = co_cloud();
+        cloud.colDen = 1.0e24;
+        cloud.set_td(30.0);
+        let plain = cloud.integrated_intensity("co", false).unwrap();
+        let subtracted = cloud.integrated_intensity("co", true).unwrap();
+        assert!(plain.iter().zip(&subtracted).all(|(p, s)| s < p));
+
+        // The dust is brightest at the highest frequency, so the 5-4
+        // line loses the most
+        let loss: Vec<f64> = plain
+            .iter()
+            .zip(&subtracted)
+            .map(|(p, s)| 1.0 - s / p)
+            .collect();
+        assert!(loss.windows(2).all(|w| w[1] > w[0]), "{loss:?}");
+        assert!(loss[4] > 0.1, "{loss:?}");
+    }
+
+    #[test]
+    fn peak_and_integrated_round_trip() {
+        // A 1 K line 2.3548 km/s wide, so sigma = 1 km/s, integrates to
+        // sqrt(2 pi) K km/s
+        let fwhm = 2.0 * (2.0 * std::f64::consts::LN_2).sqrt();
+        let area = (2.0 * std::f64::consts::PI).sqrt();
+        assert!((integrated_to_peak_tb(area, fwhm) - 1.0).abs() < 1.0e-12);
+        assert!((peak_to_integrated_tb(1.0, fwhm) - area).abs() < 1.0e-12);
+
+        let line = &co_cloud().line_lum("co").unwrap()[0];
+        let peak = line.peak_tb(3.0);
+        assert!((peak_to_integrated_tb(peak, 3.0) / line.intTB - 1.0).abs() < 1.0e-12);
+    }
+}
@@ -0,0 +1,211 @@
+//! Level populations shared between clouds in similar conditions
+
+use crate::cloud::Cloud;
+use crate::DespoticError;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+/// Default relative width of the bins into which the cloud
+/// properties are quantized
+const DEFAULT_REL_TOL: f64 = 1.0e-6;
+
+/// Quantized conditions under which an emitter's populations were
+/// solved: species, abundance, Tg, nH, colDen and dVdr
+type MemoKey = (String, [i64; 5]);
+
+/// Populations and the escape probabilities of the final iteration
+#[derive(Debug, Clone)]
+pub(crate) struct MemoEntry {
+    pub(crate) pops: Vec<f64>,
+    pub(crate) beta: Option<Vec<f64>>,
+}
+
+/// Counts describing how well a PopMemo is being used
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct MemoStats {
+    /// number of lookups that found stored populations
+    pub hits: u64,
+    /// number of lookups that did not
+    pub misses: u64,
+    /// number of sets of populations stored
+    pub entries: usize,
+}
+
+#[derive(Debug, Default)]
+struct MemoState {
+    entries: HashMap<MemoKey, MemoEntry>,
+    stats: MemoStats,
+}
+
+/// Cache of solved level populations, shared between the emitters of
+/// many clouds
+///
+/// Remarks
+///    Entries are keyed by the species name, its abundance, and the
+///    cloud's Tg, nH, colDen and dVdr, each quantized into bins of
+///    relative width rel_tol. Populations also depend on the
+///    composition, radiation field, sigmaNT and escape probability
+///    geometry, which are not part of the key, so a memo should only
+///    be shared between clouds that agree in these. Only converged
+///    solves are stored.
+#[derive(Debug)]
+pub struct PopMemo {
+    /// log of one plus the relative bin width
+    lnBin: f64,
+    state: Mutex<MemoState>,
+}
+
+impl Default for PopMemo {
+    fn default() -> Self {
+        Self::new(DEFAULT_REL_TOL).expect("the default tolerance is valid")
+    }
+}
+
+impl PopMemo {
+    /// Create an empty memo
+    ///
+    /// Parameters
+    ///    rel_tol : float
+    ///       relative width of the bins into which cloud properties
+    ///       are quantized; clouds whose properties fall in the same
+    ///       bins share populations
+    ///
+    /// Returns
+    ///    the memo
+    ///
+    /// Remarks
+    ///    An InvalidTolerance error is returned unless rel_tol is
+    ///    finite and at least f64::EPSILON; narrower bins would be
+    ///    finer than the properties can resolve.
+    pub fn new(rel_tol: f64) -> Result<Self, DespoticError> {
+        if !(rel_tol >= f64::EPSILON && rel_tol.is_finite()) {
+            return Err(DespoticError::InvalidTolerance(rel_tol));
+        }
+        Ok(Self {
+            lnBin: rel_tol.ln_1p(),
+            state: Mutex::new(MemoState::default()),
+        })
+    }
+
+    /// Hit and miss counts and the number of stored entries
+    pub fn stats(&self) -> MemoStats {
+        let state = self.lock();
+        MemoStats {
+            entries: state.entries.len(),
+            ..state.stats
+        }
+    }
+
+    /// Discard every stored entry and reset the counts
+    pub fn clear(&self) {
+        *self.lock() = MemoState::default();
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, MemoState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Bin index of a value; zero, and the sign of the value, get
+    /// bins of their own
+    fn quantize(&self, x: f64) -> i64 {
+        if x == 0.0 {
+            return 0;
+        }
+        // Interleave the bins of positive and negative values,
+        // leaving 0 for zero itself; the casts saturate, so infinite
+        // or NaN values get bins at the ends rather than overflowing
+        let bin = ((x.abs().ln() / self.lnBin).round() as i64).saturating_mul(2);
+        if x > 0.0 {
+            bin.saturating_add(1)
+        } else {
+            bin.saturating_sub(1)
+        }
+    }
+
+    fn key(&self, species: &str, abundance: f32, cloud: &Cloud) -> MemoKey {
+        let props = [abundance, cloud.Tg, cloud.nH, cloud.colDen, cloud.dVdr];
+        (species.to_string(), props.map(|x| self.quantize(x as f64)))
+    }
+
+    /// Stored populations for an emitter in a cloud, counting the
+    /// lookup as a hit or a miss
+    pub(crate) fn get(&self, species: &str, abundance: f32, cloud: &Cloud) -> Option<MemoEntry> {
+        let key = self.key(species, abundance, cloud);
+        let mut state = self.lock();
+        let entry = state.entries.get(&key).cloned();
+        if entry.is_some() {
+            state.stats.hits += 1;
+        } else {
+            state.stats.misses += 1;
+        }
+        entry
+    }
+
+    /// Store the populations solved for an emitter in a cloud
+    pub(crate) fn insert(&self, species: &str, abundance: f32, cloud: &Cloud, entry: MemoEntry) {
+        let key = self.key(species, abundance, cloud);
+        self.lock().entries.insert(key, entry);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::co_cloud;
+    use std::sync::Arc;
+
+    #[test]
+    fn identical_clouds_share_populations() {
+        let memo = Arc::new(PopMemo::default());
+        let (mut first, mut second) = (co_cloud(), co_cloud());
+        first.set_pop_memo(memo.clone());
+        second.set_pop_memo(memo.clone());
+
+        let pops = first.emitters["co"].level_pops(&first).unwrap();
+        let expected = MemoStats {
+            hits: 0,
+            misses: 1,
+            entries: 1,
+        };
+        assert_eq!(memo.stats(), expected);
+        assert_eq!(second.emitters["co"].level_pops(&second).unwrap(), pops);
+        assert_eq!(memo.stats().hits, 1);
+
+        // Different conditions miss
+        second.set_tg(25.0);
+        second.emitters["co"].level_pops(&second).unwrap();
+        assert_eq!(memo.stats().misses, 2);
+        assert_eq!(memo.stats().entries, 2);
+
+        memo.clear();
+        assert_eq!(memo.stats(), MemoStats::default());
+    }
+
+    #[test]
+    fn tolerance_must_be_resolvable() {
+        for rel_tol in [0.0, -1.0e-6, 1.0e-17, f64::NAN, f64::INFINITY] {
+            assert!(matches!(
+                PopMemo::new(rel_tol),
+                Err(DespoticError::InvalidTolerance(_))
+            ));
+        }
+
+        // The narrowest bins still number the extreme values without
+        // overflow, and keep neighbouring values apart
+        let memo = PopMemo::new(f64::EPSILON).unwrap();
+        for x in [
+            f64::MIN_POSITIVE,
+            f64::MAX,
+            -f64::MAX,
+            f64::INFINITY,
+            f64::NAN,
+        ] {
+            memo.quantize(x);
+        }
+        assert_ne!(memo.quantize(1.0e3), memo.quantize(1.0e3 * (1.0 + 1.0e-15)));
+
+        let mut cloud = co_cloud();
+        cloud.set_pop_memo(Arc::new(memo));
+        cloud.emitters["co"].level_pops(&cloud).unwrap();
+    }
+}
@@ -0,0 +1,49 @@
+//! Helpers for parsing numbers in data files
+
+use std::num::ParseFloatError;
+
+/// Parse a floating point number
+///
+/// Parameters
+///    s : string
+///       text of the number, without surrounding whitespace
+///
+/// Returns
+///    the value of the number
+///
+/// Remarks
+///    In addition to the syntax accepted by str::parse, Fortran-style
+///    double precision exponents such as 1.0D-16 or 1d-16 are
+///    accepted, since legacy cloud and LAMDA files sometimes use them.
+///    A leading sign, and a mantissa with no digits before or after
+///    the decimal point, are accepted as well, so .5e3, -.25 and
+///    +1.e2 all parse; a lone decimal point does not.
+pub fn parse_float(s: &str) -> Result<f64, ParseFloatError> {
+    if s.contains(['D', 'd']) {
+        s.replace(['D', 'd'], "e").parse()
+    } else {
+        s.parse()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fortran_exponents() {
+        assert_eq!(parse_float("2.3D5"), Ok(2.3e5));
+        assert_eq!(parse_float("1d-10"), Ok(1.0e-10));
+        assert_eq!(parse_float("1.5e3"), Ok(1.5e3));
+        assert!(parse_float("1.0D").is_err());
+    }
+
+    #[test]
+    fn mantissa_without_leading_or_trailing_digits() {
+        assert_eq!(parse_float(".5e3"), Ok(500.0));
+        assert_eq!(parse_float("-.25"), Ok(-0.25));
+        assert_eq!(parse_float("+1.e2"), Ok(100.0));
+        assert_eq!(parse_float(".5D3"), Ok(500.0));
+        assert!(parse_float(".").is_err());
+    }
+}
@@ -0,0 +1,516 @@
+use crate::consts::{c, h, kB};
+use crate::interp::{interp1d, Interp};
+use crate::units::Temperature;
+use crate::DespoticError;
+
+/// Cosmic ray ionization rates of H2 and He relative to the primary
+/// ionization rate per H atom (Glassgold & Langer 1974)
+const CR_H2_FACTOR: f64 = 2.3;
+const CR_HE_FACTOR: f64 = 1.1;
+
+/// Dilution factors and temperatures of the blackbodies that make up
+/// the optical and near-IR starlight of the solar neighborhood ISRF
+/// (Mathis, Mezger & Panagia 1983); scaled by chi, these give the
+/// spectrum of Isrf::Standard. The ultraviolet component, which
+/// carries little of the energy, is omitted.
+const ISRF_BLACKBODIES: [(f64, f64); 3] =
+    [(1.0e-14, 7500.0), (1.65e-13, 4000.0), (4.0e-13, 3000.0)];
+
+/// Planck function B_nu(T), in erg cm^-2 s^-1 Hz^-1 sr^-1; zero if T
+/// is not positive
+fn planck(nu: f64, T: f64) -> f64 {
+    if T > 0.0 {
+        2.0 * h * nu.powi(3) / (c * c) / (h * nu / (kB * T)).exp_m1()
+    } else {
+        0.0
+    }
+}
+
+/// Cosmic ray ionization rates of individual species, in s^-1
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CrRates {
+    /// ionization rate per H atom
+    pub H: f64,
+    /// ionization rate per H2 molecule
+    pub H2: f64,
+    /// ionization rate per He atom
+    pub He: f64,
+}
+
+/// Spectral shape of the interstellar radiation field
+///
+/// The shape is used only to weight the dust opacity in
+/// DustProp::mean_opacity. Photoelectric and dust heating and
+/// photochemistry depend on the ISRF through Radiation::chi alone,
+/// and dust heating uses the cross section DustProp::sigmaISRF
+/// rather than mean_opacity.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub enum Isrf {
+    /// the solar neighborhood ISRF, scaled by chi
+    #[default]
+    Standard,
+    /// a tabulated spectrum, built by Isrf::custom
+    Custom(IsrfTable),
+}
+
+/// A tabulated ISRF spectrum
+///
+/// The table can only be built by Isrf::custom, which checks it, so
+/// it always has at least two points with increasing frequencies.
+#[derive(Debug, Clone, PartialEq)]
+pub struct IsrfTable {
+    /// frequencies, in Hz, in increasing order
+    nu: Vec<f64>,
+    /// specific intensity at each frequency, in erg cm^-2 s^-1 Hz^-1
+    /// sr^-1
+    intensity: Vec<f64>,
+}
+
+impl IsrfTable {
+    /// Tabulated frequencies, in Hz, in increasing order
+    pub fn nu(&self) -> &[f64] {
+        &self.nu
+    }
+
+    /// Specific intensity at each tabulated frequency, in erg cm^-2
+    /// s^-1 Hz^-1 sr^-1
+    pub fn intensity(&self) -> &[f64] {
+        &self.intensity
+    }
+
+    /// Lowest and highest tabulated frequencies, in Hz
+    pub fn band(&self) -> (f64, f64) {
+        (self.nu[0], self.nu[self.nu.len() - 1])
+    }
+
+    /// Multiply the spectrum by a constant factor
+    ///
+    /// Parameters
+    ///    factor : float
+    ///       factor by which to multiply every intensity; it should
+    ///       be finite and non-negative
+    ///
+    /// Returns
+    ///    Nothing
+    pub fn scale(&mut self, factor: f64) {
+        self.intensity.iter_mut().for_each(|I| *I *= factor);
+    }
+}
+
+impl Isrf {
+    /// A tabulated ISRF spectrum
+    ///
+    /// Parameters
+    ///    points : list of (float, float)
+    ///       frequency in Hz and specific intensity in erg cm^-2 s^-1
+    ///       Hz^-1 sr^-1 at each point
+    ///
+    /// Returns
+    ///    the spectrum
+    ///
+    /// Remarks
+    ///    See Isrf for what the spectrum affects. An InvalidSpectrum
+    ///    error is returned if there are fewer than
+    ///    two points, the frequencies are not finite, positive and
+    ///    strictly increasing, or an intensity is negative or not
+    ///    finite
+    pub fn custom(points: &[(f64, f64)]) -> Result<Self, DespoticError> {
+        if points.len() < 2 {
+            return Err(DespoticError::InvalidSpectrum(
+                "at least two points are needed".to_string(),
+            ));
+        }
+        // NaN fails none of the comparisons below, so it has to be
+        // rejected separately
+        if points.iter().any(|p| !p.0.is_finite())
+            || points[0].0 <= 0.0
+            || points.windows(2).any(|w| w[1].0 <= w[0].0)
+        {
+            return Err(DespoticError::InvalidSpectrum(
+                "frequencies must be positive and increasing".to_string(),
+            ));
+        }
+        if points.iter().any(|&(_, I)| !I.is_finite() || I < 0.0) {
+            return Err(DespoticError::InvalidSpectrum(
+                "intensities must be finite and non-negative".to_string(),
+            ));
+        }
+        Ok(Self::Custom(IsrfTable {
+            nu: points.iter().map(|p| p.0).collect(),
+            intensity: points.iter().map(|p| p.1).collect(),
+        }))
+    }
+
+    /// Specific intensity of a tabulated spectrum
+    ///
+    /// Parameters
+    ///    nu : float
+    ///       frequency, in Hz
+    ///
+    /// Returns
+    ///    the intensity, in erg cm^-2 s^-1 Hz^-1 sr^-1, interpolated as
+    ///    a power law between tabulated points and zero outside them;
+    ///    None for the standard ISRF, which is not tabulated
+    pub fn intensity(&self, nu: f64) -> Option<f64> {
+        match self {
+            Self::Standard => None,
+            Self::Custom(table) => {
+                let (lo, hi) = table.band();
+                if nu < lo || nu > hi {
+                    Some(0.0)
+                } else {
+                    Some(interp1d(nu, &table.nu, &table.intensity, Interp::LogLog))
+                }
+            }
+        }
+    }
+}
+
+/// The radiation field impinging on a cloud
+#[derive(Debug, Clone)]
+pub struct Radiation {
+    /// temperature of the cosmic microwave background, in K
+    pub TCMB: f32,
+    /// temperature of the dust-reprocessed IR radiation field, in K
+    pub TradDust: f32,
+    /// dilution factor of the IR radiation field
+    pub fdDilute: f32,
+    /// primary ionization rate from cosmic rays and X-rays, in s^-1
+    /// H^-1
+    pub ionRate: f32,
+    /// ionization rate per H2 molecule, in s^-1; if None, 2.3 times
+    /// ionRate
+    pub cr_rate_h2: Option<f32>,
+    /// ionization rate per He atom, in s^-1; if None, 1.1 times
+    /// ionRate
+    pub cr_rate_he: Option<f32>,
+    /// strength of the ISRF, normalized to the solar neighborhood
+    /// value
+    pub chi: f32,
+    /// spectral shape of the ISRF; see Isrf for what it affects
+    pub isrf: Isrf,
+    /// if true, absorption of CMB photons is subtracted from
+    /// predicted line intensities, as when observing against the
+    /// CMB; if false, lines are reported as they would appear
+    /// against an empty background. The CMB always contributes to
+    /// excitation and to the gas energy balance.
+    pub subtract_cmb: bool,
+}
+
+impl Default for Radiation {
+    fn default() -> Self {
+        Self {
+            TCMB: 2.73,
+            TradDust: 0.0,
+            fdDilute: 1.0,
+            ionRate: 2.0e-17,
+            cr_rate_h2: None,
+            cr_rate_he: None,
+            chi: 1.0,
+            isrf: Isrf::Standard,
+            subtract_cmb: true,
+        }
+    }
+}
+
+/// Named radiation environments
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RadiationPreset {
+    /// solar neighborhood ISRF and cosmic ray ionization rate, plus
+    /// the CMB; this is the default radiation field
+    MilkyWay,
+    /// the CMB alone, with no starlight or cosmic rays
+    CmbOnly,
+    /// a starburst, with the ISRF and cosmic ray ionization rate both
+    /// 1000 times their solar neighborhood values
+    Starburst,
+}
+
+/// Builder for a Radiation, starting from a preset or the default
+/// field and overriding individual properties
+#[derive(Debug, Clone, Default)]
+pub struct RadiationBuilder {
+    rad: Radiation,
+}
+
+impl RadiationBuilder {
+    /// Start from the default (Milky Way) radiation field
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Start from a preset radiation field
+    pub fn from_preset(p: RadiationPreset) -> Self {
+        Self {
+            rad: Radiation::preset(p),
+        }
+    }
+
+    /// Set the CMB temperature
+    pub fn TCMB(mut self, TCMB: impl Into<Temperature>) -> Self {
+        self.rad.TCMB = TCMB.into().value() as f32;
+        self
+    }
+
+    /// Set the temperature of the dust-reprocessed IR field
+    pub fn TradDust(mut self, TradDust: impl Into<Temperature>) -> Self {
+        self.rad.TradDust = TradDust.into().value() as f32;
+        self
+    }
+
+    /// Set the dilution factor of the IR field
+    pub fn fdDilute(mut self, fdDilute: f32) -> Self {
+        self.rad.fdDilute = fdDilute;
+        self
+    }
+
+    /// Set the primary ionization rate, in s^-1 H^-1
+    pub fn ionRate(mut self, ionRate: f32) -> Self {
+        self.rad.ionRate = ionRate;
+        self
+    }
+
+    /// Set the ionization rate per H2 molecule, in s^-1
+    pub fn cr_rate_h2(mut self, rate: f32) -> Self {
+        self.rad.cr_rate_h2 = Some(rate);
+        self
+    }
+
+    /// Set the ionization rate per He atom, in s^-1
+    pub fn cr_rate_he(mut self, rate: f32) -> Self {
+        self.rad.cr_rate_he = Some(rate);
+        self
+    }
+
+    /// Set the ISRF strength relative to the solar neighborhood
+    pub fn chi(mut self, chi: f32) -> Self {
+        self.rad.chi = chi;
+        self
+    }
+
+    /// Set the spectral shape of the ISRF
+    pub fn isrf(mut self, isrf: Isrf) -> Self {
+        self.rad.isrf = isrf;
+        self
+    }
+
+    /// Set whether absorption of CMB photons is subtracted from
+    /// predicted line intensities
+    pub fn subtract_cmb(mut self, subtract_cmb: bool) -> Self {
+        self.rad.subtract_cmb = subtract_cmb;
+        self
+    }
+
+    /// Finish building
+    pub fn build(self) -> Radiation {
+        self.rad
+    }
+}
+
+impl Radiation {
+    /// Radiation field of a named environment
+    ///
+    /// Parameters
+    ///    p : RadiationPreset
+    ///       the environment
+    ///
+    /// Returns
+    ///    the radiation field of that environment
+    pub fn preset(p: RadiationPreset) -> Self {
+        let mw = Self::default();
+        match p {
+            RadiationPreset::MilkyWay => mw,
+            RadiationPreset::CmbOnly => Self {
+                ionRate: 0.0,
+                chi: 0.0,
+                ..mw
+            },
+            RadiationPreset::Starburst => Self {
+                ionRate: 1000.0 * mw.ionRate,
+                chi: 1000.0 * mw.chi,
+                ..mw
+            },
+        }
+    }
+
+    /// The default radiation field with the CMB at a given redshift
+    ///
+    /// Parameters
+    ///    z : float
+    ///       redshift
+    ///
+    /// Returns
+    ///    the default field, with TCMB = 2.73 (1 + z) K
+    pub fn with_redshift(z: f64) -> Self {
+        let mw = Self::default();
+        Self {
+            TCMB: (mw.TCMB as f64 * (1.0 + z)) as f32,
+            ..mw
+        }
+    }
+
+    /// Start building a radiation field from the default field
+    pub fn builder() -> RadiationBuilder {
+        RadiationBuilder::new()
+    }
+
+    /// Photon occupation number of the background radiation field
+    ///
+    /// Parameters
+    ///    nu : float
+    ///       frequency, in Hz
+    ///
+    /// Returns
+    ///    photon occupation number of the CMB plus the diluted
+    ///    dust-reprocessed IR field at frequency nu
+    pub fn ngamma(&self, nu: f64) -> f64 {
+        let mut ng = self.cmb_occupation(nu);
+        if self.TradDust > 0.0 {
+            ng += self.fdDilute as f64 / (h * nu / (kB * self.TradDust as f64)).exp_m1();
+        }
+        ng
+    }
+
+    /// Photon occupation number of the CMB
+    ///
+    /// Parameters
+    ///    nu : float
+    ///       frequency, in Hz
+    ///
+    /// Returns
+    ///    photon occupation number of a blackbody at TCMB at frequency
+    ///    nu; zero if TCMB is not positive
+    pub fn cmb_occupation(&self, nu: f64) -> f64 {
+        if self.TCMB > 0.0 {
+            1.0 / (h * nu / (kB * self.TCMB as f64)).exp_m1()
+        } else {
+            0.0
+        }
+    }
+
+    /// Mean specific intensity of the field
+    ///
+    /// Parameters
+    ///    nu : float
+    ///       frequency, in Hz
+    ///
+    /// Returns
+    ///    J_nu of the CMB, the diluted dust-reprocessed IR field, and
+    ///    the ISRF together, in erg cm^-2 s^-1 Hz^-1 sr^-1
+    ///
+    /// Remarks
+    ///    The standard ISRF is chi times the blackbodies of Mathis,
+    ///    Mezger & Panagia (1983); a custom spectrum is used as
+    ///    tabulated, and is not scaled by chi
+    pub(crate) fn mean_intensity(&self, nu: f64) -> f64 {
+        let isrf = match self.isrf.intensity(nu) {
+            Some(I) => I,
+            None => {
+                self.chi as f64
+                    * ISRF_BLACKBODIES
+                        .iter()
+                        .map(|&(W, T)| W * planck(nu, T))
+                        .sum::<f64>()
+            }
+        };
+        planck(nu, self.TCMB as f64)
+            + self.fdDilute as f64 * planck(nu, self.TradDust as f64)
+            + isrf
+    }
+
+    /// Frequencies, in Hz, between which the field carries
+    /// essentially all of its energy; None if the field is empty
+    ///
+    /// Remarks
+    ///    Each blackbody component at temperature T spans 1e-4 to 100
+    ///    times k_B T / h, and a custom ISRF its tabulated range
+    pub(crate) fn band(&self) -> Option<(f64, f64)> {
+        let mut temps = vec![
+            (self.TCMB as f64, 1.0),
+            (self.TradDust as f64, self.fdDilute as f64),
+        ];
+        if self.isrf == Isrf::Standard {
+            temps.extend(ISRF_BLACKBODIES.iter().map(|&(_, T)| (T, self.chi as f64)));
+        }
+        let mut band = temps
+            .into_iter()
+            .filter(|&(T, w)| T > 0.0 && w > 0.0)
+            .map(|(T, _)| (1.0e-4 * kB * T / h, 100.0 * kB * T / h))
+            .reduce(|a, b| (a.0.min(b.0), a.1.max(b.1)));
+        if let Isrf::Custom(table) = &self.isrf {
+            let custom = table.band();
+            band = Some(band.map_or(custom, |b| (b.0.min(custom.0), b.1.max(custom.1))));
+        }
+        band
+    }
+
+    /// Cosmic ray ionization rates of H, H2, and He
+    ///
+    /// Returns
+    ///    the ionization rate of each species, in s^-1; rates that
+    ///    are not set explicitly are the standard multiples of ionRate
+    pub fn cr_rates(&self) -> CrRates {
+        let zeta = self.ionRate as f64;
+        CrRates {
+            H: zeta,
+            H2: self.cr_rate_h2.map_or(CR_H2_FACTOR * zeta, |r| r as f64),
+            He: self.cr_rate_he.map_or(CR_HE_FACTOR * zeta, |r| r as f64),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_cr_rates_follow_ion_rate() {
+        let rad = Radiation::default();
+        let zeta = rad.cr_rates();
+        assert_eq!(zeta.H, rad.ionRate as f64);
+        assert_eq!(zeta.H2, CR_H2_FACTOR * rad.ionRate as f64);
+        assert_eq!(zeta.He, CR_HE_FACTOR * rad.ionRate as f64);
+
+        let rad = Radiation::builder()
+            .ionRate(1.0e-16)
+            .cr_rate_h2(5.0e-16)
+            .build();
+        let zeta = rad.cr_rates();
+        assert_eq!(zeta.H, 1.0e-16_f32 as f64);
+        assert_eq!(zeta.H2, 5.0e-16_f32 as f64);
+    }
+
+    #[test]
+    fn presets() {
+        let rad = Radiation::preset(RadiationPreset::CmbOnly);
+        assert_eq!(rad.chi, 0.0);
+        assert_eq!(rad.TCMB, 2.73);
+        assert_eq!(rad.ionRate, 0.0);
+
+        let rad = RadiationBuilder::from_preset(RadiationPreset::Starburst)
+            .TCMB(5.0)
+            .build();
+        assert_eq!(rad.chi, 1000.0);
+        assert_eq!(rad.TCMB, 5.0);
+    }
+
+    #[test]
+    fn custom_table_is_checked_and_scaled() {
+        let bad = |points: &[(f64, f64)]| {
+            matches!(Isrf::custom(points), Err(DespoticError::InvalidSpectrum(_)))
+        };
+        assert!(bad(&[]));
+        assert!(bad(&[(1.0e13, 1.0)]));
+        assert!(bad(&[(1.0e13, 1.0), (1.0e13, 2.0)]));
+        assert!(bad(&[(1.0e13, 1.0), (1.0e14, -1.0)]));
+
+        let mut isrf = Isrf::custom(&[(1.0e13, 1.0e-17), (1.0e14, 4.0e-17)]).unwrap();
+        let Isrf::Custom(table) = &mut isrf else {
+            panic!("expected a tabulated spectrum");
+        };
+        assert_eq!(table.band(), (1.0e13, 1.0e14));
+        table.scale(2.0);
+        assert_eq!(table.intensity(), [2.0e-17, 8.0e-17]);
+        assert_eq!(isrf.intensity(1.0e14), Some(8.0e-17));
+    }
+}
@@ -0,0 +1,300 @@
+//! One-dimensional root finding
+
+/// A point (x, f(x))
+pub(crate) type Point = (f64, f64);
+
+/// Bracket a root of f by stepping outward from a starting point
+///
+/// Parameters
+///    f : function
+///       function whose root is sought; errors it returns are passed
+///       straight back to the caller
+///    x0 : float
+///       starting point
+///    step : float
+///       distance moved in each direction per step
+///    (xmin, xmax) : float
+///       limits of the search
+///
+/// Returns
+///    Some((lo, hi)), points on either side of a sign change of f
+///    with lo < hi, or None if f does not change sign between xmin
+///    and xmax
+///
+/// Remarks
+///    The search moves up and down alternately, so if f has several
+///    roots the bracket found is for one of those nearest x0
+pub(crate) fn bracket_outward<E>(
+    mut f: impl FnMut(f64) -> Result<f64, E>,
+    x0: f64,
+    step: f64,
+    (xmin, xmax): (f64, f64),
+) -> Result<Option<(Point, Point)>, E> {
+    let x0 = x0.clamp(xmin, xmax);
+    let f0 = f(x0)?;
+    if f0 == 0.0 {
+        return Ok(Some(((x0, f0), (x0, f0))));
+    }
+    let (mut up, mut down) = ((x0, f0), (x0, f0));
+    loop {
+        if up.0 >= xmax && down.0 <= xmin {
+            return Ok(None);
+        }
+        if up.0 < xmax {
+            let x = (up.0 + step).min(xmax);
+            let next = (x, f(x)?);
+            if next.1.signum() != up.1.signum() {
+                return Ok(Some((up, next)));
+            }
+            up = next;
+        }
+        if down.0 > xmin {
+            let x = (down.0 - step).max(xmin);
+            let next = (x, f(x)?);
+            if next.1.signum() != down.1.signum() {
+                return Ok(Some((next, down)));
+            }
+            down = next;
+        }
+    }
+}
+
+/// Find a root of f in a bracketing interval by Brent's method
+///
+/// Parameters
+///    f : function
+///       function whose root is sought; errors it returns are passed
+///       straight back to the caller
+///    (a, fa), (b, fb) : float
+///       ends of the interval and the values of f there, which must
+///       not have the same sign
+///    xtol : float
+///       absolute tolerance on the root
+///    max_iter : int
+///       maximum number of evaluations of f
+///
+/// Returns
+///    Some(root), or None if the tolerance was not reached within
+///    max_iter evaluations
+pub(crate) fn brent<E>(
+    mut f: impl FnMut(f64) -> Result<f64, E>,
+    (mut a, mut fa): Point,
+    (mut b, mut fb): Point,
+    xtol: f64,
+    max_iter: usize,
+) -> Result<Option<f64>, E> {
+    if fa == 0.0 {
+        return Ok(Some(a));
+    }
+    if fb == 0.0 {
+        return Ok(Some(b));
+    }
+    let (mut c, mut fc) = (a, fa);
+    let mut d = b - a;
+    let mut e = d;
+    for _ in 0..max_iter {
+        if fb.signum() == fc.signum() {
+            (c, fc) = (a, fa);
+            d = b - a;
+            e = d;
+        }
+        if fc.abs() < fb.abs() {
+            (a, fa) = (b, fb);
+            (b, fb) = (c, fc);
+            (c, fc) = (a, fa);
+        }
+        let tol = 2.0 * f64::EPSILON * b.abs() + 0.5 * xtol;
+        let m = 0.5 * (c - b);
+        if m.abs() <= tol || fb == 0.0 {
+            return Ok(Some(b));
+        }
+        if e.abs() >= tol && fa.abs() > fb.abs() {
+            // Try inverse quadratic interpolation, or the secant
+            // method if only two distinct points are available
+            let s = fb / fa;
+            let (mut p, mut q) = if a == c {
+                (2.0 * m * s, 1.0 - s)
+            } else {
+                let q = fa / fc;
+                let r = fb / fc;
+                (
+                    s * (2.0 * m * q * (q - r) - (b - a) * (r - 1.0)),
+                    (q - 1.0) * (r - 1.0) * (s - 1.0),
+                )
+            };
+            if p > 0.0 {
+                q = -q;
+            } else {
+                p = -p;
+            }
+            if 2.0 * p < (3.0 * m * q - (tol * q).abs()).min((e * q).abs()) {
+                e = d;
+                d = p / q;
+            } else {
+                d = m;
+                e = m;
+            }
+        } else {
+            d = m;
+            e = m;
+        }
+        (a, fa) = (b, fb);
+        b += if d.abs() > tol { d } else { tol.copysign(m) };
+        fb = f(b)?;
+    }
+    Ok(None)
+}
+
+/// Find a root of f in a bracketing interval by bisection
+///
+/// Parameters
+///    f : function
+///       function whose root is sought; errors it returns are passed
+///       straight back to the caller
+///    (a, fa), (b, fb) : float
+///       ends of the interval and the values of f there, which must
+///       not have the same sign
+///    xtol : float
+///       absolute tolerance on the root
+///    max_iter : int
+///       maximum number of evaluations of f
+///
+/// Returns
+///    Some(root), or None if the tolerance was not reached within
+///    max_iter evaluations
+pub(crate) fn bisection<E>(
+    mut f: impl FnMut(f64) -> Result<f64, E>,
+    (mut a, fa): Point,
+    (mut b, fb): Point,
+    xtol: f64,
+    max_iter: usize,
+) -> Result<Option<f64>, E> {
+    if fa == 0.0 {
+        return Ok(Some(a));
+    }
+    if fb == 0.0 {
+        return Ok(Some(b));
+    }
+    let sa = fa.signum();
+    for _ in 0..max_iter {
+        let m = 0.5 * (a + b);
+        if (b - a).abs() <= 2.0 * xtol {
+            return Ok(Some(m));
+        }
+        let fm = f(m)?;
+        if fm == 0.0 {
+            return Ok(Some(m));
+        }
+        if fm.signum() == sa {
+            a = m;
+        } else {
+            b = m;
+        }
+    }
+    Ok(None)
+}
+
+/// Find a root of f by Newton's method, with the derivative estimated
+/// by finite differences
+///
+/// Parameters
+///    f : function
+///       function whose root is sought; errors it returns are passed
+///       straight back to the caller
+///    x0 : float
+///       starting point
+///    dx : float
+///       step used to estimate the derivative
+///    (xmin, xmax) : float
+///       limits of the search; steps beyond them are cut back to the
+///       limit
+///    xtol : float
+///       absolute tolerance on the root
+///    max_iter : int
+///       maximum number of evaluations of f
+///
+/// Returns
+///    Some(root), or None if the tolerance was not reached within
+///    max_iter evaluations, the derivative vanished, or the iteration
+///    was driven past a limit
+///
+/// Remarks
+///    Each iteration costs two evaluations of f. Convergence is fast
+///    near a simple root but not guaranteed from a poor start.
+pub(crate) fn newton<E>(
+    mut f: impl FnMut(f64) -> Result<f64, E>,
+    x0: f64,
+    dx: f64,
+    (xmin, xmax): (f64, f64),
+    xtol: f64,
+    max_iter: usize,
+) -> Result<Option<f64>, E> {
+    let mut x = x0.clamp(xmin, xmax);
+    for _ in 0..max_iter / 2 {
+        let fx = f(x)?;
+        if fx == 0.0 {
+            return Ok(Some(x));
+        }
+        // Difference away from the nearer limit, so the second point
+        // is always inside the search range
+        let step = if x + dx <= xmax { dx } else { -dx };
+        let deriv = (f(x + step)? - fx) / step;
+        if deriv == 0.0 || !deriv.is_finite() {
+            return Ok(None);
+        }
+        let xNew = x - fx / deriv;
+        if (xNew - x).abs() <= xtol {
+            return Ok(Some(xNew.clamp(xmin, xmax)));
+        }
+        if xNew.clamp(xmin, xmax) == x {
+            // The root lies beyond the limit we are already at
+            return Ok(None);
+        }
+        x = xNew.clamp(xmin, xmax);
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::Infallible;
+
+    /// x^3 - 2x - 5, whose only real root is Wallis's 2.0945514815...
+    fn wallis(x: f64) -> Result<f64, Infallible> {
+        Ok(x * x * x - 2.0 * x - 5.0)
+    }
+
+    const ROOT: f64 = 2.0945514815423265;
+
+    #[test]
+    fn finders_agree_on_a_smooth_function() {
+        let (lo, hi) = bracket_outward(wallis, 0.0, 0.5, (-10.0, 10.0))
+            .unwrap()
+            .unwrap();
+        assert!(lo.0 < ROOT && ROOT < hi.0);
+        let roots = [
+            bisection(wallis, lo, hi, 1.0e-10, 100).unwrap().unwrap(),
+            brent(wallis, lo, hi, 1.0e-10, 100).unwrap().unwrap(),
+            newton(wallis, 3.0, 1.0e-6, (-10.0, 10.0), 1.0e-10, 100)
+                .unwrap()
+                .unwrap(),
+        ];
+        for x in roots {
+            assert!((x - ROOT).abs() < 1.0e-8, "root = {x}");
+        }
+    }
+
+    #[test]
+    fn errors_pass_through() {
+        let fail = |_: f64| Err::<f64, _>("no value");
+        assert_eq!(
+            bracket_outward(fail, 0.0, 1.0, (-1.0, 1.0)),
+            Err("no value")
+        );
+        assert_eq!(
+            newton(fail, 0.0, 1.0e-6, (-1.0, 1.0), 1.0e-10, 10),
+            Err("no value")
+        );
+    }
+}
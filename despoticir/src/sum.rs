@@ -0,0 +1,46 @@
+//! Compensated summation
+
+/// Sum a sequence of floats with Kahan compensated summation
+///
+/// Remarks
+///    Uses Neumaier's variant, which remains accurate when a term is
+///    larger in magnitude than the running sum, so the result does not
+///    depend on the order of the terms to within a few ulps
+pub(crate) fn kahan_sum(values: impl IntoIterator<Item = f64>) -> f64 {
+    let mut sum = 0.0;
+    let mut comp = 0.0;
+    for v in values {
+        let t = sum + v;
+        if sum.abs() >= v.abs() {
+            comp += (sum - t) + v;
+        } else {
+            comp += (v - t) + sum;
+        }
+        sum = t;
+    }
+    sum + comp
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tiny_terms_are_not_lost() {
+        // One large contribution followed by many that are each below
+        // half an ulp of it: naive summation drops them all
+        let tiny = 1.0e-17;
+        let n = 1_000_000;
+        let terms = || std::iter::once(1.0).chain(std::iter::repeat_n(tiny, n));
+        let exact = 1.0 + n as f64 * tiny;
+        let naive: f64 = terms().sum();
+        let compensated = kahan_sum(terms());
+        assert_eq!(naive, 1.0);
+        assert!((compensated - exact).abs() <= f64::EPSILON * exact);
+    }
+
+    #[test]
+    fn cancellation() {
+        assert_eq!(kahan_sum([1.0, 1.0e100, 1.0, -1.0e100]), 2.0);
+    }
+}
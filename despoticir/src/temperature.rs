@@ -0,0 +1,396 @@
+//! Solvers for the equilibrium gas and dust temperatures
+
+use crate::cloud::Cloud;
+use crate::consts::kB;
+use crate::roots::{bisection, bracket_outward, brent, newton};
+use crate::DespoticError;
+
+/// Range of log10 T, in K, searched for equilibrium temperatures
+const LOG_T_MIN: f64 = 0.0;
+const LOG_T_MAX: f64 = 5.0;
+/// Step in log10 T used to bracket an equilibrium
+const LOG_T_STEP: f64 = 0.25;
+/// Step in log10 T used to estimate derivatives for Newton's method
+const LOG_T_DIFF: f64 = 1.0e-4;
+/// Tolerance on log10 T of the equilibrium temperatures
+const LOG_T_TOL: f64 = 1.0e-6;
+/// Maximum number of function evaluations in a temperature solve
+const MAX_EVAL: usize = 100;
+/// Step in log10 T used to scan for the multiple equilibria of a
+/// phase diagram
+const LOG_T_SCAN: f64 = 0.05;
+/// Relative change in both temperatures below which alternating gas
+/// and dust solves are considered converged
+const TEMP_TOL: f64 = 1.0e-4;
+/// Maximum number of alternating gas and dust solves
+const MAX_TEMP_ITER: usize = 50;
+
+/// Method used to find an equilibrium temperature
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RootFinder {
+    /// bisection of a bracketing interval; slow but always converges
+    /// once a bracket is found
+    Bisection,
+    /// Brent's method on a bracketing interval, which converges as
+    /// reliably as bisection and usually much faster
+    #[default]
+    Brent,
+    /// Newton's method from the current temperature, with a
+    /// finite-difference derivative; fastest near the solution, but
+    /// may fail from a poor starting temperature
+    Newton,
+}
+
+/// Options for the temperature solvers
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TempSolverOptions {
+    /// method used to find each equilibrium temperature
+    pub root_finder: RootFinder,
+}
+
+/// Starting point of a temperature search, log10 T
+fn log_start(T: f32) -> f64 {
+    if T > 0.0 {
+        (T as f64).log10()
+    } else {
+        1.0
+    }
+}
+
+/// Find a zero of f, a function of log10 T, starting from T0
+fn solve_log_temp(
+    mut f: impl FnMut(f64) -> Result<f64, DespoticError>,
+    T0: f32,
+    opts: &TempSolverOptions,
+) -> Result<Option<f64>, DespoticError> {
+    let range = (LOG_T_MIN, LOG_T_MAX);
+    if opts.root_finder == RootFinder::Newton {
+        return newton(f, log_start(T0), LOG_T_DIFF, range, LOG_T_TOL, MAX_EVAL);
+    }
+    let Some((lo, hi)) = bracket_outward(&mut f, log_start(T0), LOG_T_STEP, range)? else {
+        return Ok(None);
+    };
+    match opts.root_finder {
+        RootFinder::Bisection => bisection(f, lo, hi, LOG_T_TOL, MAX_EVAL),
+        _ => brent(f, lo, hi, LOG_T_TOL, MAX_EVAL),
+    }
+}
+
+impl Cloud {
+    /// Set the dust temperature to its equilibrium value
+    ///
+    /// Parameters
+    ///    None
+    ///
+    /// Returns
+    ///    Nothing
+    ///
+    /// Remarks
+    ///    The dust temperature is found by balancing heating by the
+    ///    radiation field and by collisions with the gas against
+    ///    thermal emission, holding Tg fixed. On failure Td is left
+    ///    unchanged.
+    pub fn set_dust_temp_eq(&mut self) -> Result<(), DespoticError> {
+        self.set_dust_temp_eq_with(&TempSolverOptions::default())
+    }
+
+    /// Set the dust temperature to its equilibrium value, using the
+    /// given solver options
+    ///
+    /// Parameters
+    ///    opts : TempSolverOptions
+    ///       options for the solver
+    ///
+    /// Returns
+    ///    Nothing
+    ///
+    /// Remarks
+    ///    As set_dust_temp_eq
+    pub fn set_dust_temp_eq_with(&mut self, opts: &TempSolverOptions) -> Result<(), DespoticError> {
+        let Td0 = self.Td;
+        let resid = |logT: f64| -> Result<f64, DespoticError> {
+            self.Td = 10.0_f64.powf(logT) as f32;
+            Ok(self.dedt_dust())
+        };
+        match solve_log_temp(resid, Td0, opts)? {
+            Some(logT) => {
+                self.Td = 10.0_f64.powf(logT) as f32;
+                Ok(())
+            }
+            None => {
+                self.Td = Td0;
+                Err(DespoticError::NoConvergence(
+                    "no equilibrium dust temperature".to_string(),
+                ))
+            }
+        }
+    }
+
+    /// Set the gas temperature to its equilibrium value
+    ///
+    /// Parameters
+    ///    None
+    ///
+    /// Returns
+    ///    Nothing
+    ///
+    /// Remarks
+    ///    The gas temperature is found by balancing all heating and
+    ///    cooling terms computed by dedt, holding Td fixed. Each trial
+    ///    temperature requires solving for the level populations of
+    ///    every emitter. On failure Tg is left unchanged.
+    pub fn set_gas_temp_eq(&mut self) -> Result<(), DespoticError> {
+        self.set_gas_temp_eq_with(&TempSolverOptions::default())
+    }
+
+    /// Set the gas temperature to its equilibrium value, using the
+    /// given solver options
+    ///
+    /// Parameters
+    ///    opts : TempSolverOptions
+    ///       options for the solver
+    ///
+    /// Returns
+    ///    Nothing
+    ///
+    /// Remarks
+    ///    As set_gas_temp_eq
+    pub fn set_gas_temp_eq_with(&mut self, opts: &TempSolverOptions) -> Result<(), DespoticError> {
+        let Tg0 = self.Tg;
+        let resid = |logT: f64| -> Result<f64, DespoticError> {
+            self.Tg = 10.0_f64.powf(logT) as f32;
+            Ok(self.dedt()?.dEdtGas)
+        };
+        match solve_log_temp(resid, Tg0, opts) {
+            Ok(Some(logT)) => {
+                self.Tg = 10.0_f64.powf(logT) as f32;
+                self.comp.compute_cv(self.Tg);
+                Ok(())
+            }
+            Ok(None) => {
+                self.Tg = Tg0;
+                Err(DespoticError::NoConvergence(
+                    "no equilibrium gas temperature".to_string(),
+                ))
+            }
+            Err(e) => {
+                self.Tg = Tg0;
+                Err(e)
+            }
+        }
+    }
+
+    /// Set the gas and dust temperatures to their equilibrium values
+    ///
+    /// Parameters
+    ///    None
+    ///
+    /// Returns
+    ///    Nothing
+    ///
+    /// Remarks
+    ///    The gas and dust are coupled by collisions, so their
+    ///    temperatures are found by alternately solving for each with
+    ///    the other held fixed, until neither changes by more than a
+    ///    relative 1e-4.
+    pub fn set_temp_eq(&mut self) -> Result<(), DespoticError> {
+        self.set_temp_eq_with(&TempSolverOptions::default(), None)
+    }
+
+    /// Set the gas and dust temperatures to their equilibrium values,
+    /// using the given solver options
+    ///
+    /// Parameters
+    ///    opts : TempSolverOptions
+    ///       options for the solvers
+    ///    on_iter : callable or None
+    ///       if given, called after each alternating dust and gas
+    ///       solve with the iteration number, counting from 1, the
+    ///       new gas temperature in K, and the residual, the larger of
+    ///       the relative changes in Tg and Td over the iteration
+    ///
+    /// Returns
+    ///    Nothing
+    ///
+    /// Remarks
+    ///    As set_temp_eq; the iteration stops once the residual
+    ///    falls below 1e-4
+    pub fn set_temp_eq_with(
+        &mut self,
+        opts: &TempSolverOptions,
+        mut on_iter: Option<&mut dyn FnMut(usize, f64, f64)>,
+    ) -> Result<(), DespoticError> {
+        for iter in 1..=MAX_TEMP_ITER {
+            let (Tg0, Td0) = (self.Tg, self.Td);
+            self.set_dust_temp_eq_with(opts)?;
+            self.set_gas_temp_eq_with(opts)?;
+            let change = |new: f32, old: f32| ((new - old) / new).abs() as f64;
+            let resid = change(self.Tg, Tg0).max(change(self.Td, Td0));
+            if let Some(f) = on_iter.as_mut() {
+                f(iter, self.Tg as f64, resid);
+            }
+            if resid < TEMP_TOL {
+                return Ok(());
+            }
+        }
+        Err(DespoticError::NoConvergence(format!(
+            "gas and dust temperatures after {MAX_TEMP_ITER} iterations: Tg = {}, Td = {}",
+            self.Tg, self.Td
+        )))
+    }
+
+    /// Stable gas temperatures in thermal equilibrium at each of a
+    /// set of pressures
+    ///
+    /// Parameters
+    ///    p_grid : array
+    ///       thermal pressures, in dyn cm^-2
+    ///
+    /// Returns
+    ///    for each pressure, the pressure and the stable equilibrium
+    ///    gas temperatures at it, in K, in increasing order; there
+    ///    are two where the cloud supports a cold and a warm phase,
+    ///    and none if there is no stable equilibrium between 1 and
+    ///    1e5 K
+    ///
+    /// Remarks
+    ///    At fixed pressure the density is nH = P mu / (muH kB Tg), so
+    ///    the net gas heating rate of dedt is scanned in steps of
+    ///    0.05 dex in Tg with the density varying accordingly, and
+    ///    each sign change is refined by Brent's method. An
+    ///    equilibrium is stable if the net heating changes from
+    ///    positive to negative as Tg rises. The dust temperature and
+    ///    all other properties are held fixed; nH and Tg are restored
+    ///    when done. The composition's derived quantities must have
+    ///    been computed, or a MissingField error is returned. This
+    ///    solves for the level populations of every emitter at each
+    ///    trial temperature, so is expensive.
+    pub fn phase_pressures(
+        &mut self,
+        p_grid: &[f64],
+    ) -> Result<Vec<(f64, Vec<f64>)>, DespoticError> {
+        if self.comp.mu <= 0.0 {
+            return Err(DespoticError::MissingField("mu".to_string()));
+        }
+        let (nH0, Tg0) = (self.nH, self.Tg);
+        let result = p_grid
+            .iter()
+            .map(|&P| Ok((P, self.stable_temps_at(P)?)))
+            .collect();
+        self.nH = nH0;
+        self.Tg = Tg0;
+        self.comp.compute_derived(nH0);
+        if Tg0 > 0.0 {
+            self.comp.compute_cv(Tg0);
+        }
+        for em in self.emitters.values() {
+            em.clear_pop_cache();
+        }
+        result
+    }
+
+    /// Stable equilibrium gas temperatures at thermal pressure P,
+    /// leaving nH and Tg at the last trial values
+    fn stable_temps_at(&mut self, P: f64) -> Result<Vec<f64>, DespoticError> {
+        let ratio = self.comp.mu as f64 / self.comp.muH as f64;
+        let mut resid = |logT: f64| -> Result<f64, DespoticError> {
+            let T = 10.0_f64.powf(logT);
+            self.Tg = T as f32;
+            self.nH = (P * ratio / (kB * T)) as f32;
+            self.comp.compute_derived(self.nH);
+            Ok(self.dedt()?.dEdtGas)
+        };
+        let nstep = ((LOG_T_MAX - LOG_T_MIN) / LOG_T_SCAN).round() as usize;
+        let mut temps = Vec::new();
+        let mut prev = (LOG_T_MIN, resid(LOG_T_MIN)?);
+        for i in 1..=nstep {
+            let x = LOG_T_MIN + i as f64 * LOG_T_SCAN;
+            let next = (x, resid(x)?);
+            if prev.1 > 0.0 && next.1 <= 0.0 {
+                if let Some(logT) = brent(&mut resid, prev, next, LOG_T_TOL, MAX_EVAL)? {
+                    temps.push(10.0_f64.powf(logT));
+                }
+            }
+            prev = next;
+        }
+        Ok(temps)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::energy::RateKind;
+    use crate::testutil::{co_cloud, read_cloud};
+
+    #[test]
+    fn root_finders_agree() {
+        let mut Td = Vec::new();
+        for root_finder in [RootFinder::Bisection, RootFinder::Brent, RootFinder::Newton] {
+            let mut cloud = co_cloud();
+            cloud
+                .set_dust_temp_eq_with(&TempSolverOptions { root_finder })
+                .unwrap();
+            Td.push(cloud.Td as f64);
+        }
+        for T in &Td[1..] {
+            assert!((T / Td[0] - 1.0).abs() < 1.0e-5, "Td = {Td:?}");
+        }
+        assert!(Td[0] > 5.0 && Td[0] < 30.0);
+    }
+
+    #[test]
+    fn trajectory_residual_falls() {
+        let mut cloud = co_cloud();
+        cloud.set_nh(1.0e6);
+        cloud.set_tg(50.0);
+        cloud.set_td(50.0);
+        let mut trajectory = Vec::new();
+        let mut record = |iter: usize, Tg: f64, resid: f64| trajectory.push((iter, Tg, resid));
+        cloud
+            .set_temp_eq_with(&TempSolverOptions::default(), Some(&mut record))
+            .unwrap();
+        // Dense gas couples Tg and Td strongly, so the alternating
+        // solves take several iterations
+        assert!(trajectory.len() >= 3, "{trajectory:?}");
+        for (i, step) in trajectory.iter().enumerate() {
+            assert_eq!(step.0, i + 1);
+        }
+        assert!(
+            trajectory.windows(2).all(|w| w[1].2 < w[0].2),
+            "{trajectory:?}"
+        );
+        let &(_, Tg, resid) = trajectory.last().unwrap();
+        assert!(resid < 1.0e-4);
+        assert_eq!(Tg, cloud.Tg as f64);
+    }
+
+    #[test]
+    fn two_phase_pressures() {
+        // Diffuse atomic gas cooled by [CII] and, in warm gas, by
+        // Lyman alpha (Spitzer 1978), which has an S-shaped
+        // equilibrium curve (Wolfire et al. 1995)
+        let mut cloud = read_cloud(
+            "nH = 1\ncolDen = 1e19\nxHI = 1\nxHe = 0.1\nxe = 0.01\nTg = 100\nTd = 20\n\
+             emitter = c+ 1.6e-4\n",
+        )
+        .unwrap();
+        cloud.add_custom_rate(
+            Box::new(|cl: &Cloud| {
+                7.3e-19 * cl.comp.xe as f64 * cl.nH as f64 * (-118400.0 / cl.Tg as f64).exp()
+            }),
+            RateKind::Cooling,
+        );
+        let P = [300.0 * kB, 1.0e4 * kB];
+        let phases = cloud.phase_pressures(&P).unwrap();
+
+        // Only the warm phase survives at low pressure, but at higher
+        // pressure a cold phase coexists with it
+        assert_eq!(phases[0].1.len(), 1, "{phases:?}");
+        assert!(phases[0].1[0] > 5000.0);
+        let temps = &phases[1].1;
+        assert_eq!(temps.len(), 2, "{phases:?}");
+        assert!(temps[0] < 100.0 && temps[1] > 5000.0, "{temps:?}");
+        assert_eq!((cloud.nH, cloud.Tg), (1.0, 100.0));
+    }
+}
@@ -0,0 +1,39 @@
+//! Fixtures shared by the unit tests
+
+use crate::cloud::Cloud;
+use crate::emitter_data::EmitterData;
+use std::path::PathBuf;
+use std::sync::{Arc, Once};
+
+/// Directory of test data, laid out like the data directory
+pub fn testdata() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("testdata")
+}
+
+/// Point the data directory at the test data; every test that
+/// searches the data directory calls this first, so the variable is
+/// set once, before any test reads it
+pub fn init() {
+    static INIT: Once = Once::new();
+    INIT.call_once(|| std::env::set_var("DESPOTIC_DATA", testdata()));
+}
+
+/// LAMDA data of one of the test species
+pub fn lamda(name: &str) -> Arc<EmitterData> {
+    let path = testdata().join("LAMDA").join(format!("{name}.dat"));
+    Arc::new(EmitterData::from_lamda(path).unwrap())
+}
+
+/// A cold molecular cloud with CO at an abundance of 1e-4
+pub fn co_cloud() -> Cloud {
+    init();
+    Cloud::simple(1.0e3, 20.0, &[("co", 1.0e-4)]).unwrap()
+}
+
+/// Read a cloud from the text of a cloud file
+pub fn read_cloud(text: &str) -> Result<Cloud, crate::DespoticError> {
+    init();
+    let mut cloud = Cloud::new(None::<&str>, true, false)?;
+    cloud.read_from(text.as_bytes(), false, &Default::default())?;
+    Ok(cloud)
+}
@@ -0,0 +1,135 @@
+//! Strongly typed physical quantities
+//!
+//! These wrap a CGS value so that, for example, a temperature cannot
+//! be passed where a density is expected. Quantities of the same kind
+//! can be added and subtracted, scaled by plain numbers, and divided
+//! to give a plain ratio. Every type converts from a raw f32 or f64 in
+//! CGS units, so APIs that accept them still accept plain numbers.
+
+use std::ops::{Add, Div, Mul, Neg, Sub};
+
+macro_rules! unit {
+    ($(#[$meta:meta])* $name:ident, $ctor:ident) => {
+        $(#[$meta])*
+        #[derive(Debug, Clone, Copy, PartialEq, PartialOrd, Default)]
+        pub struct $name(f64);
+
+        impl $name {
+            /// Create a quantity from its value in CGS units
+            pub const fn $ctor(value: f64) -> Self {
+                Self(value)
+            }
+
+            /// Value of the quantity in CGS units
+            pub const fn value(self) -> f64 {
+                self.0
+            }
+        }
+
+        impl From<f64> for $name {
+            fn from(value: f64) -> Self {
+                Self(value)
+            }
+        }
+
+        impl From<f32> for $name {
+            fn from(value: f32) -> Self {
+                Self(value as f64)
+            }
+        }
+
+        impl Add for $name {
+            type Output = Self;
+            fn add(self, rhs: Self) -> Self {
+                Self(self.0 + rhs.0)
+            }
+        }
+
+        impl Sub for $name {
+            type Output = Self;
+            fn sub(self, rhs: Self) -> Self {
+                Self(self.0 - rhs.0)
+            }
+        }
+
+        impl Neg for $name {
+            type Output = Self;
+            fn neg(self) -> Self {
+                Self(-self.0)
+            }
+        }
+
+        impl Mul<f64> for $name {
+            type Output = Self;
+            fn mul(self, rhs: f64) -> Self {
+                Self(self.0 * rhs)
+            }
+        }
+
+        impl Mul<$name> for f64 {
+            type Output = $name;
+            fn mul(self, rhs: $name) -> $name {
+                $name(self * rhs.0)
+            }
+        }
+
+        impl Div<f64> for $name {
+            type Output = Self;
+            fn div(self, rhs: f64) -> Self {
+                Self(self.0 / rhs)
+            }
+        }
+
+        impl Div for $name {
+            type Output = f64;
+            fn div(self, rhs: Self) -> f64 {
+                self.0 / rhs.0
+            }
+        }
+    };
+}
+
+unit!(
+    /// A number density, in cm^-3
+    NumberDensity,
+    per_cm3
+);
+unit!(
+    /// A temperature, in K
+    Temperature,
+    kelvin
+);
+unit!(
+    /// A column density, in cm^-2
+    ColumnDensity,
+    per_cm2
+);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cloud::Cloud;
+
+    #[test]
+    fn builder_accepts_typed_quantities() {
+        let cloud = Cloud::builder()
+            .nH(NumberDensity::per_cm3(100.0))
+            .colDen(ColumnDensity::per_cm2(1.0e21))
+            .Tg(Temperature::kelvin(10.0))
+            .Td(8.0)
+            .build();
+        assert_eq!(cloud.Tg, 10.0);
+        assert_eq!(cloud.Td, 8.0);
+        assert_eq!(cloud.nH, 100.0);
+        assert_eq!(cloud.colDen, 1.0e21);
+    }
+
+    #[test]
+    fn arithmetic_preserves_units() {
+        let T = Temperature::kelvin(10.0);
+        assert_eq!(T + T * 2.0, Temperature::kelvin(30.0));
+        assert_eq!(3.0 * T - T, Temperature::kelvin(20.0));
+        assert_eq!(-T / 2.0, Temperature::kelvin(-5.0));
+        assert_eq!(Temperature::kelvin(30.0) / T, 3.0);
+    }
+}